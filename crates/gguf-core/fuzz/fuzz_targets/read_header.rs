@@ -0,0 +1,20 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use gguf_core::reader::read_header;
+use libfuzzer_sys::fuzz_target;
+
+// Parses arbitrary bytes as a GGUF header. Run with:
+//   cargo +nightly fuzz run read_header tests/corpus -- -rss_limit_mb=512
+fuzz_target!(|data: &[u8]| {
+    let Ok(header) = read_header(Cursor::new(data), data.len() as u64) else {
+        return;
+    };
+    if header.check_data_bounds(data.len() as u64).is_ok() {
+        for tensor in &header.tensors {
+            let start = header.data_offset + tensor.offset;
+            assert!(start + tensor.size <= data.len() as u64);
+        }
+    }
+});
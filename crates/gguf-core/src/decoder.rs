@@ -1,28 +1,67 @@
-use std::io::{self, Cursor, Read};
-use byteorder::{LittleEndian, ReadBytesExt};
+use std::io;
+use std::slice::ChunksExact;
+use half::{bf16, f16};
+use thiserror::Error;
 
-#[derive(Debug)]
+use crate::iq_grids::{
+    IQ2S_GRID, IQ2XS_GRID, IQ2XXS_GRID, IQ3S_GRID, IQ3XXS_GRID, KSIGNS_IQ2XS, KVALUES_IQ4NL,
+};
+use crate::types::GgmlType;
+
+/// Values per K-quant super-block
+const QK_K: usize = 256;
+
+/// Decode failures; `offset` is the byte offset within the tensor's data
+#[derive(Debug, Error)]
 pub enum DecodeError {
-    InvalidScale,
-    InvalidBlock,
-    UnexpectedEOF,
-    Io(io::Error),
+    #[error("invalid block scale at byte {offset}")]
+    InvalidScale { offset: usize },
+    #[error("invalid block at byte {offset}")]
+    InvalidBlock { offset: usize },
+    #[error("unexpected end of data at byte {offset}")]
+    UnexpectedEOF { offset: usize },
+    #[error("no decoder for ggml type {0}")]
+    Unsupported(GgmlType),
+    #[error(transparent)]
+    Io(#[from] io::Error),
 }
 
-impl From<io::Error> for DecodeError {
-    fn from(e: io::Error) -> Self {
-        DecodeError::Io(e)
+/// Decodes tensor data of any supported ggml type to f32 values
+pub fn dequantize(ggml_type: GgmlType, bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    match ggml_type {
+        GgmlType::F32 => try_decode_f32(bytes, dims),
+        GgmlType::F16 => try_decode_f16(bytes, dims),
+        GgmlType::BF16 => try_decode_bf16(bytes, dims),
+        GgmlType::Q4_0 => try_decode_q4_0(bytes, dims),
+        GgmlType::Q4_1 => try_decode_q4_1(bytes, dims),
+        GgmlType::Q5_0 => try_decode_q5_0(bytes, dims),
+        GgmlType::Q5_1 => try_decode_q5_1(bytes, dims),
+        GgmlType::Q8_0 => try_decode_q8_0(bytes, dims),
+        GgmlType::Q2_K => try_decode_q2_k(bytes, dims),
+        GgmlType::Q3_K => try_decode_q3_k(bytes, dims),
+        GgmlType::Q4_K => try_decode_q4_k(bytes, dims),
+        GgmlType::Q5_K => try_decode_q5_k(bytes, dims),
+        GgmlType::Q6_K => try_decode_q6_k(bytes, dims),
+        GgmlType::IQ2_XXS => try_decode_iq2_xxs(bytes, dims),
+        GgmlType::IQ2_XS => try_decode_iq2_xs(bytes, dims),
+        GgmlType::IQ2_S => try_decode_iq2_s(bytes, dims),
+        GgmlType::IQ3_XXS => try_decode_iq3_xxs(bytes, dims),
+        GgmlType::IQ3_S => try_decode_iq3_s(bytes, dims),
+        GgmlType::IQ4_NL => try_decode_iq4_nl(bytes, dims),
+        GgmlType::IQ4_XS => try_decode_iq4_xs(bytes, dims),
+        other => Err(DecodeError::Unsupported(other)),
     }
 }
-// Add this to gguf-core/src/decoder.rs
 
 pub fn try_decode_f32(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
     let expected_len = dims.iter().product::<u64>() as usize;
-    if bytes.len() % 4 != 0 {
-        return Err(DecodeError::InvalidBlock);
+    if !bytes.len().is_multiple_of(4) {
+        return Err(DecodeError::InvalidBlock {
+            offset: bytes.len() / 4 * 4,
+        });
     }
     if bytes.len() < expected_len * 4 {
-        return Err(DecodeError::UnexpectedEOF);
+        return Err(DecodeError::UnexpectedEOF { offset: bytes.len() });
     }
     let mut floats = Vec::with_capacity(expected_len);
     for chunk in bytes.chunks_exact(4) {
@@ -31,97 +70,462 @@ pub fn try_decode_f32(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeErro
     }
     Ok(floats)
 }
+
+/// Decodes little-endian IEEE half-precision values
+pub fn try_decode_f16(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let values = checked_blocks(bytes, dims, GgmlType::F16)?;
+    Ok(values.map(|v| f16::from_le_bytes([v[0], v[1]]).to_f32()).collect())
+}
+
+/// Decodes little-endian bfloat16 values, the top half of an f32
+pub fn try_decode_bf16(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let values = checked_blocks(bytes, dims, GgmlType::BF16)?;
+    Ok(values.map(|v| bf16::from_le_bytes([v[0], v[1]]).to_f32()).collect())
+}
+
+/// Decodes ggml `Q4_0` blocks: an f16 scale and 16 bytes of 4-bit values
+/// offset by 8, low nibbles first
 pub fn try_decode_q4_0(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
-    let expected_len = dims.iter().product::<u64>() as usize;
-    let mut cursor = Cursor::new(bytes);
-    let mut decoded = Vec::with_capacity(expected_len);
+    let blocks = checked_blocks(bytes, dims, GgmlType::Q4_0)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * 32);
 
-    while decoded.len() < expected_len {
-        if (cursor.position() as usize) + 8 > bytes.len() {
-            return Err(DecodeError::UnexpectedEOF);
-        }
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 18)?;
+        let qs = &block[2..18];
+        decoded.extend(qs.iter().map(|&q| ((q & 0x0F) as i32 - 8) as f32 * d));
+        decoded.extend(qs.iter().map(|&q| ((q >> 4) as i32 - 8) as f32 * d));
+    }
+
+    Ok(decoded)
+}
 
-        let scale = cursor.read_f32::<LittleEndian>()?;
-        let zero = cursor.read_f32::<LittleEndian>()?;
+/// Decodes ggml `Q4_1` blocks: an f16 scale and minimum followed by 16
+/// bytes of unsigned 4-bit steps above the minimum, low nibbles first
+pub fn try_decode_q4_1(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::Q4_1)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * 32);
 
-        if !scale.is_finite() || scale == 0.0 {
-            return Err(DecodeError::InvalidScale);
-        }
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 20)?;
+        let m = read_f16(&block[2..], i * 20 + 2)?;
+        let qs = &block[4..20];
+        decoded.extend(qs.iter().map(|&q| (q & 0x0F) as f32 * d + m));
+        decoded.extend(qs.iter().map(|&q| (q >> 4) as f32 * d + m));
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes ggml `Q5_0` blocks: an f16 scale, a 32-bit mask holding the
+/// fifth bit of each value, and 16 bytes of low nibbles; values are offset by 16
+pub fn try_decode_q5_0(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::Q5_0)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * 32);
+
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 22)?;
+        let qh = u32::from_le_bytes([block[2], block[3], block[4], block[5]]);
+        let q = unpack_q5(qh, &block[6..22]);
+        decoded.extend(q.iter().map(|&v| (v as i32 - 16) as f32 * d));
+    }
+
+    Ok(decoded)
+}
 
-        // Read up to 16 packed bytes (max 32 values)
-        let remaining = bytes.len() - cursor.position() as usize;
-        let packed_len = remaining.min(16);
-        let mut packed = vec![0u8; packed_len];
-        cursor.read_exact(&mut packed)?;
+/// Decodes ggml `Q5_1` blocks: an f16 scale and minimum followed by the
+/// same high-bit mask and nibble layout as `Q5_0`
+pub fn try_decode_q5_1(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::Q5_1)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * 32);
+
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 24)?;
+        let m = read_f16(&block[2..], i * 24 + 2)?;
+        let qh = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+        let q = unpack_q5(qh, &block[8..24]);
+        decoded.extend(q.iter().map(|&v| v as f32 * d + m));
+    }
+
+    Ok(decoded)
+}
 
-        for byte in packed {
-            if decoded.len() >= expected_len {
-                break;
+/// Decodes ggml `Q8_0` blocks: an f16 scale followed by 32 signed bytes
+pub fn try_decode_q8_0(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::Q8_0)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * 32);
+
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 34)?;
+        decoded.extend(block[2..34].iter().map(|&q| q as i8 as f32 * d));
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes ggml `Q2_K` super-blocks: 16 bytes of 4-bit scale and min pairs,
+/// 64 bytes of 2-bit levels, then the f16 `d` and `dmin`
+pub fn try_decode_q2_k(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::Q2_K)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * QK_K);
+
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(&block[80..], i * 84 + 80)?;
+        let min = read_f16(&block[82..], i * 84 + 82)?;
+        let mut scales = block[..16].iter();
+
+        for q in block[16..80].chunks_exact(32) {
+            for shift in (0..8).step_by(2) {
+                for half in q.chunks_exact(16) {
+                    let sc = scales.next().unwrap();
+                    let dl = d * (sc & 0xF) as f32;
+                    let ml = min * (sc >> 4) as f32;
+                    decoded.extend(half.iter().map(|&b| dl * ((b >> shift) & 3) as f32 - ml));
+                }
             }
-            let lo = byte & 0x0F;
-            decoded.push(scale * lo as f32 + zero);
+        }
+    }
+
+    Ok(decoded)
+}
 
-            if decoded.len() >= expected_len {
-                break;
+/// Decodes ggml `Q3_K` super-blocks: a 32-byte high-bit mask, 64 bytes of
+/// low 2-bit levels, 16 signed 6-bit scales in 12 bytes and an f16 `d`
+pub fn try_decode_q3_k(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::Q3_K)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * QK_K);
+
+    for (i, block) in blocks.enumerate() {
+        let hmask = &block[..32];
+        let packed = &block[96..108];
+        let d = read_f16(&block[108..], i * 110 + 108)?;
+
+        let mut is = 0;
+        let mut m = 1u8;
+        for q in block[32..96].chunks_exact(32) {
+            for shift in (0..8).step_by(2) {
+                for (l0, half) in [(0, &q[..16]), (16, &q[16..])] {
+                    let lo = if is < 8 { packed[is] & 0xF } else { packed[is - 8] >> 4 };
+                    let hi = (packed[8 + is % 4] >> (2 * (is / 4))) & 3;
+                    let dl = d * ((lo | (hi << 4)) as i32 - 32) as f32;
+                    decoded.extend(half.iter().zip(&hmask[l0..l0 + 16]).map(|(&b, &h)| {
+                        let low = ((b >> shift) & 3) as i32;
+                        dl * (low - if h & m != 0 { 0 } else { 4 }) as f32
+                    }));
+                    is += 1;
+                }
+                m <<= 1;
             }
-            let hi = (byte >> 4) & 0x0F;
-            decoded.push(scale * hi as f32 + zero);
         }
     }
 
     Ok(decoded)
 }
 
-pub fn try_decode_q5_1(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
-    let expected_len = dims.iter().product::<u64>() as usize;
-    let mut cursor = Cursor::new(bytes);
-    let mut decoded = Vec::with_capacity(expected_len);
+/// Decodes ggml `Q4_K` super-blocks: f16 `d` and `dmin`, eight 6-bit scales
+/// and mins in 12 bytes, then 128 bytes of 4-bit levels
+pub fn try_decode_q4_k(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::Q4_K)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * QK_K);
+
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 144)?;
+        let min = read_f16(&block[2..], i * 144 + 2)?;
+        let scales = &block[4..16];
 
-    while decoded.len() < expected_len {
-        if (cursor.position() as usize) + 8 > bytes.len() {
-            return Err(DecodeError::UnexpectedEOF);
+        for (n, q) in block[16..144].chunks_exact(32).enumerate() {
+            let (sc1, m1) = get_scale_min_k4(2 * n, scales);
+            let (sc2, m2) = get_scale_min_k4(2 * n + 1, scales);
+            let (d1, m1) = (d * sc1 as f32, min * m1 as f32);
+            let (d2, m2) = (d * sc2 as f32, min * m2 as f32);
+            decoded.extend(q.iter().map(|&b| d1 * (b & 0xF) as f32 - m1));
+            decoded.extend(q.iter().map(|&b| d2 * (b >> 4) as f32 - m2));
         }
+    }
+
+    Ok(decoded)
+}
 
-        let scale = cursor.read_f32::<LittleEndian>()?;
-        let zero = cursor.read_f32::<LittleEndian>()?;
+/// Decodes ggml `Q5_K` super-blocks: the `Q4_K` layout with a 32-byte mask
+/// holding the fifth bit of each level before the nibbles
+pub fn try_decode_q5_k(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::Q5_K)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * QK_K);
 
-        if !scale.is_finite() || scale == 0.0 {
-            return Err(DecodeError::InvalidScale);
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 176)?;
+        let min = read_f16(&block[2..], i * 176 + 2)?;
+        let scales = &block[4..16];
+        let qh = &block[16..48];
+
+        for (n, ql) in block[48..176].chunks_exact(32).enumerate() {
+            let (sc1, m1) = get_scale_min_k4(2 * n, scales);
+            let (sc2, m2) = get_scale_min_k4(2 * n + 1, scales);
+            let (d1, m1) = (d * sc1 as f32, min * m1 as f32);
+            let (d2, m2) = (d * sc2 as f32, min * m2 as f32);
+            let (u1, u2) = (1u8 << (2 * n), 2u8 << (2 * n));
+            decoded.extend(ql.iter().zip(qh).map(|(&b, &h)| {
+                d1 * ((b & 0xF) + if h & u1 != 0 { 16 } else { 0 }) as f32 - m1
+            }));
+            decoded.extend(ql.iter().zip(qh).map(|(&b, &h)| {
+                d2 * ((b >> 4) + if h & u2 != 0 { 16 } else { 0 }) as f32 - m2
+            }));
         }
+    }
 
-        // Read up to 20 bytes of 5-bit values (max 32 values)
-        let remaining = bytes.len() - cursor.position() as usize;
-        let packed_len = remaining.min(20);
-        let mut packed = vec![0u8; packed_len];
-        cursor.read_exact(&mut packed)?;
+    Ok(decoded)
+}
 
-        let mut acc: u64 = 0;
-        let mut bits = 0;
-        let mut values = Vec::with_capacity(32);
+/// Decodes ggml `Q6_K` super-blocks: 128 bytes of low nibbles, 64 bytes of
+/// high 2-bit pairs, 16 signed 8-bit scales and an f16 `d`
+pub fn try_decode_q6_k(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::Q6_K)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * QK_K);
 
-        for byte in packed {
-            acc |= (byte as u64) << bits;
-            bits += 8;
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(&block[208..], i * 210 + 208)?;
 
-            while bits >= 5 {
-                let val = (acc & 0x1F) as u8;
-                values.push(val);
-                acc >>= 5;
-                bits -= 5;
+        for n in 0..2 {
+            let ql = &block[64 * n..64 * n + 64];
+            let qh = &block[128 + 32 * n..128 + 32 * n + 32];
+            let sc = &block[192 + 8 * n..192 + 8 * n + 8];
 
-                if values.len() >= 32 {
-                    break;
+            let mut out = [0.0f32; 128];
+            for l in 0..32 {
+                let is = l / 16;
+                let q = [
+                    (ql[l] & 0xF) | ((qh[l] & 3) << 4),
+                    (ql[l + 32] & 0xF) | (((qh[l] >> 2) & 3) << 4),
+                    (ql[l] >> 4) | (((qh[l] >> 4) & 3) << 4),
+                    (ql[l + 32] >> 4) | (((qh[l] >> 6) & 3) << 4),
+                ];
+                for (k, &q) in q.iter().enumerate() {
+                    let scale = sc[is + 2 * k] as i8;
+                    out[l + 32 * k] = d * scale as f32 * (q as i32 - 32) as f32;
                 }
             }
+            decoded.extend_from_slice(&out);
         }
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes ggml `IQ2_XXS` super-blocks: an f16 `d`, then per 32 values four
+/// `IQ2XXS_GRID` indices and a u32 holding four 7-bit sign indices and a
+/// 4-bit scale in the top bits
+pub fn try_decode_iq2_xxs(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::IQ2_XXS)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * QK_K);
 
-        for val in values {
-            decoded.push(scale * val as f32 + zero);
-            if decoded.len() >= expected_len {
-                break;
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 66)?;
+        for q in block[2..66].chunks_exact(8) {
+            let aux = u32::from_le_bytes([q[4], q[5], q[6], q[7]]);
+            let db = d * (0.5 + (aux >> 28) as f32) * 0.25;
+            for l in 0..4 {
+                let signs = KSIGNS_IQ2XS[((aux >> (7 * l)) & 127) as usize];
+                push_grid(&mut decoded, IQ2XXS_GRID[q[l] as usize], signs, db);
             }
         }
     }
 
     Ok(decoded)
 }
+
+/// Decodes ggml `IQ2_XS` super-blocks: an f16 `d`, 32 u16s each holding a
+/// 9-bit `IQ2XS_GRID` index and a 7-bit sign index, then a 4-bit scale per
+/// 16 values
+pub fn try_decode_iq2_xs(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::IQ2_XS)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * QK_K);
+
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 74)?;
+        for (ib32, q) in block[2..66].chunks_exact(8).enumerate() {
+            let sc = block[66 + ib32];
+            let db = [d * (0.5 + (sc & 0xF) as f32) * 0.25, d * (0.5 + (sc >> 4) as f32) * 0.25];
+            for l in 0..4 {
+                let qs = u16::from_le_bytes([q[2 * l], q[2 * l + 1]]);
+                let signs = KSIGNS_IQ2XS[(qs >> 9) as usize];
+                push_grid(&mut decoded, IQ2XS_GRID[(qs & 511) as usize], signs, db[l / 2]);
+            }
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes ggml `IQ2_S` super-blocks: an f16 `d`, the low bytes of 32
+/// `IQ2S_GRID` indices, 32 sign bytes, the indices' top 2 bits packed four
+/// to a byte, then a 4-bit scale per 16 values
+pub fn try_decode_iq2_s(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::IQ2_S)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * QK_K);
+
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 82)?;
+        let (qs, signs, qh) = (&block[2..34], &block[34..66], &block[66..74]);
+        for ib32 in 0..QK_K / 32 {
+            let sc = block[74 + ib32];
+            let db = [d * (0.5 + (sc & 0xF) as f32) * 0.25, d * (0.5 + (sc >> 4) as f32) * 0.25];
+            for l in 0..4 {
+                let index = qs[4 * ib32 + l] as usize | ((qh[ib32] as usize) << (8 - 2 * l) & 0x300);
+                push_grid(&mut decoded, IQ2S_GRID[index], signs[4 * ib32 + l], db[l / 2]);
+            }
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes ggml `IQ3_XXS` super-blocks: an f16 `d`, 64 `IQ3XXS_GRID`
+/// indices of four values each, then per 32 values a u32 of four 7-bit sign
+/// indices and a 4-bit scale in the top bits
+pub fn try_decode_iq3_xxs(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::IQ3_XXS)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * QK_K);
+
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 98)?;
+        for (ib32, q) in block[2..66].chunks_exact(8).enumerate() {
+            let s = &block[66 + 4 * ib32..70 + 4 * ib32];
+            let aux = u32::from_le_bytes([s[0], s[1], s[2], s[3]]);
+            let db = d * (0.5 + (aux >> 28) as f32) * 0.5;
+            for l in 0..4 {
+                let signs = KSIGNS_IQ2XS[((aux >> (7 * l)) & 127) as usize];
+                let grid = IQ3XXS_GRID[q[2 * l] as usize] as u64
+                    | (IQ3XXS_GRID[q[2 * l + 1] as usize] as u64) << 32;
+                push_grid(&mut decoded, grid, signs, db);
+            }
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes ggml `IQ3_S` super-blocks: an f16 `d`, the low bytes of 64
+/// `IQ3S_GRID` indices, their ninth bits packed eight to a byte, 32 sign
+/// bytes, then an odd 4-bit scale per 32 values
+pub fn try_decode_iq3_s(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::IQ3_S)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * QK_K);
+
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 110)?;
+        let (qs, qh, signs) = (&block[2..66], &block[66..74], &block[74..106]);
+        for ib32 in 0..QK_K / 32 {
+            let sc = block[106 + ib32 / 2] >> (4 * (ib32 % 2)) & 0xF;
+            let db = d * (1 + 2 * sc as i32) as f32;
+            for l in 0..4 {
+                let q = &qs[8 * ib32 + 2 * l..];
+                let h = qh[ib32] as usize;
+                let grid1 = IQ3S_GRID[q[0] as usize | ((h << (8 - 2 * l)) & 256)];
+                let grid2 = IQ3S_GRID[q[1] as usize | ((h << (7 - 2 * l)) & 256)];
+                push_grid(&mut decoded, grid1 as u64 | (grid2 as u64) << 32, signs[4 * ib32 + l], db);
+            }
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes ggml `IQ4_NL` blocks: an f16 scale and 16 bytes of 4-bit indices
+/// into the non-linear `KVALUES_IQ4NL` levels, laid out like `Q4_0`
+pub fn try_decode_iq4_nl(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::IQ4_NL)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * 32);
+
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 18)?;
+        push_iq4(&mut decoded, &block[2..18], d);
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes ggml `IQ4_XS` super-blocks: an f16 `d`, a 6-bit signed scale per
+/// 32 values split into high bits (a u16) and low nibbles (4 bytes), then
+/// 128 bytes of `IQ4_NL` indices
+pub fn try_decode_iq4_xs(bytes: &[u8], dims: &[u64]) -> Result<Vec<f32>, DecodeError> {
+    let blocks = checked_blocks(bytes, dims, GgmlType::IQ4_XS)?;
+    let mut decoded = Vec::with_capacity(blocks.len() * QK_K);
+
+    for (i, block) in blocks.enumerate() {
+        let d = read_f16(block, i * 136)?;
+        let scales_h = u16::from_le_bytes([block[2], block[3]]);
+        for (ib, q) in block[8..136].chunks_exact(16).enumerate() {
+            let lo = (block[4 + ib / 2] >> (4 * (ib % 2))) & 0xF;
+            let hi = ((scales_h >> (2 * ib)) & 3) as u8;
+            let dl = d * ((lo | (hi << 4)) as i32 - 32) as f32;
+            push_iq4(&mut decoded, q, dl);
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Reads sub-block `j`'s 6-bit scale and min from the 12-byte `Q4_K`/`Q5_K`
+/// layout: the low 6 bits of the first eight bytes for sub-blocks 0-3, and
+/// the last four nibble pairs plus the spare top bits for sub-blocks 4-7
+fn get_scale_min_k4(j: usize, q: &[u8]) -> (u8, u8) {
+    if j < 4 {
+        (q[j] & 63, q[j + 4] & 63)
+    } else {
+        (
+            (q[j + 4] & 0xF) | ((q[j - 4] >> 6) << 4),
+            (q[j + 4] >> 4) | ((q[j] >> 6) << 4),
+        )
+    }
+}
+
+/// Reassembles the 32 five-bit values of a `Q5_0`/`Q5_1` block. Byte `j` of
+/// `qs` holds values `j` and `j + 16`; bit `i` of `qh` is the fifth bit of value `i`.
+fn unpack_q5(qh: u32, qs: &[u8]) -> [u8; 32] {
+    let mut q = [0u8; 32];
+    for (j, &b) in qs.iter().enumerate() {
+        q[j] = (b & 0x0F) | ((((qh >> j) & 1) as u8) << 4);
+        q[j + 16] = (b >> 4) | ((((qh >> (j + 16)) & 1) as u8) << 4);
+    }
+    q
+}
+
+/// Appends the eight grid magnitudes packed in `grid`, one per byte, times
+/// `d` and negated where `signs` has the matching bit set
+fn push_grid(decoded: &mut Vec<f32>, grid: u64, signs: u8, d: f32) {
+    for j in 0..8 {
+        let q = d * (grid >> (8 * j) & 0xFF) as f32;
+        decoded.push(if signs & (1 << j) != 0 { -q } else { q });
+    }
+}
+
+/// Appends the 32 `KVALUES_IQ4NL` levels indexed by `qs`, low nibbles
+/// first, times `d`
+fn push_iq4(decoded: &mut Vec<f32>, qs: &[u8], d: f32) {
+    decoded.extend(qs.iter().map(|&b| d * KVALUES_IQ4NL[(b & 0xF) as usize] as f32));
+    decoded.extend(qs.iter().map(|&b| d * KVALUES_IQ4NL[(b >> 4) as usize] as f32));
+}
+
+/// Splits tensor data into blocks of `ggml_type` after checking it holds
+/// exactly the number of blocks `dims` calls for
+fn checked_blocks<'a>(
+    bytes: &'a [u8],
+    dims: &[u64],
+    ggml_type: GgmlType,
+) -> Result<ChunksExact<'a, u8>, DecodeError> {
+    let expected = ggml_type
+        .tensor_nbytes(dims)
+        .ok_or(DecodeError::InvalidBlock { offset: 0 })? as usize;
+    if bytes.len() < expected {
+        return Err(DecodeError::UnexpectedEOF { offset: bytes.len() });
+    }
+    Ok(bytes[..expected].chunks_exact(ggml_type.type_size() as usize))
+}
+
+/// Reads the f16 at the start of `block`, rejecting NaN and infinity
+fn read_f16(block: &[u8], offset: usize) -> Result<f32, DecodeError> {
+    let d = f16::from_le_bytes([block[0], block[1]]).to_f32();
+    if !d.is_finite() {
+        return Err(DecodeError::InvalidScale { offset });
+    }
+    Ok(d)
+}
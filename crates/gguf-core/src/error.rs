@@ -0,0 +1,118 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::decoder::DecodeError;
+
+/// Result alias used throughout gguf-core
+pub type GgufResult<T> = Result<T, GgufError>;
+
+/// Everything that can go wrong reading, writing or decoding a GGUF file.
+///
+/// `offset` is always an absolute file position. Tensor errors point at the
+/// tensor's entry in the directory, except those raised when its data is
+/// read (`TensorOutOfBounds`, and `UnknownTensorType` from `tensor_data`),
+/// which point at where the data would start; `data_offset` is the tensor's
+/// offset within the data section, as stored in its entry.
+#[derive(Debug, Error)]
+pub enum GgufError {
+    #[error("bad magic {found:02x?} at offset {offset}, expected \"GGUF\"")]
+    BadMagic { offset: u64, found: [u8; 4] },
+
+    #[error("unsupported GGUF version {version} at offset {offset}")]
+    UnsupportedVersion { offset: u64, version: u32 },
+
+    #[error("unknown metadata value type {type_id} for key '{key}' at offset {offset}")]
+    UnknownValueType { offset: u64, key: String, type_id: u32 },
+
+    #[error("header truncated at offset {offset} while reading {context}")]
+    TruncatedHeader { offset: u64, context: String },
+
+    #[error("length {len} of {context} at offset {offset} exceeds the {limit} bytes available")]
+    OversizedLength {
+        offset: u64,
+        context: String,
+        len: u64,
+        limit: u64,
+    },
+
+    #[error("{what} is {value} at offset {offset}, over the limit of {limit}")]
+    LimitExceeded {
+        offset: u64,
+        what: String,
+        value: u64,
+        limit: u64,
+    },
+
+    #[error("{context} at offset {offset} is not valid UTF-8")]
+    BadUtf8 { offset: u64, context: String },
+
+    #[error("duplicate metadata key '{key}' at offset {offset}")]
+    DuplicateKey { offset: u64, key: String },
+
+    #[error("duplicate tensor '{name}' at offset {offset}")]
+    DuplicateTensor { offset: u64, name: String },
+
+    #[error("invalid general.alignment {alignment} at offset {offset}, must be a power of two")]
+    InvalidAlignment { offset: u64, alignment: u64 },
+
+    #[error("invalid metadata value for key '{key}' at offset {offset}: {reason}")]
+    InvalidValue { offset: u64, key: String, reason: String },
+
+    #[error("tensor '{name}' at offset {offset} has unknown ggml type {type_id}")]
+    UnknownTensorType { offset: u64, name: String, type_id: u32 },
+
+    #[error("tensor '{name}' at offset {offset} of ggml type {type_id} has invalid dims {dims:?}")]
+    InvalidShape {
+        offset: u64,
+        name: String,
+        type_id: u32,
+        dims: Vec<u64>,
+    },
+
+    #[error("tensor '{name}' at offset {offset} has data offset {data_offset}, not a multiple of the alignment {alignment}")]
+    MisalignedOffset {
+        offset: u64,
+        name: String,
+        data_offset: u64,
+        alignment: u64,
+    },
+
+    #[error("tensor '{name}' ({size} bytes at data offset {data_offset}) at offset {offset} is out of bounds of the {data_len}-byte data section")]
+    TensorOutOfBounds {
+        offset: u64,
+        name: String,
+        data_offset: u64,
+        size: u64,
+        data_len: u64,
+    },
+
+    #[error("tensor '{name}' payload is {actual} bytes, but {expected} were declared")]
+    TensorSizeMismatch {
+        name: String,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error("{written} tensor payloads written for a directory of {expected}")]
+    TensorCountMismatch { expected: usize, written: usize },
+
+    #[error("failed to decode tensor '{tensor}': {source}")]
+    Decode {
+        tensor: String,
+        #[source]
+        source: DecodeError,
+    },
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<GgufError> for io::Error {
+    fn from(e: GgufError) -> Self {
+        match e {
+            GgufError::Io(e) => e,
+            other => io::Error::new(io::ErrorKind::InvalidData, other),
+        }
+    }
+}
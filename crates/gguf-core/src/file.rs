@@ -0,0 +1,131 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Mutex;
+
+use memmap2::Mmap;
+
+use crate::error::{GgufError, GgufResult};
+use crate::reader::{read_header_with_limits, ReaderLimits};
+use crate::types::{GGUFValue, TensorInfo};
+
+trait ReadSeek: Read + Seek + Send {}
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Where tensor bytes come from once the header has been parsed
+enum Source {
+    Mmap(Mmap),
+    Reader(Mutex<Box<dyn ReadSeek>>),
+}
+
+/// An open GGUF file: parsed header and tensor directory, with tensor data
+/// left on disk until it is asked for
+pub struct GgufFile {
+    pub version: u32,
+    pub alignment: u64,
+    pub metadata: BTreeMap<String, GGUFValue>,
+    pub tensors: Vec<TensorInfo>,
+    data_offset: u64,
+    source: Source,
+}
+
+impl GgufFile {
+    /// Memory-maps a GGUF file and parses its header and tensor directory
+    pub fn open<P: AsRef<Path>>(path: P) -> GgufResult<Self> {
+        Self::open_with_limits(path, &ReaderLimits::default())
+    }
+
+    /// [`GgufFile::open`] with explicit limits for untrusted files
+    pub fn open_with_limits<P: AsRef<Path>>(path: P, limits: &ReaderLimits) -> GgufResult<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read-only; like every mmap-based loader we
+        // assume the file is not truncated or rewritten while it is open.
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header = read_header_with_limits(Cursor::new(&mmap[..]), mmap.len() as u64, limits)?;
+        header.check_data_bounds(mmap.len() as u64)?;
+
+        Ok(GgufFile {
+            version: header.version,
+            alignment: header.alignment,
+            metadata: header.metadata,
+            tensors: header.tensors,
+            data_offset: header.data_offset,
+            source: Source::Mmap(mmap),
+        })
+    }
+
+    /// Parses a GGUF stream that cannot be memory-mapped; tensor data is read
+    /// on demand by seeking within `reader`
+    pub fn from_reader<R: Read + Seek + Send + 'static>(reader: R) -> GgufResult<Self> {
+        Self::from_reader_with_limits(reader, &ReaderLimits::default())
+    }
+
+    /// [`GgufFile::from_reader`] with explicit limits for untrusted input
+    pub fn from_reader_with_limits<R: Read + Seek + Send + 'static>(
+        mut reader: R,
+        limits: &ReaderLimits,
+    ) -> GgufResult<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let header = read_header_with_limits(BufReader::new(&mut reader), len, limits)?;
+        header.check_data_bounds(len)?;
+
+        Ok(GgufFile {
+            version: header.version,
+            alignment: header.alignment,
+            metadata: header.metadata,
+            tensors: header.tensors,
+            data_offset: header.data_offset,
+            source: Source::Reader(Mutex::new(Box::new(reader))),
+        })
+    }
+
+    /// Absolute file position of the aligned tensor data section
+    pub fn data_offset(&self) -> u64 {
+        self.data_offset
+    }
+
+    /// Looks up a tensor directory entry by name
+    pub fn tensor(&self, name: &str) -> Option<&TensorInfo> {
+        self.tensors.iter().find(|t| t.name == name)
+    }
+
+    /// Returns a tensor's raw bytes: borrowed from the mapping for mmapped
+    /// files, read into a new buffer otherwise. Fails for a tensor of a type
+    /// id outside the ggml table, whose size cannot be known.
+    pub fn tensor_data(&self, info: &TensorInfo) -> GgufResult<Cow<'_, [u8]>> {
+        let start = self.data_offset + info.offset;
+        if info.ggml_type().is_none() {
+            return Err(GgufError::UnknownTensorType {
+                offset: start,
+                name: info.name.clone(),
+                type_id: info.type_id,
+            });
+        }
+        match &self.source {
+            Source::Mmap(mmap) => {
+                let range = start as usize..(start + info.size) as usize;
+                mmap.get(range).map(Cow::Borrowed).ok_or_else(|| {
+                    GgufError::TensorOutOfBounds {
+                        offset: start,
+                        name: info.name.clone(),
+                        data_offset: info.offset,
+                        size: info.size,
+                        data_len: (mmap.len() as u64).saturating_sub(self.data_offset),
+                    }
+                })
+            }
+            Source::Reader(reader) => {
+                let mut reader = reader
+                    .lock()
+                    .map_err(|_| io::Error::other("Tensor reader lock poisoned"))?;
+                reader.seek(SeekFrom::Start(start))?;
+                let mut buf = vec![0u8; info.size as usize];
+                reader.read_exact(&mut buf)?;
+                Ok(Cow::Owned(buf))
+            }
+        }
+    }
+}
@@ -1,6 +1,9 @@
 //! GGUF core parsing and writing library
 
 pub mod reader;
+pub mod file;
 pub mod writer;
 pub mod types;
 pub mod decoder;
+pub mod iq_grids;
+pub mod error;
@@ -1,130 +1,449 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::fs::File;
-use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::io::{self, BufReader, Read};
 use byteorder::{LittleEndian, ReadBytesExt};
 
-use crate::types::{GGUFValue, GGUFValueType, GGUFTensor};
+use crate::error::{GgufError, GgufResult};
+use crate::file::GgufFile;
+use crate::types::{
+    align_offset, GGUFValue, GgmlType, GGUFValueType, GGUFTensor, TensorInfo,
+    GGUF_DEFAULT_ALIGNMENT,
+};
 
-/// Reads a GGUF file and returns metadata and tensors
+/// Parsed header and tensor directory of a GGUF file
+#[derive(Debug, Clone)]
+pub struct GgufHeader {
+    pub version: u32,
+    pub alignment: u64,
+    pub metadata: BTreeMap<String, GGUFValue>,
+    pub tensors: Vec<TensorInfo>,
+    /// Absolute file position of the aligned tensor data section
+    pub data_offset: u64,
+}
+
+impl GgufHeader {
+    /// Checks every tensor lies within the data section of a `file_len`-byte
+    /// file. Parsing the header does not need the data section, so this is
+    /// left to readers that go on to load tensor data.
+    pub fn check_data_bounds(&self, file_len: u64) -> GgufResult<()> {
+        let data_len = file_len.saturating_sub(self.data_offset);
+        for tensor in &self.tensors {
+            if tensor.offset.checked_add(tensor.size).is_none_or(|end| end > data_len) {
+                return Err(GgufError::TensorOutOfBounds {
+                    offset: self.data_offset.saturating_add(tensor.offset),
+                    name: tensor.name.clone(),
+                    data_offset: tensor.offset,
+                    size: tensor.size,
+                    data_len,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Upper bounds enforced on untrusted counts and length prefixes before
+/// anything is allocated for them. Every count is additionally checked
+/// against the number of bytes left in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReaderLimits {
+    /// Longest metadata key in bytes
+    pub max_key_len: u64,
+    /// Longest string value or tensor name in bytes
+    pub max_string_len: u64,
+    /// Most elements in a single metadata array
+    pub max_array_len: u64,
+    /// Deepest nesting of arrays within arrays
+    pub max_array_depth: u32,
+    /// Most metadata key/value pairs
+    pub max_metadata_count: u64,
+    /// Most tensors in the directory
+    pub max_tensor_count: u64,
+    /// Most dimensions per tensor
+    pub max_ndims: u32,
+}
+
+impl Default for ReaderLimits {
+    fn default() -> Self {
+        ReaderLimits {
+            max_key_len: 64 * 1024,
+            max_string_len: 64 * 1024 * 1024,
+            max_array_len: 64 * 1024 * 1024,
+            max_array_depth: 8,
+            max_metadata_count: 1024 * 1024,
+            max_tensor_count: 1024 * 1024,
+            max_ndims: 4,
+        }
+    }
+}
+
+/// Reads a GGUF (v2 or v3) file and returns metadata and tensors
 pub fn read_gguf_file<P: AsRef<std::path::Path>>(
     path: P,
-) -> io::Result<(BTreeMap<String, GGUFValue>, Vec<GGUFTensor>)> {
-    let mut file = File::open(&path)?;
-    let mut reader = BufReader::new(&file);
+) -> GgufResult<(BTreeMap<String, GGUFValue>, Vec<GGUFTensor>)> {
+    let file = GgufFile::open(path)?;
+
+    let mut tensors = Vec::with_capacity(file.tensors.len());
+    for info in &file.tensors {
+        tensors.push(GGUFTensor {
+            name: info.name.clone(),
+            type_id: info.type_id,
+            dims: info.dims.clone(),
+            offset: info.offset,
+            values: file.tensor_data(info)?.into_owned(),
+        });
+    }
+
+    Ok((file.metadata, tensors))
+}
+
+/// Reads only the header, metadata and tensor directory of a GGUF file.
+///
+/// The tensor data section is never read, so this is cheap even for
+/// multi-gigabyte models, and works on files whose data section is
+/// truncated or missing; see [`GgufHeader::check_data_bounds`].
+pub fn read_gguf_header<P: AsRef<std::path::Path>>(path: P) -> GgufResult<GgufHeader> {
+    read_gguf_header_with_limits(path, &ReaderLimits::default())
+}
+
+/// [`read_gguf_header`] with explicit limits for untrusted files
+pub fn read_gguf_header_with_limits<P: AsRef<std::path::Path>>(
+    path: P,
+    limits: &ReaderLimits,
+) -> GgufResult<GgufHeader> {
+    let file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    read_header_with_limits(BufReader::new(file), file_len, limits)
+}
+
+/// Parses the header, metadata and tensor directory from the start of a GGUF
+/// stream of `file_len` bytes, without reading any tensor data. `file_len`
+/// bounds the counts read; tensors are not checked against it.
+pub fn read_header<R: Read>(reader: R, file_len: u64) -> GgufResult<GgufHeader> {
+    read_header_with_limits(reader, file_len, &ReaderLimits::default())
+}
+
+/// [`read_header`] with explicit limits for untrusted input
+pub fn read_header_with_limits<R: Read>(
+    reader: R,
+    file_len: u64,
+    limits: &ReaderLimits,
+) -> GgufResult<GgufHeader> {
+    let mut reader = CountingReader::new(reader, file_len, *limits);
 
     let mut magic = [0u8; 4];
-    reader.read_exact(&mut magic)?;
+    reader.field("magic", |r| r.read_exact(&mut magic))?;
     if &magic != b"GGUF" {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "Missing GGUF header"));
+        return Err(GgufError::BadMagic { offset: 0, found: magic });
     }
 
-    let _version = reader.read_u32::<LittleEndian>()?;
-    let tensor_count = reader.read_u64::<LittleEndian>()?;
-    let metadata_count = reader.read_u64::<LittleEndian>()?;
+    let version_offset = reader.position;
+    let version = reader.field("version", |r| r.read_u32::<LittleEndian>())?;
+    if !(2..=3).contains(&version) {
+        return Err(GgufError::UnsupportedVersion {
+            offset: version_offset,
+            version,
+        });
+    }
+    // The smallest possible tensor info is an empty name, no dims, a type
+    // and an offset; the smallest metadata entry is an empty key, a type
+    // tag and a one-byte value.
+    let tensor_count = reader.count("tensor count", 24, limits.max_tensor_count)?;
+    let metadata_count = reader.count("metadata count", 13, limits.max_metadata_count)?;
 
     let mut metadata = BTreeMap::new();
+    let mut alignment_offset = 0;
 
     for _ in 0..metadata_count {
-        let key_len = reader.read_u64::<LittleEndian>()?;
-        let mut key_bytes = vec![0u8; key_len as usize];
-        reader.read_exact(&mut key_bytes)?;
-        let key = String::from_utf8_lossy(&key_bytes).to_string();
-
-        let type_byte = reader.read_u8()?;
-        let parsed = match GGUFValueType::from_u8(type_byte) {
-            GGUFValueType::String => {
-                let len = reader.read_u64::<LittleEndian>()?;
-                let mut buf = vec![0u8; len as usize];
-                reader.read_exact(&mut buf)?;
-                Some(GGUFValue::String(String::from_utf8_lossy(&buf).to_string()))
-            }
-            GGUFValueType::Bool => Some(GGUFValue::Bool(reader.read_u8()? != 0)),
-            GGUFValueType::U64 => Some(GGUFValue::U64(reader.read_u64::<LittleEndian>()?)),
-            GGUFValueType::I64 => Some(GGUFValue::I64(reader.read_i64::<LittleEndian>()?)),
-            GGUFValueType::F64 => Some(GGUFValue::F64(reader.read_f64::<LittleEndian>()?)),
-            GGUFValueType::F32 => Some(GGUFValue::F32(reader.read_f32::<LittleEndian>()?)),
-            GGUFValueType::U8 => Some(GGUFValue::U8(reader.read_u8()?)),
-            GGUFValueType::I8 => Some(GGUFValue::I8(reader.read_i8()?)),
-            GGUFValueType::U16 => Some(GGUFValue::U16(reader.read_u16::<LittleEndian>()?)),
-            GGUFValueType::I16 => Some(GGUFValue::I16(reader.read_i16::<LittleEndian>()?)),
-            GGUFValueType::U32 => Some(GGUFValue::U32(reader.read_u32::<LittleEndian>()?)),
-            GGUFValueType::I32 => Some(GGUFValue::I32(reader.read_i32::<LittleEndian>()?)),
-            GGUFValueType::StringArray => {
-                let count = reader.read_u64::<LittleEndian>()?;
-                let mut items = Vec::with_capacity(count as usize);
-                for _ in 0..count {
-                    let len = reader.read_u64::<LittleEndian>()?;
-                    let mut buf = vec![0u8; len as usize];
-                    reader.read_exact(&mut buf)?;
-                    items.push(String::from_utf8_lossy(&buf).to_string());
-                }
-                Some(GGUFValue::StringArray(items))
-            }
-            GGUFValueType::Binary => {
-                let len = reader.read_u64::<LittleEndian>()?;
-                let mut buf = vec![0u8; len as usize];
-                reader.read_exact(&mut buf)?;
-                Some(GGUFValue::Binary(buf))
-            }
-            GGUFValueType::Array => {
-                eprintln!("⚠️ Skipping unsupported metadata type Array for key: {key}");
-                None
-            }
-            GGUFValueType::Unknown(t) => {
-                eprintln!("⚠️ Skipping unsupported metadata type {t} for key: {key}");
-                None
-            }
-        };
+        let key_offset = reader.position;
+        let key = reader.string("metadata key", limits.max_key_len)?;
+
+        let type_offset = reader.position;
+        let type_id = reader.field(&key, |r| r.read_u32::<LittleEndian>())?;
+        let value = read_value(&mut reader, type_id, type_offset, &key, 0)?;
+        if key == "general.alignment" {
+            alignment_offset = type_offset;
+        }
+
+        if metadata.contains_key(&key) {
+            return Err(GgufError::DuplicateKey {
+                offset: key_offset,
+                key,
+            });
+        }
+        metadata.insert(key, value);
+    }
 
-        if let Some(val) = parsed {
-            metadata.insert(key, val);
+    let alignment = match metadata.get("general.alignment") {
+        Some(GGUFValue::U32(a)) => *a as u64,
+        Some(GGUFValue::U64(a)) => *a,
+        Some(_) => {
+            return Err(GgufError::InvalidValue {
+                offset: alignment_offset,
+                key: "general.alignment".to_string(),
+                reason: "must be an unsigned integer".to_string(),
+            })
         }
+        None => GGUF_DEFAULT_ALIGNMENT,
+    };
+    if !alignment.is_power_of_two() {
+        return Err(GgufError::InvalidAlignment {
+            offset: alignment_offset,
+            alignment,
+        });
     }
 
     // === TENSOR HEADERS ===
-    let mut tensors = Vec::new();
-    let mut tensor_headers = Vec::new();
+    let mut tensor_headers: Vec<(u64, String, u32, Vec<u64>, u64)> =
+        Vec::with_capacity(tensor_count as usize);
+    let mut tensor_names = HashSet::with_capacity(tensor_count as usize);
     for _ in 0..tensor_count {
-        let name_len = reader.read_u64::<LittleEndian>()?;
-        let mut name_bytes = vec![0u8; name_len as usize];
-        reader.read_exact(&mut name_bytes)?;
-        let name = String::from_utf8_lossy(&name_bytes).to_string();
+        let name_offset = reader.position;
+        let name = reader.string("tensor name", limits.max_string_len)?;
+        if !tensor_names.insert(name.clone()) {
+            return Err(GgufError::DuplicateTensor {
+                offset: name_offset,
+                name,
+            });
+        }
 
-        let type_id = reader.read_u32::<LittleEndian>()?;
-        let ndim = reader.read_u32::<LittleEndian>()?;
+        let ndim_offset = reader.position;
+        let ndim = reader.field(&name, |r| r.read_u32::<LittleEndian>())?;
+        if ndim > limits.max_ndims {
+            return Err(GgufError::LimitExceeded {
+                offset: ndim_offset,
+                what: format!("dimension count of tensor '{name}'"),
+                value: ndim as u64,
+                limit: limits.max_ndims as u64,
+            });
+        }
         let mut dims = Vec::with_capacity(ndim as usize);
         for _ in 0..ndim {
-            dims.push(reader.read_u64::<LittleEndian>()?);
+            dims.push(reader.field(&name, |r| r.read_u64::<LittleEndian>())?);
         }
+        let type_id = reader.field(&name, |r| r.read_u32::<LittleEndian>())?;
 
-        let offset = reader.read_u64::<LittleEndian>()?;
+        let offset = reader.field(&name, |r| r.read_u64::<LittleEndian>())?;
+        if !offset.is_multiple_of(alignment) {
+            return Err(GgufError::MisalignedOffset {
+                offset: name_offset,
+                name,
+                data_offset: offset,
+                alignment,
+            });
+        }
 
-        tensor_headers.push((name, type_id, dims, offset));
+        tensor_headers.push((name_offset, name, type_id, dims, offset));
     }
 
-    // === TENSOR BLOBS ===
-    let file_len = file.metadata()?.len();
-    for i in 0..tensor_headers.len() {
-        let (name, type_id, dims, offset) = &tensor_headers[i];
-        let end = if i + 1 < tensor_headers.len() {
-            tensor_headers[i + 1].3
-        } else {
-            file_len
-        };
+    // === TENSOR DIRECTORY ===
+    // Offsets are relative to the data section, which starts at the next
+    // alignment boundary after the tensor headers.
+    let data_offset = align_offset(reader.position, alignment);
 
-        let size = end - offset;
-        file.seek(SeekFrom::Start(*offset))?;
-        let mut values = vec![0u8; size as usize];
-        file.read_exact(&mut values)?;
+    let mut tensors = Vec::with_capacity(tensor_headers.len());
+    for (name_offset, name, type_id, dims, offset) in &tensor_headers {
+        // A type id newer than this table keeps the directory readable; the
+        // tensor's size is unknown, so only its data is unavailable
+        let size = match GgmlType::from_u32(*type_id) {
+            Some(ggml_type) => match ggml_type.tensor_nbytes(dims) {
+                Some(size) => size,
+                None => {
+                    return Err(GgufError::InvalidShape {
+                        offset: *name_offset,
+                        name: name.clone(),
+                        type_id: *type_id,
+                        dims: dims.clone(),
+                    })
+                }
+            },
+            None => 0,
+        };
 
-        tensors.push(GGUFTensor {
+        tensors.push(TensorInfo {
             name: name.clone(),
             type_id: *type_id,
             dims: dims.clone(),
             offset: *offset,
-            values,
+            size,
         });
     }
 
-    Ok((metadata, tensors))
+    Ok(GgufHeader {
+        version,
+        alignment,
+        metadata,
+        tensors,
+        data_offset,
+    })
+}
+
+/// Reads a single metadata value payload of the given type, recursing into
+/// arrays. `type_offset` is where the type tag was read, for error reporting.
+fn read_value<R: Read>(
+    reader: &mut CountingReader<R>,
+    type_id: u32,
+    type_offset: u64,
+    key: &str,
+    depth: u32,
+) -> GgufResult<GGUFValue> {
+    let value = match GGUFValueType::from_u32(type_id) {
+        GGUFValueType::String => {
+            let bytes = reader.bytes(key, reader.limits.max_string_len)?;
+            GGUFValue::String(String::from_utf8_lossy(&bytes).to_string())
+        }
+        GGUFValueType::Bool => GGUFValue::Bool(reader.field(key, |r| r.read_u8())? != 0),
+        GGUFValueType::U64 => GGUFValue::U64(reader.field(key, |r| r.read_u64::<LittleEndian>())?),
+        GGUFValueType::I64 => GGUFValue::I64(reader.field(key, |r| r.read_i64::<LittleEndian>())?),
+        GGUFValueType::F64 => GGUFValue::F64(reader.field(key, |r| r.read_f64::<LittleEndian>())?),
+        GGUFValueType::F32 => GGUFValue::F32(reader.field(key, |r| r.read_f32::<LittleEndian>())?),
+        GGUFValueType::U8 => GGUFValue::U8(reader.field(key, |r| r.read_u8())?),
+        GGUFValueType::I8 => GGUFValue::I8(reader.field(key, |r| r.read_i8())?),
+        GGUFValueType::U16 => GGUFValue::U16(reader.field(key, |r| r.read_u16::<LittleEndian>())?),
+        GGUFValueType::I16 => GGUFValue::I16(reader.field(key, |r| r.read_i16::<LittleEndian>())?),
+        GGUFValueType::U32 => GGUFValue::U32(reader.field(key, |r| r.read_u32::<LittleEndian>())?),
+        GGUFValueType::I32 => GGUFValue::I32(reader.field(key, |r| r.read_i32::<LittleEndian>())?),
+        GGUFValueType::Array => {
+            if depth >= reader.limits.max_array_depth {
+                return Err(GgufError::LimitExceeded {
+                    offset: type_offset,
+                    what: format!("array nesting of '{key}'"),
+                    value: depth as u64 + 1,
+                    limit: reader.limits.max_array_depth as u64,
+                });
+            }
+            let elem_offset = reader.position;
+            let elem_type = reader.field(key, |r| r.read_u32::<LittleEndian>())?;
+            let min_elem_size = match GGUFValueType::from_u32(elem_type) {
+                GGUFValueType::U8 | GGUFValueType::I8 | GGUFValueType::Bool => 1,
+                GGUFValueType::U16 | GGUFValueType::I16 => 2,
+                GGUFValueType::U32 | GGUFValueType::I32 | GGUFValueType::F32 => 4,
+                GGUFValueType::U64 | GGUFValueType::I64 | GGUFValueType::F64 => 8,
+                GGUFValueType::String => 8,
+                GGUFValueType::Array => 12,
+                GGUFValueType::Unknown(type_id) => {
+                    return Err(GgufError::UnknownValueType {
+                        offset: elem_offset,
+                        key: key.to_string(),
+                        type_id,
+                    })
+                }
+            };
+            let count = reader.count(
+                &format!("element count of '{key}'"),
+                min_elem_size,
+                reader.limits.max_array_len,
+            )?;
+            let mut items = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                items.push(read_value(reader, elem_type, elem_offset, key, depth + 1)?);
+            }
+            GGUFValue::Array(GGUFValueType::from_u32(elem_type), items)
+        }
+        // The payload size of an unknown type is unknown, so the rest of the
+        // stream cannot be parsed.
+        GGUFValueType::Unknown(type_id) => {
+            return Err(GgufError::UnknownValueType {
+                offset: type_offset,
+                key: key.to_string(),
+                type_id,
+            })
+        }
+    };
+    Ok(value)
+}
+
+/// Tracks the stream position so the data section can be located without
+/// seeking, and so errors can report where they happened
+struct CountingReader<R: Read> {
+    inner: R,
+    position: u64,
+    len: u64,
+    limits: ReaderLimits,
+}
+
+impl<R: Read> CountingReader<R> {
+    fn new(inner: R, len: u64, limits: ReaderLimits) -> Self {
+        CountingReader {
+            inner,
+            position: 0,
+            len,
+            limits,
+        }
+    }
+
+    fn remaining(&self) -> u64 {
+        self.len.saturating_sub(self.position)
+    }
+
+    /// Reads a u64 element count, refusing counts above `max` or above the
+    /// number of `min_item_size`-byte items left in the stream
+    fn count(&mut self, what: &str, min_item_size: u64, max: u64) -> GgufResult<u64> {
+        let offset = self.position;
+        let count = self.field(what, |r| r.read_u64::<LittleEndian>())?;
+        let limit = max.min(self.remaining() / min_item_size);
+        if count > limit {
+            return Err(GgufError::LimitExceeded {
+                offset,
+                what: what.to_string(),
+                value: count,
+                limit,
+            });
+        }
+        Ok(count)
+    }
+
+    /// Runs a fixed-size read, reporting EOF as a truncated header at the
+    /// offset the read started from
+    fn field<T>(
+        &mut self,
+        context: &str,
+        read: impl FnOnce(&mut Self) -> io::Result<T>,
+    ) -> GgufResult<T> {
+        let offset = self.position;
+        read(self).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => GgufError::TruncatedHeader {
+                offset,
+                context: context.to_string(),
+            },
+            _ => GgufError::Io(e),
+        })
+    }
+
+    /// Reads a u64 length prefix and that many bytes, refusing lengths above
+    /// `max` or past the end of the stream
+    fn bytes(&mut self, context: &str, max: u64) -> GgufResult<Vec<u8>> {
+        let offset = self.position;
+        let len = self.field(context, |r| r.read_u64::<LittleEndian>())?;
+        let limit = max.min(self.remaining());
+        if len > limit {
+            return Err(GgufError::OversizedLength {
+                offset,
+                context: context.to_string(),
+                len,
+                limit,
+            });
+        }
+        let mut buf = vec![0u8; len as usize];
+        self.field(context, |r| r.read_exact(&mut buf))?;
+        Ok(buf)
+    }
+
+    /// Reads a length-prefixed string that must be valid UTF-8
+    fn string(&mut self, context: &str, max: u64) -> GgufResult<String> {
+        let offset = self.position;
+        let bytes = self.bytes(context, max)?;
+        String::from_utf8(bytes).map_err(|_| GgufError::BadUtf8 {
+            offset,
+            context: context.to_string(),
+        })
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
 }
@@ -15,75 +15,300 @@ pub enum GGUFValue {
     I64(i64),
     F32(f32),
     F64(f64),
-    StringArray(Vec<String>),
-    Binary(Vec<u8>),
-    Unknown(u8), // fallback
+    /// Typed array: every element has the given type, which may itself be `Array`
+    Array(GGUFValueType, Vec<GGUFValue>),
+    Unknown(u32), // fallback
 }
 
+impl GGUFValue {
+    /// The metadata type tag this value is written with
+    pub fn value_type(&self) -> GGUFValueType {
+        match self {
+            GGUFValue::String(_) => GGUFValueType::String,
+            GGUFValue::Bool(_) => GGUFValueType::Bool,
+            GGUFValue::U8(_) => GGUFValueType::U8,
+            GGUFValue::I8(_) => GGUFValueType::I8,
+            GGUFValue::U16(_) => GGUFValueType::U16,
+            GGUFValue::I16(_) => GGUFValueType::I16,
+            GGUFValue::U32(_) => GGUFValueType::U32,
+            GGUFValue::I32(_) => GGUFValueType::I32,
+            GGUFValue::U64(_) => GGUFValueType::U64,
+            GGUFValue::I64(_) => GGUFValueType::I64,
+            GGUFValue::F32(_) => GGUFValueType::F32,
+            GGUFValue::F64(_) => GGUFValueType::F64,
+            GGUFValue::Array(..) => GGUFValueType::Array,
+            GGUFValue::Unknown(t) => GGUFValueType::Unknown(*t),
+        }
+    }
+}
+
+/// Metadata value type tags as defined by the GGUF spec (`gguf_metadata_value_type`)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GGUFValueType {
-    String,
-    Array,
-    Bool,
     U8,
     I8,
     U16,
     I16,
     U32,
     I32,
+    F32,
+    Bool,
+    String,
+    Array,
     U64,
     I64,
-    F32,
     F64,
-    StringArray,
-    Binary,
-    Unknown(u8), // required for fallback handling
+    Unknown(u32), // required for fallback handling
 }
 
 impl GGUFValueType {
-    pub fn from_u8(n: u8) -> Self {
+    pub fn from_u32(n: u32) -> Self {
         match n {
-            1 => GGUFValueType::String,
-            2 => GGUFValueType::Array,
-            3 => GGUFValueType::U8,
-            4 => GGUFValueType::I8,
-            5 => GGUFValueType::U16,
-            6 => GGUFValueType::I16,
-            7 => GGUFValueType::U32,
-            8 => GGUFValueType::I32,
-            9 => GGUFValueType::U64,
-            10 => GGUFValueType::Bool,
+            0 => GGUFValueType::U8,
+            1 => GGUFValueType::I8,
+            2 => GGUFValueType::U16,
+            3 => GGUFValueType::I16,
+            4 => GGUFValueType::U32,
+            5 => GGUFValueType::I32,
+            6 => GGUFValueType::F32,
+            7 => GGUFValueType::Bool,
+            8 => GGUFValueType::String,
+            9 => GGUFValueType::Array,
+            10 => GGUFValueType::U64,
             11 => GGUFValueType::I64,
             12 => GGUFValueType::F64,
-            13 => GGUFValueType::F32,
-            14 => GGUFValueType::StringArray,
-            15 => GGUFValueType::Binary,
             _ => GGUFValueType::Unknown(n),
         }
     }
 
-    pub fn to_u8(self) -> u8 {
+    pub fn to_u32(self) -> u32 {
         match self {
-            GGUFValueType::String => 1,
-            GGUFValueType::Array => 2,
-            GGUFValueType::U8 => 3,
-            GGUFValueType::I8 => 4,
-            GGUFValueType::U16 => 5,
-            GGUFValueType::I16 => 6,
-            GGUFValueType::U32 => 7,
-            GGUFValueType::I32 => 8,
-            GGUFValueType::U64 => 9,
-            GGUFValueType::Bool => 10,
+            GGUFValueType::U8 => 0,
+            GGUFValueType::I8 => 1,
+            GGUFValueType::U16 => 2,
+            GGUFValueType::I16 => 3,
+            GGUFValueType::U32 => 4,
+            GGUFValueType::I32 => 5,
+            GGUFValueType::F32 => 6,
+            GGUFValueType::Bool => 7,
+            GGUFValueType::String => 8,
+            GGUFValueType::Array => 9,
+            GGUFValueType::U64 => 10,
             GGUFValueType::I64 => 11,
             GGUFValueType::F64 => 12,
-            GGUFValueType::F32 => 13,
-            GGUFValueType::StringArray => 14,
-            GGUFValueType::Binary => 15,
             GGUFValueType::Unknown(n) => n,
         }
     }
 }
 
+/// Alignment of the tensor data section when `general.alignment` is absent
+pub const GGUF_DEFAULT_ALIGNMENT: u64 = 32;
+
+/// ggml tensor data types, with the ids used in GGUF tensor infos
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum GgmlType {
+    F32 = 0,
+    F16 = 1,
+    Q4_0 = 2,
+    Q4_1 = 3,
+    Q5_0 = 6,
+    Q5_1 = 7,
+    Q8_0 = 8,
+    Q8_1 = 9,
+    Q2_K = 10,
+    Q3_K = 11,
+    Q4_K = 12,
+    Q5_K = 13,
+    Q6_K = 14,
+    Q8_K = 15,
+    IQ2_XXS = 16,
+    IQ2_XS = 17,
+    IQ3_XXS = 18,
+    IQ1_S = 19,
+    IQ4_NL = 20,
+    IQ3_S = 21,
+    IQ2_S = 22,
+    IQ4_XS = 23,
+    I8 = 24,
+    I16 = 25,
+    I32 = 26,
+    I64 = 27,
+    F64 = 28,
+    IQ1_M = 29,
+    BF16 = 30,
+    TQ1_0 = 34,
+    TQ2_0 = 35,
+}
+
+impl GgmlType {
+    /// Every type, in id order
+    pub const ALL: [GgmlType; 31] = [
+        GgmlType::F32,
+        GgmlType::F16,
+        GgmlType::Q4_0,
+        GgmlType::Q4_1,
+        GgmlType::Q5_0,
+        GgmlType::Q5_1,
+        GgmlType::Q8_0,
+        GgmlType::Q8_1,
+        GgmlType::Q2_K,
+        GgmlType::Q3_K,
+        GgmlType::Q4_K,
+        GgmlType::Q5_K,
+        GgmlType::Q6_K,
+        GgmlType::Q8_K,
+        GgmlType::IQ2_XXS,
+        GgmlType::IQ2_XS,
+        GgmlType::IQ3_XXS,
+        GgmlType::IQ1_S,
+        GgmlType::IQ4_NL,
+        GgmlType::IQ3_S,
+        GgmlType::IQ2_S,
+        GgmlType::IQ4_XS,
+        GgmlType::I8,
+        GgmlType::I16,
+        GgmlType::I32,
+        GgmlType::I64,
+        GgmlType::F64,
+        GgmlType::IQ1_M,
+        GgmlType::BF16,
+        GgmlType::TQ1_0,
+        GgmlType::TQ2_0,
+    ];
+
+    pub fn from_u32(id: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| *t as u32 == id)
+    }
+
+    pub fn id(self) -> u32 {
+        self as u32
+    }
+
+    /// Name as used by ggml and llama.cpp (`"Q4_K"`, `"BF16"`, ...)
+    pub fn name(self) -> &'static str {
+        match self {
+            GgmlType::F32 => "F32",
+            GgmlType::F16 => "F16",
+            GgmlType::Q4_0 => "Q4_0",
+            GgmlType::Q4_1 => "Q4_1",
+            GgmlType::Q5_0 => "Q5_0",
+            GgmlType::Q5_1 => "Q5_1",
+            GgmlType::Q8_0 => "Q8_0",
+            GgmlType::Q8_1 => "Q8_1",
+            GgmlType::Q2_K => "Q2_K",
+            GgmlType::Q3_K => "Q3_K",
+            GgmlType::Q4_K => "Q4_K",
+            GgmlType::Q5_K => "Q5_K",
+            GgmlType::Q6_K => "Q6_K",
+            GgmlType::Q8_K => "Q8_K",
+            GgmlType::IQ2_XXS => "IQ2_XXS",
+            GgmlType::IQ2_XS => "IQ2_XS",
+            GgmlType::IQ3_XXS => "IQ3_XXS",
+            GgmlType::IQ1_S => "IQ1_S",
+            GgmlType::IQ4_NL => "IQ4_NL",
+            GgmlType::IQ3_S => "IQ3_S",
+            GgmlType::IQ2_S => "IQ2_S",
+            GgmlType::IQ4_XS => "IQ4_XS",
+            GgmlType::I8 => "I8",
+            GgmlType::I16 => "I16",
+            GgmlType::I32 => "I32",
+            GgmlType::I64 => "I64",
+            GgmlType::F64 => "F64",
+            GgmlType::IQ1_M => "IQ1_M",
+            GgmlType::BF16 => "BF16",
+            GgmlType::TQ1_0 => "TQ1_0",
+            GgmlType::TQ2_0 => "TQ2_0",
+        }
+    }
+
+    /// Number of elements stored in one block
+    pub fn block_size(self) -> u64 {
+        self.block_info().0
+    }
+
+    /// Number of bytes in one block
+    pub fn type_size(self) -> u64 {
+        self.block_info().1
+    }
+
+    /// Whether elements are stored in scaled blocks rather than one by one
+    pub fn is_quantized(self) -> bool {
+        self.block_size() > 1
+    }
+
+    /// Byte size of a tensor's data, or `None` when the first dimension is
+    /// not a whole number of blocks or the size overflows
+    pub fn tensor_nbytes(self, dims: &[u64]) -> Option<u64> {
+        let (block_size, type_size) = self.block_info();
+        let row = dims.first().copied().unwrap_or(1);
+        if !row.is_multiple_of(block_size) {
+            return None;
+        }
+        dims.iter()
+            .skip(1)
+            .try_fold((row / block_size).checked_mul(type_size)?, |acc, &d| acc.checked_mul(d))
+    }
+
+    fn block_info(self) -> (u64, u64) {
+        match self {
+            GgmlType::F32 => (1, 4),
+            GgmlType::F16 => (1, 2),
+            GgmlType::Q4_0 => (32, 18),
+            GgmlType::Q4_1 => (32, 20),
+            GgmlType::Q5_0 => (32, 22),
+            GgmlType::Q5_1 => (32, 24),
+            GgmlType::Q8_0 => (32, 34),
+            GgmlType::Q8_1 => (32, 36),
+            GgmlType::Q2_K => (256, 84),
+            GgmlType::Q3_K => (256, 110),
+            GgmlType::Q4_K => (256, 144),
+            GgmlType::Q5_K => (256, 176),
+            GgmlType::Q6_K => (256, 210),
+            GgmlType::Q8_K => (256, 292),
+            GgmlType::IQ2_XXS => (256, 66),
+            GgmlType::IQ2_XS => (256, 74),
+            GgmlType::IQ3_XXS => (256, 98),
+            GgmlType::IQ1_S => (256, 50),
+            GgmlType::IQ4_NL => (32, 18),
+            GgmlType::IQ3_S => (256, 110),
+            GgmlType::IQ2_S => (256, 82),
+            GgmlType::IQ4_XS => (256, 136),
+            GgmlType::I8 => (1, 1),
+            GgmlType::I16 => (1, 2),
+            GgmlType::I32 => (1, 4),
+            GgmlType::I64 => (1, 8),
+            GgmlType::F64 => (1, 8),
+            GgmlType::IQ1_M => (256, 56),
+            GgmlType::BF16 => (1, 2),
+            GgmlType::TQ1_0 => (256, 54),
+            GgmlType::TQ2_0 => (256, 66),
+        }
+    }
+}
+
+impl std::fmt::Display for GgmlType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for GgmlType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown ggml type: {s}"))
+    }
+}
+
+/// Rounds `offset` up to the next multiple of `alignment`
+pub fn align_offset(offset: u64, alignment: u64) -> u64 {
+    offset.div_ceil(alignment) * alignment
+}
 
 /// Minimal tensor definition for writing (JSON-based)
 #[derive(Debug, Deserialize, Clone)]
@@ -101,13 +326,31 @@ pub struct GGUFTensor {
     pub name: String,
     pub type_id: u32,
     pub dims: Vec<u64>,
+    /// Offset relative to the start of the aligned tensor data section
     pub offset: u64,
     pub values: Vec<u8>,
 }
 
-#[derive(Debug, Clone)]
-pub enum QuantizedTensor {
-    Q4_0 { scale: f32, zero: f32, values: Vec<u8> },
-    Q5_1 { scale: f32, zero: f32, values: Vec<u8> },
-    Unknown { raw: Vec<u8> },
-}
\ No newline at end of file
+/// Tensor directory entry: everything about a tensor except its data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TensorInfo {
+    pub name: String,
+    pub type_id: u32,
+    pub dims: Vec<u64>,
+    /// Offset relative to the start of the aligned tensor data section
+    pub offset: u64,
+    /// Byte size of the tensor data, 0 when `type_id` is not a known ggml type
+    pub size: u64,
+}
+
+impl TensorInfo {
+    /// The tensor's ggml type, or `None` for ids outside the ggml table
+    pub fn ggml_type(&self) -> Option<GgmlType> {
+        GgmlType::from_u32(self.type_id)
+    }
+
+    /// Total number of elements
+    pub fn n_elements(&self) -> u64 {
+        self.dims.iter().product()
+    }
+}
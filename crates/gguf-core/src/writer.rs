@@ -1,126 +1,368 @@
 use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::io::{self, BufWriter, Read, Write};
 use byteorder::{LittleEndian, WriteBytesExt};
 
-use crate::types::{GGUFValue, GGUFTensor};
+use crate::error::{GgufError, GgufResult};
+use crate::types::{align_offset, GGUFValue, GGUFTensor, TensorInfo, GGUF_DEFAULT_ALIGNMENT};
 
-/// Write a GGUF file with metadata and tensors
+/// Container layout options for [`write_gguf_file_with_options`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// GGUF version to emit (2 or 3)
+    pub version: u32,
+    /// Alignment of every tensor in the data section, a power of two
+    pub alignment: u64,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            version: 3,
+            alignment: GGUF_DEFAULT_ALIGNMENT,
+        }
+    }
+}
+
+/// Write a GGUF v3 file with metadata and tensors using the default alignment
 pub fn write_gguf_file<P: AsRef<std::path::Path>>(
     path: P,
     metadata: &BTreeMap<String, GGUFValue>,
     tensors: &[GGUFTensor],
-) -> io::Result<()> {
-    let file = File::create(path)?;
-    let mut writer = BufWriter::new(file);
-
-    // === HEADER ===
-    writer.write_all(b"GGUF")?;
-    writer.write_u32::<LittleEndian>(2)?; // version
-    writer.write_u64::<LittleEndian>(tensors.len() as u64)?;
-    writer.write_u64::<LittleEndian>(metadata.len() as u64)?;
-
-    // === METADATA ===
-    for (key, value) in metadata {
-        writer.write_u64::<LittleEndian>(key.len() as u64)?;
-        writer.write_all(key.as_bytes())?;
-
-        match value {
-            GGUFValue::String(s) => {
-                writer.write_u8(1)?;
-                writer.write_u64::<LittleEndian>(s.len() as u64)?;
-                writer.write_all(s.as_bytes())?;
-            }
-            GGUFValue::Bool(b) => {
-                writer.write_u8(10)?;
-                writer.write_u8(if *b { 1 } else { 0 })?;
-            }
-            GGUFValue::U64(v) => {
-                writer.write_u8(9)?;
-                writer.write_u64::<LittleEndian>(*v)?;
-            }
-            GGUFValue::I64(v) => {
-                writer.write_u8(11)?;
-                writer.write_i64::<LittleEndian>(*v)?;
-            }
-            GGUFValue::F64(v) => {
-                writer.write_u8(12)?;
-                writer.write_f64::<LittleEndian>(*v)?;
-            }
-            GGUFValue::F32(v) => {
-                writer.write_u8(13)?;
-                writer.write_f32::<LittleEndian>(*v)?;
-            }
-            GGUFValue::U8(v) => {
-                writer.write_u8(3)?;
-                writer.write_u8(*v)?;
-            }
-            GGUFValue::I8(v) => {
-                writer.write_u8(4)?;
-                writer.write_i8(*v)?;
-            }
-            GGUFValue::U16(v) => {
-                writer.write_u8(5)?;
-                writer.write_u16::<LittleEndian>(*v)?;
-            }
-            GGUFValue::I16(v) => {
-                writer.write_u8(6)?;
-                writer.write_i16::<LittleEndian>(*v)?;
-            }
-            GGUFValue::U32(v) => {
-                writer.write_u8(7)?;
-                writer.write_u32::<LittleEndian>(*v)?;
-            }
-            GGUFValue::I32(v) => {
-                writer.write_u8(8)?;
-                writer.write_i32::<LittleEndian>(*v)?;
-            }
-            GGUFValue::StringArray(arr) => {
-                writer.write_u8(14)?;
-                writer.write_u64::<LittleEndian>(arr.len() as u64)?;
-                for s in arr {
-                    writer.write_u64::<LittleEndian>(s.len() as u64)?;
-                    writer.write_all(s.as_bytes())?;
-                }
-            }
-            GGUFValue::Binary(data) => {
-                writer.write_u8(15)?;
-                writer.write_u64::<LittleEndian>(data.len() as u64)?;
-                writer.write_all(data)?;
+) -> GgufResult<()> {
+    write_gguf_file_with_options(path, metadata, tensors, &WriteOptions::default())
+}
+
+/// Write a GGUF file with metadata and tensors using the given layout options.
+///
+/// Tensor offsets are relative to the data section and each tensor is padded
+/// to `options.alignment`. Any `general.alignment` entry in `metadata` is
+/// replaced by the configured alignment, which is only written when it
+/// differs from the default.
+pub fn write_gguf_file_with_options<P: AsRef<std::path::Path>>(
+    path: P,
+    metadata: &BTreeMap<String, GGUFValue>,
+    tensors: &[GGUFTensor],
+    options: &WriteOptions,
+) -> GgufResult<()> {
+    let directory = tensors
+        .iter()
+        .map(|t| TensorInfo {
+            name: t.name.clone(),
+            type_id: t.type_id,
+            dims: t.dims.clone(),
+            offset: 0,
+            size: t.values.len() as u64,
+        })
+        .collect();
+
+    let mut writer = GgufWriter::create(path, metadata, directory, options)?;
+    for tensor in tensors {
+        writer.write_tensor_data(&tensor.values)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Streaming GGUF writer.
+///
+/// The header, metadata and tensor directory are written as soon as the
+/// writer is created, with offsets derived from the declared tensor sizes.
+/// Tensor payloads are then written one at a time, in directory order, and
+/// each must be exactly as long as declared. No seeking is needed, so any
+/// `Write` works as the destination.
+pub struct GgufWriter<W: Write> {
+    writer: CountingWriter<W>,
+    tensors: Vec<TensorInfo>,
+    next: usize,
+    alignment: u64,
+}
+
+impl GgufWriter<BufWriter<File>> {
+    /// Creates `path` and writes the header for the given tensor directory
+    pub fn create<P: AsRef<std::path::Path>>(
+        path: P,
+        metadata: &BTreeMap<String, GGUFValue>,
+        tensors: Vec<TensorInfo>,
+        options: &WriteOptions,
+    ) -> GgufResult<Self> {
+        let file = File::create(path)?;
+        GgufWriter::new(BufWriter::new(file), metadata, tensors, options)
+    }
+}
+
+impl<W: Write> GgufWriter<W> {
+    /// Writes the header for the given tensor directory to `writer`.
+    ///
+    /// The `offset` of each entry is ignored and recomputed from the sizes;
+    /// every tensor must have a known ggml type whose size matches its dims,
+    /// or writing stops at the first entry that does not.
+    pub fn new(
+        writer: W,
+        metadata: &BTreeMap<String, GGUFValue>,
+        mut tensors: Vec<TensorInfo>,
+        options: &WriteOptions,
+    ) -> GgufResult<Self> {
+        if !(2..=3).contains(&options.version) {
+            return Err(GgufError::UnsupportedVersion {
+                offset: 4,
+                version: options.version,
+            });
+        }
+        let alignment = options.alignment;
+        if !alignment.is_power_of_two() || alignment > u32::MAX as u64 {
+            // Where general.alignment would be written, as the first metadata entry
+            return Err(GgufError::InvalidAlignment { offset: 24, alignment });
+        }
+
+        let alignment_entry = (alignment != GGUF_DEFAULT_ALIGNMENT)
+            .then(|| ("general.alignment".to_string(), GGUFValue::U32(alignment as u32)));
+        let entries: Vec<(&String, &GGUFValue)> = alignment_entry
+            .iter()
+            .map(|(k, v)| (k, v))
+            .chain(metadata.iter().filter(|(k, _)| k.as_str() != "general.alignment"))
+            .collect();
+
+        let mut writer = CountingWriter::new(writer);
+
+        // === HEADER ===
+        writer.write_all(b"GGUF")?;
+        writer.write_u32::<LittleEndian>(options.version)?;
+        writer.write_u64::<LittleEndian>(tensors.len() as u64)?;
+        writer.write_u64::<LittleEndian>(entries.len() as u64)?;
+
+        // === METADATA ===
+        for (key, value) in entries {
+            writer.write_u64::<LittleEndian>(key.len() as u64)?;
+            writer.write_all(key.as_bytes())?;
+            writer.write_u32::<LittleEndian>(value.value_type().to_u32())?;
+            write_value(&mut writer, value, key)?;
+        }
+
+        // === TENSOR HEADERS ===
+        let mut next_offset = 0u64;
+        for tensor in &mut tensors {
+            let info_offset = writer.position;
+            let Some(ggml_type) = tensor.ggml_type() else {
+                return Err(GgufError::UnknownTensorType {
+                    offset: info_offset,
+                    name: tensor.name.clone(),
+                    type_id: tensor.type_id,
+                });
+            };
+            if ggml_type.tensor_nbytes(&tensor.dims) != Some(tensor.size) {
+                return Err(GgufError::InvalidShape {
+                    offset: info_offset,
+                    name: tensor.name.clone(),
+                    type_id: tensor.type_id,
+                    dims: tensor.dims.clone(),
+                });
             }
-            GGUFValue::Unknown(type_id) => {
-                writer.write_u8(*type_id)?;
+            tensor.offset = next_offset;
+            next_offset = align_offset(next_offset + tensor.size, alignment);
+
+            writer.write_u64::<LittleEndian>(tensor.name.len() as u64)?;
+            writer.write_all(tensor.name.as_bytes())?;
+            writer.write_u32::<LittleEndian>(tensor.dims.len() as u32)?;
+            for &dim in &tensor.dims {
+                writer.write_u64::<LittleEndian>(dim)?;
             }
+            writer.write_u32::<LittleEndian>(tensor.type_id)?;
+            writer.write_u64::<LittleEndian>(tensor.offset)?;
         }
+
+        writer.pad_to(alignment)?;
+
+        Ok(GgufWriter {
+            writer,
+            tensors,
+            next: 0,
+            alignment,
+        })
     }
 
-    // === TENSOR HEADERS ===
-    let mut offset_positions = Vec::with_capacity(tensors.len());
-    for tensor in tensors {
-        writer.write_u64::<LittleEndian>(tensor.name.len() as u64)?;
-        writer.write_all(tensor.name.as_bytes())?;
-        writer.write_u32::<LittleEndian>(tensor.type_id)?;
-        writer.write_u32::<LittleEndian>(tensor.dims.len() as u32)?;
-        for &dim in &tensor.dims {
-            writer.write_u64::<LittleEndian>(dim)?;
+    /// The tensor whose payload is expected next, if any
+    pub fn next_tensor(&self) -> Option<&TensorInfo> {
+        self.tensors.get(self.next)
+    }
+
+    /// Writes the next tensor's payload from a buffer
+    pub fn write_tensor_data(&mut self, data: &[u8]) -> GgufResult<()> {
+        self.write_tensor_with(|w| w.write_all(data))
+    }
+
+    /// Writes the next tensor's payload by copying exactly its declared size
+    /// from `reader`, which must then be exhausted
+    pub fn write_tensor_from<R: Read>(&mut self, mut reader: R) -> GgufResult<()> {
+        let mut trailing = 0u64;
+        self.write_tensor_with(|w| {
+            let size = w.remaining;
+            io::copy(&mut (&mut reader).take(size), w)?;
+            trailing = io::copy(&mut reader, &mut io::sink())?;
+            Ok(())
+        })?;
+        if trailing > 0 {
+            let tensor = &self.tensors[self.next - 1];
+            return Err(GgufError::TensorSizeMismatch {
+                name: tensor.name.clone(),
+                expected: tensor.size,
+                actual: tensor.size + trailing,
+            });
         }
+        Ok(())
+    }
+
+    /// Writes the next tensor's payload through a callback. Writing more
+    /// than the declared size fails inside the callback; writing less fails
+    /// once it returns.
+    pub fn write_tensor_with<F>(&mut self, write: F) -> GgufResult<()>
+    where
+        F: FnOnce(&mut TensorSink<'_, W>) -> io::Result<()>,
+    {
+        let tensor = self.tensors.get(self.next).ok_or(GgufError::TensorCountMismatch {
+            expected: self.tensors.len(),
+            written: self.next + 1,
+        })?;
 
-        offset_positions.push(writer.seek(SeekFrom::Current(0))?);
-        writer.write_u64::<LittleEndian>(0)?; // reserve space for tensor offset
+        let mut sink = TensorSink {
+            writer: &mut self.writer,
+            remaining: tensor.size,
+            overflow: 0,
+        };
+        let result = write(&mut sink);
+        let (remaining, overflow) = (sink.remaining, sink.overflow);
+
+        if overflow == 0 {
+            result?;
+        }
+        if remaining > 0 || overflow > 0 {
+            return Err(GgufError::TensorSizeMismatch {
+                name: tensor.name.clone(),
+                expected: tensor.size,
+                actual: tensor.size - remaining + overflow,
+            });
+        }
+
+        self.writer.pad_to(self.alignment)?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Flushes the output after checking every tensor was written, and
+    /// returns the underlying writer
+    pub fn finish(mut self) -> GgufResult<W> {
+        if self.next != self.tensors.len() {
+            return Err(GgufError::TensorCountMismatch {
+                expected: self.tensors.len(),
+                written: self.next,
+            });
+        }
+        self.writer.flush()?;
+        Ok(self.writer.inner)
     }
+}
+
+/// Destination for one tensor payload, refusing writes past its declared size
+pub struct TensorSink<'a, W: Write> {
+    writer: &'a mut CountingWriter<W>,
+    remaining: u64,
+    /// Bytes of refused writes, which count towards the reported payload size
+    overflow: u64,
+}
 
-    // === TENSOR BINARY PAYLOADS ===
-    for (i, tensor) in tensors.iter().enumerate() {
-        let data_offset = writer.seek(SeekFrom::Current(0))?;
-        writer.write_all(&tensor.values)?;
+impl<W: Write> Write for TensorSink<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            self.overflow += buf.len() as u64;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "tensor payload exceeds its declared size",
+            ));
+        }
+        let written = self.writer.write(buf)?;
+        self.remaining -= written as u64;
+        Ok(written)
+    }
 
-        // backpatch offset
-        let return_pos = writer.seek(SeekFrom::Current(0))?;
-        writer.seek(SeekFrom::Start(offset_positions[i]))?;
-        writer.write_u64::<LittleEndian>(data_offset)?;
-        writer.seek(SeekFrom::Start(return_pos))?;
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
     }
+}
 
-    writer.flush()?;
+/// Writes a metadata value payload (without its type tag), recursing into arrays
+fn write_value<W: Write>(writer: &mut CountingWriter<W>, value: &GGUFValue, key: &str) -> GgufResult<()> {
+    let offset = writer.position;
+    match value {
+        GGUFValue::String(s) => {
+            writer.write_u64::<LittleEndian>(s.len() as u64)?;
+            writer.write_all(s.as_bytes())?;
+        }
+        GGUFValue::Bool(b) => writer.write_u8(if *b { 1 } else { 0 })?,
+        GGUFValue::U8(v) => writer.write_u8(*v)?,
+        GGUFValue::I8(v) => writer.write_i8(*v)?,
+        GGUFValue::U16(v) => writer.write_u16::<LittleEndian>(*v)?,
+        GGUFValue::I16(v) => writer.write_i16::<LittleEndian>(*v)?,
+        GGUFValue::U32(v) => writer.write_u32::<LittleEndian>(*v)?,
+        GGUFValue::I32(v) => writer.write_i32::<LittleEndian>(*v)?,
+        GGUFValue::U64(v) => writer.write_u64::<LittleEndian>(*v)?,
+        GGUFValue::I64(v) => writer.write_i64::<LittleEndian>(*v)?,
+        GGUFValue::F32(v) => writer.write_f32::<LittleEndian>(*v)?,
+        GGUFValue::F64(v) => writer.write_f64::<LittleEndian>(*v)?,
+        GGUFValue::Array(elem_type, items) => {
+            if let Some(item) = items.iter().find(|item| item.value_type() != *elem_type) {
+                return Err(GgufError::InvalidValue {
+                    offset,
+                    key: key.to_string(),
+                    reason: format!(
+                        "{:?} element in {:?} array",
+                        item.value_type(),
+                        elem_type
+                    ),
+                });
+            }
+            writer.write_u32::<LittleEndian>(elem_type.to_u32())?;
+            writer.write_u64::<LittleEndian>(items.len() as u64)?;
+            for item in items {
+                write_value(writer, item, key)?;
+            }
+        }
+        GGUFValue::Unknown(type_id) => {
+            return Err(GgufError::InvalidValue {
+                offset,
+                key: key.to_string(),
+                reason: format!("cannot write unknown metadata type {type_id}"),
+            })
+        }
+    }
     Ok(())
 }
+
+/// Tracks the number of bytes written so padding can be computed without seeking
+struct CountingWriter<W: Write> {
+    inner: W,
+    position: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        CountingWriter { inner, position: 0 }
+    }
+
+    /// Writes zero bytes up to the next multiple of `alignment`
+    fn pad_to(&mut self, alignment: u64) -> io::Result<()> {
+        let padding = align_offset(self.position, alignment) - self.position;
+        self.write_all(&vec![0u8; padding as usize])
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,85 @@
+//! Known llama.cpp block vectors. `tests/vectors/input.f32` holds a [256, 2]
+//! tensor of 16 blocks covering zero, constant, outlier and wide-range cases;
+//! for each type `<type>.bin` is the output of ggml's `quantize_row_<type>_ref`
+//! on it and `<type>.f32` is ggml's `dequantize_row_<type>` of those blocks.
+//! The i-quant vectors come from `quantize_<type>`, as llama-quantize calls
+//! it; IQ2_XXS and IQ2_XS need importance weights, so theirs are the
+//! `<type>_imatrix` blocks quantized with `imatrix.f32`.
+
+use std::fs;
+use std::path::Path;
+
+use gguf_core::decoder::{dequantize, DecodeError};
+use gguf_core::types::GgmlType;
+
+const DIMS: [u64; 2] = [256, 2];
+const TYPES: &[GgmlType] = &[
+    GgmlType::Q4_0,
+    GgmlType::Q4_1,
+    GgmlType::Q5_0,
+    GgmlType::Q5_1,
+    GgmlType::Q8_0,
+    GgmlType::Q2_K,
+    GgmlType::Q3_K,
+    GgmlType::Q4_K,
+    GgmlType::Q5_K,
+    GgmlType::Q6_K,
+    GgmlType::IQ2_S,
+    GgmlType::IQ3_XXS,
+    GgmlType::IQ3_S,
+    GgmlType::IQ4_NL,
+    GgmlType::IQ4_XS,
+];
+/// Types that are only quantized with importance weights
+const WEIGHTED_TYPES: &[GgmlType] = &[GgmlType::IQ2_XXS, GgmlType::IQ2_XS];
+
+/// Every vector as its type and file stem
+fn vectors() -> impl Iterator<Item = (GgmlType, String)> {
+    let plain = TYPES.iter().map(|&ty| (ty, ty.name().to_lowercase()));
+    let weighted = WEIGHTED_TYPES.iter().map(|&ty| (ty, format!("{}_imatrix", ty.name().to_lowercase())));
+    plain.chain(weighted)
+}
+
+fn vector(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/vectors").join(name);
+    fs::read(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+}
+
+fn floats(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+#[test]
+fn decoders_match_ggml_dequantize() {
+    for (ty, stem) in vectors() {
+        let blocks = vector(&format!("{stem}.bin"));
+        let expected = floats(&vector(&format!("{stem}.f32")));
+
+        assert_eq!(ty.tensor_nbytes(&DIMS), Some(blocks.len() as u64), "{ty}");
+        let decoded = dequantize(ty, &blocks, &DIMS).unwrap_or_else(|e| panic!("{ty}: {e}"));
+
+        assert_eq!(decoded.len(), expected.len(), "{ty}");
+        for (i, (got, want)) in decoded.iter().zip(&expected).enumerate() {
+            assert_eq!(got.to_bits(), want.to_bits(), "{ty} value {i}: {got} != {want}");
+        }
+    }
+}
+
+#[test]
+fn decoders_reject_truncated_blocks() {
+    for (ty, stem) in vectors() {
+        let blocks = vector(&format!("{stem}.bin"));
+        let truncated = &blocks[..blocks.len() - 1];
+
+        assert!(
+            matches!(
+                dequantize(ty, truncated, &DIMS),
+                Err(DecodeError::UnexpectedEOF { .. })
+            ),
+            "{ty}"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Checks tensor and metadata errors report the file offset of the entry
+//! they concern.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Cursor;
+
+use gguf_core::error::GgufError;
+use gguf_core::file::GgufFile;
+use gguf_core::reader::read_header;
+use gguf_core::types::{GGUFTensor, GGUFValue, GGUFValueType, GgmlType};
+use gguf_core::writer::write_gguf_file;
+use gguf_test_support::TempDir;
+
+/// A GGUF with no metadata and one 4-element F32 tensor `t`, whose entry
+/// starts right after the 24-byte header
+fn one_tensor() -> Vec<u8> {
+    let dir = TempDir::new("core-error-offsets");
+    let tensor = GGUFTensor {
+        name: "t".to_string(),
+        type_id: GgmlType::F32.id(),
+        dims: vec![4],
+        offset: 0,
+        values: vec![0; 16],
+    };
+    let path = dir.join("t.gguf");
+    write_gguf_file(&path, &BTreeMap::new(), &[tensor]).unwrap();
+    fs::read(path).unwrap()
+}
+
+// Field positions within one_tensor(): name length, name, ndims, dim, type, offset
+const ENTRY: usize = 24;
+const TYPE: usize = ENTRY + 8 + 1 + 4 + 8;
+const DATA_OFFSET: usize = TYPE + 4;
+
+fn parse(bytes: &[u8]) -> GgufError {
+    read_header(Cursor::new(bytes), bytes.len() as u64).unwrap_err()
+}
+
+#[test]
+fn tensor_errors_point_at_the_directory_entry() {
+    let file = one_tensor();
+
+    let mut bytes = file.clone();
+    bytes[TYPE..TYPE + 4].copy_from_slice(&GgmlType::Q4_0.id().to_le_bytes());
+    let err = parse(&bytes);
+    assert!(matches!(err, GgufError::InvalidShape { offset, .. } if offset == ENTRY as u64), "{err}");
+
+    let mut bytes = file.clone();
+    bytes[DATA_OFFSET..DATA_OFFSET + 8].copy_from_slice(&4u64.to_le_bytes());
+    let err = parse(&bytes);
+    assert!(
+        matches!(err, GgufError::MisalignedOffset { offset, data_offset: 4, .. } if offset == ENTRY as u64),
+        "{err}"
+    );
+
+    // Errors raised reading the data point at where it would start
+    let mut bytes = file.clone();
+    bytes[TYPE..TYPE + 4].copy_from_slice(&9999u32.to_le_bytes());
+    let gguf = GgufFile::from_reader(Cursor::new(bytes)).unwrap();
+    let err = gguf.tensor_data(&gguf.tensors[0]).unwrap_err();
+    assert!(matches!(err, GgufError::UnknownTensorType { offset: 64, type_id: 9999, .. }), "{err}");
+
+    let mut bytes = file.clone();
+    bytes[DATA_OFFSET..DATA_OFFSET + 8].copy_from_slice(&32u64.to_le_bytes());
+    let err = GgufFile::from_reader(Cursor::new(bytes)).err().unwrap();
+    assert!(
+        matches!(err, GgufError::TensorOutOfBounds { offset: 96, data_offset: 32, .. }),
+        "{err}"
+    );
+}
+
+/// A GGUF v3 header with no tensors and one `general.alignment` entry of
+/// the given type tag and payload
+fn alignment_entry(type_id: u32, value: &[u8]) -> Vec<u8> {
+    let mut bytes = b"GGUF".to_vec();
+    bytes.extend(3u32.to_le_bytes());
+    bytes.extend(0u64.to_le_bytes());
+    bytes.extend(1u64.to_le_bytes());
+    bytes.extend(17u64.to_le_bytes());
+    bytes.extend(b"general.alignment");
+    bytes.extend(type_id.to_le_bytes());
+    bytes.extend(value);
+    bytes
+}
+
+#[test]
+fn metadata_errors_point_at_the_entry() {
+    // The entry's type tag follows the 24-byte header and the 17-byte key
+    let type_offset = 24 + 8 + 17;
+    let err = parse(&alignment_entry(4, &48u32.to_le_bytes()));
+    assert!(matches!(err, GgufError::InvalidAlignment { offset, alignment: 48 } if offset == type_offset), "{err}");
+    let err = parse(&alignment_entry(6, &32f32.to_le_bytes()));
+    assert!(matches!(err, GgufError::InvalidValue { offset, .. } if offset == type_offset), "{err}");
+
+    // On write, the offending array's position in the output
+    let dir = TempDir::new("core-error-offsets-metadata");
+    let metadata = BTreeMap::from([(
+        "a".to_string(),
+        GGUFValue::Array(GGUFValueType::U8, vec![GGUFValue::U8(1), GGUFValue::I8(2)]),
+    )]);
+    let err = write_gguf_file(dir.join("mixed.gguf"), &metadata, &[]).unwrap_err();
+    assert!(matches!(err, GgufError::InvalidValue { offset, .. } if offset == 24 + 8 + 1 + 4), "{err}");
+}
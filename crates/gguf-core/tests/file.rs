@@ -0,0 +1,56 @@
+//! Checks a GGUF read through a seekable stream gives the same header and
+//! tensor bytes as the same file memory-mapped.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Cursor;
+
+use gguf_core::file::GgufFile;
+use gguf_core::types::{GGUFTensor, GGUFValue, GgmlType};
+use gguf_core::writer::{write_gguf_file_with_options, WriteOptions};
+use gguf_test_support::TempDir;
+
+#[test]
+fn reader_source_matches_mmap() {
+    let dir = TempDir::new("core-file-reader");
+    let tensors: Vec<GGUFTensor> = [
+        ("token_embd.weight", GgmlType::F16, vec![8u64, 3]),
+        ("blk.0.attn_q.weight", GgmlType::Q4_0, vec![64, 2]),
+        ("blk.0.attn_norm.weight", GgmlType::F32, vec![5]),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(i, (name, ty, dims))| {
+        let size = ty.tensor_nbytes(&dims).unwrap();
+        GGUFTensor {
+            name: name.to_string(),
+            type_id: ty.id(),
+            dims,
+            offset: 0,
+            values: (0..size).map(|b| (b * 7 + i as u64) as u8).collect(),
+        }
+    })
+    .collect();
+    let metadata = BTreeMap::from([("general.name".to_string(), GGUFValue::String("cursor".to_string()))]);
+    let path = dir.join("model.gguf");
+    write_gguf_file_with_options(&path, &metadata, &tensors, &WriteOptions { version: 3, alignment: 64 }).unwrap();
+
+    let mapped = GgufFile::open(&path).unwrap();
+    for streamed in [
+        GgufFile::from_reader(Cursor::new(fs::read(&path).unwrap())).unwrap(),
+        GgufFile::from_reader(File::open(&path).unwrap()).unwrap(),
+    ] {
+        assert_eq!(streamed.version, mapped.version);
+        assert_eq!(streamed.alignment, mapped.alignment);
+        assert_eq!(streamed.metadata, mapped.metadata);
+        assert_eq!(streamed.tensors, mapped.tensors);
+        assert_eq!(streamed.data_offset(), mapped.data_offset());
+
+        // Read out of directory order so every read has to seek
+        for (info, tensor) in mapped.tensors.iter().zip(&tensors).rev() {
+            let bytes = streamed.tensor_data(info).unwrap();
+            assert!(bytes == mapped.tensor_data(info).unwrap(), "{}", info.name);
+            assert!(bytes[..] == tensor.values[..], "{}", info.name);
+        }
+    }
+}
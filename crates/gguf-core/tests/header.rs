@@ -0,0 +1,96 @@
+//! Checks the header-only reader returns the whole tensor directory without
+//! touching the data section, so truncated files can still be inspected.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use gguf_core::error::GgufError;
+use gguf_core::file::GgufFile;
+use gguf_core::reader::read_gguf_header;
+use gguf_core::types::{GGUFTensor, GGUFValue, GgmlType};
+use gguf_core::writer::write_gguf_file;
+use gguf_test_support::TempDir;
+
+#[test]
+fn truncated_data_section_keeps_the_directory() {
+    let dir = TempDir::new("core-header-truncated");
+    let tensors: Vec<GGUFTensor> = [
+        ("token_embd.weight", GgmlType::Q8_0, vec![64u64, 4]),
+        ("blk.0.ffn_up.weight", GgmlType::Q4_K, vec![256, 2]),
+        ("output_norm.weight", GgmlType::F32, vec![64]),
+    ]
+    .into_iter()
+    .map(|(name, ty, dims)| GGUFTensor {
+        name: name.to_string(),
+        type_id: ty.id(),
+        values: vec![0x5a; ty.tensor_nbytes(&dims).unwrap() as usize],
+        dims,
+        offset: 0,
+    })
+    .collect();
+    let metadata = BTreeMap::from([("general.architecture".to_string(), GGUFValue::String("llama".to_string()))]);
+    let path = dir.join("full.gguf");
+    write_gguf_file(&path, &metadata, &tensors).unwrap();
+
+    let full = read_gguf_header(&path).unwrap();
+    assert_eq!(full.tensors.len(), tensors.len());
+    let bytes = fs::read(&path).unwrap();
+    let data_offset = full.data_offset as usize;
+
+    // Cut inside the second tensor, at the start of the data section and
+    // where the last tensor info ends, before the padding up to the data
+    let infos: usize = tensors.iter().map(|t| 8 + t.name.len() + 4 + 8 * t.dims.len() + 4 + 8).sum();
+    let header_end = 24 + (8 + "general.architecture".len() + 4 + 8 + "llama".len()) + infos;
+    assert!(header_end < data_offset);
+    for (cut, len) in [("partial", data_offset + 100), ("no-data", data_offset), ("unpadded", header_end)] {
+        let truncated = dir.join(format!("{cut}.gguf"));
+        fs::write(&truncated, &bytes[..len]).unwrap();
+
+        let header = read_gguf_header(&truncated).unwrap_or_else(|e| panic!("{cut}: {e}"));
+        assert_eq!(header.metadata, full.metadata, "{cut}");
+        assert_eq!(header.data_offset, full.data_offset, "{cut}");
+        assert_eq!(header.tensors, full.tensors, "{cut}");
+        for (info, tensor) in header.tensors.iter().zip(&tensors) {
+            assert_eq!((&info.name, &info.dims, info.type_id), (&tensor.name, &tensor.dims, tensor.type_id));
+            assert_eq!(info.size, tensor.values.len() as u64, "{}", info.name);
+        }
+
+        // Loading tensor data still needs every tensor to be present
+        let err = GgufFile::open(&truncated).err().unwrap();
+        assert!(matches!(err, GgufError::TensorOutOfBounds { .. }), "{cut}: {err}");
+    }
+}
+
+#[test]
+fn unknown_tensor_type_only_fails_its_data() {
+    let dir = TempDir::new("core-header-unknown-type");
+    let tensors: Vec<GGUFTensor> = ["new", "old"]
+        .into_iter()
+        .map(|name| GGUFTensor {
+            name: name.to_string(),
+            type_id: GgmlType::F32.id(),
+            dims: vec![32],
+            offset: 0,
+            values: vec![0x3c; 128],
+        })
+        .collect();
+    let metadata = BTreeMap::from([("general.name".to_string(), GGUFValue::String("mx".to_string()))]);
+    let path = dir.join("mxfp4.gguf");
+    write_gguf_file(&path, &metadata, &tensors).unwrap();
+
+    // Retype the first tensor as MXFP4 (39), which this reader does not know
+    let mut bytes = fs::read(&path).unwrap();
+    let type_pos = 24 + (8 + "general.name".len() + 4 + 8 + "mx".len()) + (8 + "new".len() + 4 + 8);
+    bytes[type_pos..type_pos + 4].copy_from_slice(&39u32.to_le_bytes());
+    fs::write(&path, &bytes).unwrap();
+
+    let header = read_gguf_header(&path).unwrap();
+    assert_eq!(header.metadata, metadata);
+    assert_eq!((header.tensors[0].type_id, header.tensors[0].ggml_type()), (39, None));
+    assert_eq!(header.tensors[1].ggml_type(), Some(GgmlType::F32));
+
+    let file = GgufFile::open(&path).unwrap();
+    let err = file.tensor_data(&file.tensors[0]).unwrap_err();
+    assert!(matches!(err, GgufError::UnknownTensorType { type_id: 39, .. }), "{err}");
+    assert!(file.tensor_data(&file.tensors[1]).unwrap()[..] == tensors[1].values[..]);
+}
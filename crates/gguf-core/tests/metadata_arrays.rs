@@ -0,0 +1,69 @@
+//! Checks typed metadata arrays, nested and empty ones included, read back
+//! as written, and that an array whose elements disagree with its declared
+//! type is refused on write.
+
+use std::collections::BTreeMap;
+
+use gguf_core::error::GgufError;
+use gguf_core::reader::read_gguf_header;
+use gguf_core::types::{GGUFValue, GGUFValueType};
+use gguf_core::writer::write_gguf_file;
+use gguf_test_support::TempDir;
+
+fn array(elem_type: GGUFValueType, items: Vec<GGUFValue>) -> GGUFValue {
+    GGUFValue::Array(elem_type, items)
+}
+
+#[test]
+fn typed_nested_and_empty_arrays_round_trip() {
+    let dir = TempDir::new("core-arrays");
+    let strings = ["<s>", "</s>", ""].map(|s| GGUFValue::String(s.to_string())).to_vec();
+    let metadata: BTreeMap<String, GGUFValue> = [
+        ("u32", array(GGUFValueType::U32, vec![GGUFValue::U32(1), GGUFValue::U32(u32::MAX)])),
+        ("i8", array(GGUFValueType::I8, vec![GGUFValue::I8(-128), GGUFValue::I8(127)])),
+        ("f64", array(GGUFValueType::F64, vec![GGUFValue::F64(0.25)])),
+        ("bool", array(GGUFValueType::Bool, vec![GGUFValue::Bool(true), GGUFValue::Bool(false)])),
+        ("strings", array(GGUFValueType::String, strings)),
+        ("empty", array(GGUFValueType::F32, vec![])),
+        ("empty_nested", array(GGUFValueType::Array, vec![])),
+        (
+            "nested",
+            array(
+                GGUFValueType::Array,
+                vec![
+                    array(GGUFValueType::U16, vec![GGUFValue::U16(7), GGUFValue::U16(8)]),
+                    array(GGUFValueType::String, vec![GGUFValue::String("merge".to_string())]),
+                    array(GGUFValueType::I64, vec![]),
+                    array(GGUFValueType::Array, vec![array(GGUFValueType::U8, vec![GGUFValue::U8(3)])]),
+                ],
+            ),
+        ),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v))
+    .collect();
+
+    let path = dir.join("arrays.gguf");
+    write_gguf_file(&path, &metadata, &[]).unwrap();
+    assert_eq!(read_gguf_header(&path).unwrap().metadata, metadata);
+}
+
+#[test]
+fn mixed_element_types_are_refused_on_write() {
+    let dir = TempDir::new("core-mixed-arrays");
+    for (key, value) in [
+        ("mixed", array(GGUFValueType::U32, vec![GGUFValue::U32(1), GGUFValue::I32(2)])),
+        (
+            "mixed_nested",
+            array(GGUFValueType::Array, vec![array(GGUFValueType::U8, vec![GGUFValue::U8(1)]), GGUFValue::U8(2)]),
+        ),
+        (
+            "mixed_inner",
+            array(GGUFValueType::Array, vec![array(GGUFValueType::F32, vec![GGUFValue::F64(1.0)])]),
+        ),
+    ] {
+        let metadata = BTreeMap::from([(key.to_string(), value)]);
+        let err = write_gguf_file(dir.join(format!("{key}.gguf")), &metadata, &[]).unwrap_err();
+        assert!(matches!(&err, GgufError::InvalidValue { key: k, .. } if k == key), "{key}: {err}");
+    }
+}
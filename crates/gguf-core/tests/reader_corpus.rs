@@ -0,0 +1,109 @@
+//! Regression corpus for the header reader. Every file in `tests/corpus` is
+//! parsed and its tensors checked against the data section; `valid_*` files must parse and everything else must be rejected
+//! with the error [`rejects_as_expected`] records for it, without panicking
+//! or allocating far beyond the size of the input.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use gguf_core::error::GgufError;
+use gguf_core::reader::read_header;
+
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let now = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(now, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAlloc = PeakAlloc;
+
+/// Whether `err` is the check the malformed corpus file `name` targets, so a
+/// file rejected for another reason (usually running out of input) fails
+fn rejects_as_expected(name: &str, err: &GgufError) -> bool {
+    let limit = |err: &GgufError, prefix: &str| {
+        matches!(err, GgufError::LimitExceeded { what, .. } if what.starts_with(prefix))
+    };
+    match name {
+        "bad_alignment.gguf" => matches!(err, GgufError::InvalidAlignment { .. }),
+        "bad_magic.gguf" => matches!(err, GgufError::BadMagic { .. }),
+        "bad_utf8_key.gguf" => matches!(err, GgufError::BadUtf8 { .. }),
+        "bad_version.gguf" => matches!(err, GgufError::UnsupportedVersion { .. }),
+        "deep_nested_array.gguf" => limit(err, "array nesting"),
+        "dims_overflow.gguf" => matches!(err, GgufError::InvalidShape { .. }),
+        "duplicate_key.gguf" => matches!(err, GgufError::DuplicateKey { .. }),
+        "duplicate_tensor.gguf" => matches!(err, GgufError::DuplicateTensor { .. }),
+        "huge_array_count.gguf" => limit(err, "element count"),
+        "huge_key_len.gguf" => {
+            matches!(err, GgufError::OversizedLength { context, .. } if context == "metadata key")
+        }
+        "huge_metadata_count.gguf" => limit(err, "metadata count"),
+        "huge_ndims.gguf" => limit(err, "dimension count"),
+        "huge_string_len.gguf" => matches!(err, GgufError::OversizedLength { .. }),
+        "huge_tensor_count.gguf" => limit(err, "tensor count"),
+        "misaligned_offset.gguf" => matches!(err, GgufError::MisalignedOffset { .. }),
+        "tensor_out_of_bounds.gguf" => matches!(err, GgufError::TensorOutOfBounds { .. }),
+        "truncated_counts.gguf" => matches!(err, GgufError::TruncatedHeader { .. }),
+        "unknown_array_type.gguf" | "unknown_value_type.gguf" => {
+            matches!(err, GgufError::UnknownValueType { .. })
+        }
+        _ => panic!("{name}: no expected error recorded for this corpus file"),
+    }
+}
+
+#[test]
+fn corpus_never_panics_or_over_allocates() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut entries: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    entries.sort();
+    assert!(!entries.is_empty());
+
+    for path in entries {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let data = fs::read(&path).unwrap();
+
+        let baseline = CURRENT.load(Ordering::SeqCst);
+        PEAK.store(baseline, Ordering::SeqCst);
+        let result = read_header(Cursor::new(&data[..]), data.len() as u64)
+            .and_then(|header| header.check_data_bounds(data.len() as u64).map(|()| header));
+        let peak = PEAK.load(Ordering::SeqCst) - baseline;
+
+        // Parsed values are larger than their encoding, but never by more
+        // than a constant factor plus a small fixed overhead.
+        assert!(
+            peak <= 64 * data.len() + 64 * 1024,
+            "{name}: allocated {peak} bytes for a {}-byte input",
+            data.len()
+        );
+
+        if name.starts_with("valid_") {
+            let header = result.unwrap_or_else(|e| panic!("{name}: {e}"));
+            for tensor in &header.tensors {
+                assert!(header.data_offset + tensor.offset + tensor.size <= data.len() as u64);
+            }
+        } else {
+            match result {
+                Ok(_) => panic!("{name}: expected an error"),
+                Err(e) => assert!(rejects_as_expected(&name, &e), "{name}: rejected for the wrong reason: {e}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Checks `GgufWriter` holds every payload to its declared size and the
+//! directory to its declared length, and that what it writes reads back
+//! through `GgufFile` unchanged.
+
+use std::collections::BTreeMap;
+use std::io::{Cursor, Write};
+
+use gguf_core::error::GgufError;
+use gguf_core::file::GgufFile;
+use gguf_core::types::{align_offset, GGUFValue, GgmlType, TensorInfo};
+use gguf_core::writer::{GgufWriter, WriteOptions};
+use gguf_test_support::TempDir;
+
+fn info(name: &str, ty: GgmlType, dims: Vec<u64>) -> TensorInfo {
+    TensorInfo {
+        name: name.to_string(),
+        type_id: ty.id(),
+        size: ty.tensor_nbytes(&dims).unwrap(),
+        dims,
+        offset: 0,
+    }
+}
+
+/// A writer for `count` 16-byte F32 tensors, header already written
+fn writer(count: usize) -> GgufWriter<Vec<u8>> {
+    let directory = (0..count).map(|i| info(&format!("t{i}"), GgmlType::F32, vec![4])).collect();
+    GgufWriter::new(Vec::new(), &BTreeMap::new(), directory, &WriteOptions::default()).unwrap()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Method {
+    Data,
+    From,
+    With,
+}
+
+/// Writes `len` bytes as the next payload; through `With` the last
+/// `trailing` of them go in a second write, after the first has stopped
+fn write(writer: &mut GgufWriter<Vec<u8>>, method: Method, len: usize, trailing: usize) -> Result<(), GgufError> {
+    let payload = vec![0x11; len];
+    match method {
+        Method::Data => writer.write_tensor_data(&payload),
+        Method::From => writer.write_tensor_from(&payload[..]),
+        Method::With => writer.write_tensor_with(|w| {
+            w.write_all(&payload[..len - trailing])?;
+            w.write_all(&payload[len - trailing..])
+        }),
+    }
+}
+
+#[test]
+fn payloads_must_match_their_declared_size() {
+    // Short, oversized in one write, and the right size followed by more
+    for method in [Method::Data, Method::From, Method::With] {
+        for (len, trailing) in [(15, 0), (10, 0), (17, 0), (4096, 0), (17, 1), (24, 8)] {
+            let err = write(&mut writer(1), method, len, trailing).unwrap_err();
+            assert!(
+                matches!(
+                    &err,
+                    GgufError::TensorSizeMismatch { name, expected: 16, actual }
+                        if name == "t0" && *actual == len as u64
+                ),
+                "{method:?} {len} bytes ({trailing} trailing): {err}"
+            );
+        }
+
+        let mut exact = writer(1);
+        write(&mut exact, method, 16, 0).unwrap();
+        assert!(exact.next_tensor().is_none());
+        exact.finish().unwrap();
+    }
+}
+
+#[test]
+fn payload_count_must_match_the_directory() {
+    for method in [Method::Data, Method::From, Method::With] {
+        let mut short = writer(2);
+        write(&mut short, method, 16, 0).unwrap();
+        assert_eq!(short.next_tensor().map(|t| t.name.as_str()), Some("t1"));
+        let err = short.finish().unwrap_err();
+        assert!(matches!(err, GgufError::TensorCountMismatch { expected: 2, written: 1 }), "{method:?}: {err}");
+
+        let mut extra = writer(2);
+        for _ in 0..2 {
+            write(&mut extra, method, 16, 0).unwrap();
+        }
+        let err = write(&mut extra, method, 16, 0).unwrap_err();
+        assert!(matches!(err, GgufError::TensorCountMismatch { expected: 2, written: 3 }), "{method:?}: {err}");
+    }
+
+    // An empty directory needs no payloads at all
+    writer(0).finish().unwrap();
+}
+
+/// Writes each payload through a different method, the last in small pieces
+fn fill<W: Write>(writer: &mut GgufWriter<W>, payloads: &[Vec<u8>]) {
+    writer.write_tensor_data(&payloads[0]).unwrap();
+    writer.write_tensor_from(&payloads[1][..]).unwrap();
+    writer.write_tensor_with(|w| w.write_all(&payloads[2])).unwrap();
+    writer
+        .write_tensor_with(|w| payloads[3].chunks(5).try_for_each(|c| w.write_all(c)))
+        .unwrap();
+}
+
+#[test]
+fn written_files_read_back_unchanged() {
+    let dir = TempDir::new("core-streaming-writer");
+    let metadata = BTreeMap::from([
+        ("general.architecture".to_string(), GGUFValue::String("llama".to_string())),
+        ("llama.block_count".to_string(), GGUFValue::U32(1)),
+    ]);
+    let directory = vec![
+        info("token_embd.weight", GgmlType::Q4_0, vec![64, 3]),
+        info("blk.0.attn_norm.weight", GgmlType::F32, vec![5]),
+        info("blk.0.ffn_up.weight", GgmlType::Q6_K, vec![256, 2]),
+        info("output.weight", GgmlType::BF16, vec![7, 3]),
+    ];
+    let payloads: Vec<Vec<u8>> = directory
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (0..t.size).map(|b| (b * 31 + i as u64) as u8).collect())
+        .collect();
+
+    let path = dir.join("streamed.gguf");
+    let mut on_disk = GgufWriter::create(&path, &metadata, directory.clone(), &WriteOptions::default()).unwrap();
+    fill(&mut on_disk, &payloads);
+    on_disk.finish().unwrap();
+
+    let mut in_memory = GgufWriter::new(Vec::new(), &metadata, directory.clone(), &WriteOptions::default()).unwrap();
+    fill(&mut in_memory, &payloads);
+    let bytes = in_memory.finish().unwrap();
+    assert!(bytes == std::fs::read(&path).unwrap());
+
+    // Offsets are packed in directory order at the default alignment
+    let mut expected = directory;
+    let mut offset = 0;
+    for t in &mut expected {
+        t.offset = offset;
+        offset = align_offset(offset + t.size, 32);
+    }
+
+    for file in [GgufFile::open(&path).unwrap(), GgufFile::from_reader(Cursor::new(bytes)).unwrap()] {
+        assert_eq!(file.version, 3);
+        assert_eq!(file.metadata, metadata);
+        assert_eq!(file.tensors, expected);
+        for (t, payload) in file.tensors.iter().zip(&payloads) {
+            assert!(file.tensor_data(t).unwrap()[..] == payload[..], "{}", t.name);
+        }
+    }
+}
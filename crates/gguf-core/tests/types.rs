@@ -0,0 +1,102 @@
+//! Checks the ggml type table against the block layouts in llama.cpp's
+//! ggml-common.h, and that tensor sizes refuse partial blocks and overflow.
+
+use gguf_core::types::GgmlType;
+
+/// Elements per block and bytes per block, the bytes spelled out field by
+/// field from each block struct (f16 scales are 2 bytes, QK_K is 256)
+const LAYOUTS: &[(GgmlType, u64, u64)] = &[
+    (GgmlType::F32, 1, 4),
+    (GgmlType::F16, 1, 2),
+    (GgmlType::BF16, 1, 2),
+    (GgmlType::F64, 1, 8),
+    (GgmlType::I8, 1, 1),
+    (GgmlType::I16, 1, 2),
+    (GgmlType::I32, 1, 4),
+    (GgmlType::I64, 1, 8),
+    // d, qs[16]
+    (GgmlType::Q4_0, 32, 2 + 16),
+    // d, m, qs[16]
+    (GgmlType::Q4_1, 32, 2 + 2 + 16),
+    // d, qh[4], qs[16]
+    (GgmlType::Q5_0, 32, 2 + 4 + 16),
+    // d, m, qh[4], qs[16]
+    (GgmlType::Q5_1, 32, 2 + 2 + 4 + 16),
+    // d, qs[32]
+    (GgmlType::Q8_0, 32, 2 + 32),
+    // d, s, qs[32]
+    (GgmlType::Q8_1, 32, 2 + 2 + 32),
+    // scales[16], qs[64], d, dmin
+    (GgmlType::Q2_K, 256, 16 + 64 + 2 + 2),
+    // hmask[32], qs[64], scales[12], d
+    (GgmlType::Q3_K, 256, 32 + 64 + 12 + 2),
+    // d, dmin, scales[12], qs[128]
+    (GgmlType::Q4_K, 256, 2 + 2 + 12 + 128),
+    // d, dmin, scales[12], qh[32], qs[128]
+    (GgmlType::Q5_K, 256, 2 + 2 + 12 + 32 + 128),
+    // ql[128], qh[64], scales[16], d
+    (GgmlType::Q6_K, 256, 128 + 64 + 16 + 2),
+    // f32 d, qs[256], i16 bsums[16]
+    (GgmlType::Q8_K, 256, 4 + 256 + 32),
+    // d, u16 qs[32]
+    (GgmlType::IQ2_XXS, 256, 2 + 64),
+    // d, u16 qs[32], scales[8]
+    (GgmlType::IQ2_XS, 256, 2 + 64 + 8),
+    // d, qs[64] (grid indices then signs), qh[8], scales[8]
+    (GgmlType::IQ2_S, 256, 2 + 64 + 8 + 8),
+    // d, qs[96]
+    (GgmlType::IQ3_XXS, 256, 2 + 96),
+    // d, qs[64], qh[8], signs[32], scales[4]
+    (GgmlType::IQ3_S, 256, 2 + 64 + 8 + 32 + 4),
+    // d, qs[32], u16 qh[8]
+    (GgmlType::IQ1_S, 256, 2 + 32 + 16),
+    // qs[32], qh[16], scales[8]
+    (GgmlType::IQ1_M, 256, 32 + 16 + 8),
+    // d, qs[16]
+    (GgmlType::IQ4_NL, 32, 2 + 16),
+    // d, u16 scales_h, scales_l[4], qs[128]
+    (GgmlType::IQ4_XS, 256, 2 + 2 + 4 + 128),
+    // qs[48], qh[4], d
+    (GgmlType::TQ1_0, 256, 48 + 4 + 2),
+    // qs[64], d
+    (GgmlType::TQ2_0, 256, 64 + 2),
+];
+
+#[test]
+fn block_sizes_match_ggml() {
+    assert_eq!(LAYOUTS.len(), GgmlType::ALL.len());
+    for &(ty, block_size, type_size) in LAYOUTS {
+        assert_eq!((ty.block_size(), ty.type_size()), (block_size, type_size), "{ty}");
+        assert_eq!(ty.is_quantized(), block_size > 1, "{ty}");
+
+        // Three rows of four blocks, and a scalar as one element
+        assert_eq!(ty.tensor_nbytes(&[4 * block_size, 3]), Some(12 * type_size), "{ty}");
+        assert_eq!(ty.tensor_nbytes(&[block_size]), Some(type_size), "{ty}");
+        assert_eq!(ty.tensor_nbytes(&[]), (block_size == 1).then_some(type_size), "{ty}");
+        assert_eq!(ty.tensor_nbytes(&[0, 7]), Some(0), "{ty}");
+    }
+}
+
+#[test]
+fn partial_blocks_have_no_size() {
+    for ty in GgmlType::ALL.into_iter().filter(|ty| ty.is_quantized()) {
+        let block = ty.block_size();
+        for row in [1, block - 1, block + 1, 3 * block / 2] {
+            assert_eq!(ty.tensor_nbytes(&[row, 2]), None, "{ty} with a row of {row}");
+        }
+    }
+    // Only the row length has to be a whole number of blocks
+    assert_eq!(GgmlType::Q4_K.tensor_nbytes(&[256, 3, 5]), Some(15 * 144));
+}
+
+#[test]
+fn overflowing_sizes_have_no_size() {
+    assert_eq!(GgmlType::F32.tensor_nbytes(&[u64::MAX]), None);
+    assert_eq!(GgmlType::F32.tensor_nbytes(&[1 << 62]), None);
+    assert_eq!(GgmlType::F32.tensor_nbytes(&[1 << 61]), Some(1 << 63));
+    assert_eq!(GgmlType::Q8_0.tensor_nbytes(&[32, 1 << 32, 1 << 31]), None);
+    assert_eq!(GgmlType::I8.tensor_nbytes(&[u64::MAX, 1]), Some(u64::MAX));
+    assert_eq!(GgmlType::I8.tensor_nbytes(&[u64::MAX, 2]), None);
+    // A zero dimension after an overflowing one does not rescue it
+    assert_eq!(GgmlType::F64.tensor_nbytes(&[1 << 62, 1 << 62, 0]), None);
+}
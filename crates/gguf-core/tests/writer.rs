@@ -0,0 +1,69 @@
+//! Checks the container layout the writer emits: v2 and v3 headers, tensor
+//! data padded to the configured alignment and `general.alignment` written
+//! only when it is not the default.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use gguf_core::reader::read_gguf_header;
+use gguf_core::types::{GGUFTensor, GGUFValue, GgmlType};
+use gguf_core::writer::{write_gguf_file_with_options, WriteOptions};
+use gguf_test_support::TempDir;
+
+/// F32 tensors whose sizes (12 and 40 bytes) are not a multiple of any alignment
+fn tensors() -> Vec<GGUFTensor> {
+    [("a", vec![3u64]), ("b", vec![5, 2])]
+        .into_iter()
+        .map(|(name, dims)| {
+            let n: u64 = dims.iter().product();
+            GGUFTensor {
+                name: name.to_string(),
+                type_id: GgmlType::F32.id(),
+                dims,
+                offset: 0,
+                values: (0..n).flat_map(|i| (i as f32 + 0.5).to_le_bytes()).collect(),
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn versions_and_alignment_round_trip_through_the_header() {
+    let dir = TempDir::new("core-writer-layout");
+    let tensors = tensors();
+
+    // A stale general.alignment in the metadata is replaced by the option
+    let mut metadata = BTreeMap::new();
+    metadata.insert("general.name".to_string(), GGUFValue::String("layout".to_string()));
+    metadata.insert("general.alignment".to_string(), GGUFValue::U32(128));
+
+    for (version, alignment) in [(2, 32), (3, 32), (2, 64), (3, 256)] {
+        let path = dir.join(format!("v{version}-{alignment}.gguf"));
+        write_gguf_file_with_options(&path, &metadata, &tensors, &WriteOptions { version, alignment }).unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(&bytes[..4], b"GGUF");
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), version);
+
+        let header = read_gguf_header(&path).unwrap();
+        assert_eq!((header.version, header.alignment), (version, alignment));
+        assert_eq!(header.metadata.get("general.name"), metadata.get("general.name"));
+        let expected_alignment = (alignment != 32).then_some(GGUFValue::U32(alignment as u32));
+        assert_eq!(header.metadata.get("general.alignment"), expected_alignment.as_ref(), "v{version} {alignment}");
+
+        // Each tensor starts on an alignment boundary, and the gap after it is zeros
+        assert_eq!(header.data_offset % alignment, 0);
+        assert_eq!(bytes.len() as u64 % alignment, 0);
+        let mut end = 0;
+        for (info, tensor) in header.tensors.iter().zip(&tensors) {
+            assert_eq!(info.offset % alignment, 0, "{} in v{version} {alignment}", info.name);
+            assert!(info.offset >= end);
+            let start = (header.data_offset + info.offset) as usize;
+            assert!(bytes[(header.data_offset + end) as usize..start].iter().all(|&b| b == 0));
+            assert!(bytes[start..start + tensor.values.len()] == tensor.values[..]);
+            end = info.offset + info.size;
+        }
+        // The 12-byte first tensor is padded to one full alignment unit
+        assert_eq!(header.tensors[1].offset, alignment);
+    }
+}
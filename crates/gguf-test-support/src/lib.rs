@@ -0,0 +1,96 @@
+//! Fixtures shared by the integration tests of gguf-writer and quantize-rs:
+//! a self-removing temp directory and writers for small safetensors and
+//! F32 GGUF inputs.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use gguf_core::types::{GgmlType, TensorInfo};
+use gguf_core::writer::{GgufWriter, WriteOptions};
+use half::{bf16, f16};
+
+/// A fresh directory under the system temp dir, removed when dropped, so a
+/// failing test does not leave it behind
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `gguf-test-{tag}-{pid}`; `tag` must be unique among the tests of a
+    /// binary, which run in parallel
+    pub fn new(tag: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("gguf-test-{tag}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Encodes values in a safetensors dtype: `BF16`, `F16` or `F32`
+pub fn encode(dtype: &str, values: &[f32]) -> Vec<u8> {
+    match dtype {
+        "BF16" => values.iter().flat_map(|&v| bf16::from_f32(v).to_le_bytes()).collect(),
+        "F16" => values.iter().flat_map(|&v| f16::from_f32(v).to_le_bytes()).collect(),
+        _ => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+    }
+}
+
+/// The values [`encode`] stores for `values`, rounded to `dtype`
+pub fn rounded(dtype: &str, values: &[f32]) -> Vec<f32> {
+    match dtype {
+        "BF16" => values.iter().map(|&v| bf16::from_f32(v).to_f32()).collect(),
+        "F16" => values.iter().map(|&v| f16::from_f32(v).to_f32()).collect(),
+        _ => values.to_vec(),
+    }
+}
+
+/// Writes a safetensors file of `(name, dtype, shape, bytes)` tensors
+pub fn write_safetensors(path: &Path, tensors: &[(&str, &str, &[u64], Vec<u8>)]) {
+    let mut header = Vec::new();
+    let mut data: Vec<u8> = Vec::new();
+    for (name, dtype, shape, bytes) in tensors {
+        header.push(format!(
+            r#""{name}":{{"dtype":"{dtype}","shape":{shape:?},"data_offsets":[{},{}]}}"#,
+            data.len(),
+            data.len() + bytes.len()
+        ));
+        data.extend(bytes);
+    }
+    let header = format!("{{{}}}", header.join(","));
+    let mut file = (header.len() as u64).to_le_bytes().to_vec();
+    file.extend(header.as_bytes());
+    file.extend(data);
+    fs::write(path, file).unwrap();
+}
+
+/// Writes an F32 GGUF of `(name, dims, values)` tensors, dims innermost first
+pub fn write_f32_gguf(path: &Path, tensors: &[(&str, Vec<u64>, Vec<f32>)]) {
+    let directory = tensors
+        .iter()
+        .map(|(name, dims, values)| TensorInfo {
+            name: name.to_string(),
+            type_id: GgmlType::F32.id(),
+            dims: dims.clone(),
+            offset: 0,
+            size: values.len() as u64 * 4,
+        })
+        .collect();
+    let mut writer = GgufWriter::create(path, &Default::default(), directory, &WriteOptions::default()).unwrap();
+    for (_, _, values) in tensors {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        writer.write_tensor_data(&bytes).unwrap();
+    }
+    writer.finish().unwrap();
+}
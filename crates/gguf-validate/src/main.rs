@@ -1,15 +1,23 @@
 use std::env;
 use std::io;
 
-use gguf_core::decoder::{try_decode_q4_0, try_decode_q5_1, try_decode_f32, DecodeError};
-use gguf_core::reader::read_gguf_file;
+use gguf_core::decoder::{dequantize, DecodeError};
+use gguf_core::error::GgufError;
+use gguf_core::file::GgufFile;
 use gguf_core::types::GGUFValue;
 
 fn main() -> io::Result<()> {
     let path = env::args().nth(1).expect("Usage: gguf-validate <file.gguf>");
     println!("🧪 Validating GGUF file: {path}\n");
 
-    let (metadata, tensors) = read_gguf_file(&path)?;
+    let gguf = match GgufFile::open(&path) {
+        Ok(gguf) => gguf,
+        Err(e) => {
+            println!("❌ Invalid GGUF file: {e}");
+            std::process::exit(1);
+        }
+    };
+    let (metadata, tensors) = (&gguf.metadata, &gguf.tensors);
 
     let format = metadata
         .get("quantization_format")
@@ -26,33 +34,29 @@ fn main() -> io::Result<()> {
 
     for tensor in tensors {
         println!("→ tensor '{}':", tensor.name);
-        println!("   type_id: {}", tensor.type_id);
+        let Some(ggml_type) = tensor.ggml_type() else {
+            println!("   type: unknown ({})", tensor.type_id);
+            println!("   dims: {:?}", tensor.dims);
+            println!("   ⚠ Unknown tensor type — skipping validation.\n");
+            continue;
+        };
+        println!("   type: {} ({})", ggml_type, tensor.type_id);
         println!("   dims: {:?}", tensor.dims);
 
-        let result = match tensor.type_id {
-            0 => try_decode_f32(&tensor.values, &tensor.dims),
-            100 => try_decode_q4_0(&tensor.values, &tensor.dims),
-            101 => try_decode_q5_1(&tensor.values, &tensor.dims),
-            _ => {
+        let values = gguf.tensor_data(tensor)?;
+        match dequantize(ggml_type, &values, &tensor.dims) {
+            Err(DecodeError::Unsupported(_)) => {
                 println!("   ⚠ Unsupported tensor type — skipping validation.\n");
-                continue;
             }
-        };
-
-        match result {
             Ok(decoded) => {
                 println!("   ✅ Decoded successfully ({} floats)\n", decoded.len());
             }
-            Err(e) => {
-                println!(
-                    "   ❌ Decode error: {}\n",
-                    match e {
-                        DecodeError::InvalidScale => "InvalidScale".to_string(),
-                        DecodeError::InvalidBlock => "InvalidBlock".to_string(),
-                        DecodeError::UnexpectedEOF => "UnexpectedEOF".to_string(),
-                        DecodeError::Io(ioe) => format!("Io({})", ioe),
-                    }
-                );
+            Err(source) => {
+                let e = GgufError::Decode {
+                    tensor: tensor.name.clone(),
+                    source,
+                };
+                println!("   ❌ {e}\n");
                 errors += 1;
             }
         }
@@ -2,70 +2,88 @@ use std::env;
 use std::fs;
 use std::io;
 
-use gguf_core::reader::read_gguf_file;
+use gguf_core::reader::read_gguf_header;
 use gguf_core::types::GGUFValue;
 
 fn main() -> io::Result<()> {
     let path = env::args().nth(1).expect("Usage: gguf-inspect <file.gguf>");
     println!("magic: GGUF");
 
-    let (metadata, tensors) = read_gguf_file(&path)?;
+    let gguf = read_gguf_header(&path)?;
+    let (metadata, tensors) = (&gguf.metadata, &gguf.tensors);
     let file_size = fs::metadata(&path)?.len();
 
     // Print general metadata
-    let version = metadata
-        .get("gguf_version")
-        .and_then(|v| match v {
-            GGUFValue::String(s) => Some(s.clone()),
-            GGUFValue::U64(n) => Some(n.to_string()),
-            _ => None,
-        })
-        .unwrap_or_else(|| "unknown".to_string());
-
-    println!("version: {}", version);
+    println!("version: {}", gguf.version);
     println!("tensor count: {}", tensors.len());
     println!("metadata count: {}", metadata.len());
 
     for (i, (key, value)) in metadata.iter().enumerate() {
-        println!("  {}. {} => {:?}", i, key, value);
+        match value {
+            // Tokenizer arrays hold tens of thousands of entries
+            GGUFValue::Array(elem_type, items) if items.len() > 8 => println!(
+                "  {}. {} => Array<{:?}>[{}] {:?} ...",
+                i,
+                key,
+                elem_type,
+                items.len(),
+                &items[..8]
+            ),
+            _ => println!("  {}. {} => {:?}", i, key, value),
+        }
     }
 
     // Count tensor types
-    use std::collections::HashMap;
-    let mut type_counts: HashMap<u32, usize> = HashMap::new();
+    use std::collections::BTreeMap;
+    // Per type: tensor count, bytes and elements, listed by type name
+    let mut type_counts: BTreeMap<String, (usize, u64, u64)> = BTreeMap::new();
     let mut top_tensors = Vec::new();
     let mut total_memory: u64 = 0;
 
-    for t in &tensors {
-        let num_elements: u64 = t.dims.iter().product();
-        let per_elem_size = match t.type_id {
-            0 => 4,     // f32
-            100 => 1,   // Q4_0 (approx)
-            101 => 2,   // Q5_1 (approx)
-            _ => 4,     // fallback
-        };
+    for t in tensors {
+        let type_name = t
+            .ggml_type()
+            .map(|ty| ty.name().to_string())
+            .unwrap_or_else(|| format!("unknown({})", t.type_id));
 
-        let tensor_size = num_elements * per_elem_size;
-        total_memory += tensor_size;
+        total_memory += t.size;
 
-        *type_counts.entry(t.type_id).or_insert(0) += 1;
+        let entry = type_counts.entry(type_name.clone()).or_insert((0, 0, 0));
+        entry.0 += 1;
+        entry.1 += t.size;
+        entry.2 += t.n_elements();
 
         // Save top tensor info for summary
-        top_tensors.push((tensor_size, t.name.clone(), t.dims.clone(), t.type_id));
+        if t.ggml_type().is_some() {
+            top_tensors.push((t.size, t.name.clone(), t.dims.clone(), type_name));
+        }
     }
 
-    top_tensors.sort_by(|a, b| b.0.cmp(&a.0));
+    top_tensors.sort_by_key(|t| std::cmp::Reverse(t.0));
     let top_display = top_tensors.iter().take(6);
 
     println!("\n--- Tensor Table Overview ---");
 
-    for (type_id, count) in &type_counts {
-        println!("Types: {} ({})", type_id, count);
+    for (type_name, (count, bytes, elements)) in &type_counts {
+        // Tensors of a type this build does not know have no size to report
+        if type_name.starts_with("unknown(") {
+            println!("Types: {} ({}) | size unknown", type_name, count);
+            continue;
+        }
+        let bpw = if *elements > 0 {
+            *bytes as f64 * 8.0 / *elements as f64
+        } else {
+            0.0
+        };
+        println!(
+            "Types: {} ({}) | {} bytes | {:.2} bits/weight",
+            type_name, count, bytes, bpw
+        );
     }
 
     println!("\nTop tensors by size:");
-    for (_, name, dims, type_id) in top_display {
-        println!("  - {:<40} | {:?} | type_id: {}", name, dims, type_id);
+    for (size, name, dims, type_name) in top_display {
+        println!("  - {:<40} | {:?} | {} | {} bytes", name, dims, type_name, size);
     }
 
     println!(
@@ -73,7 +91,7 @@ fn main() -> io::Result<()> {
         file_size as f64 / 1e9
     );
     println!(
-        "Total tensor memory: {:.2} GB (approx)",
+        "Total tensor memory: {:.2} GB",
         total_memory as f64 / 1e9
     );
 
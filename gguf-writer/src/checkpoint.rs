@@ -0,0 +1,221 @@
+//! Memory-mapped safetensors checkpoints: a single file, the shards listed
+//! by a `model.safetensors.index.json`, or a model directory holding either.
+//!
+//! Tensors are listed shard by shard in data order, so a checkpoint always
+//! converts to the same GGUF layout. A tensor stored in two shards, or one
+//! the index and the shards disagree about, is an error rather than being
+//! silently dropped.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use gguf_core::types::GgmlType;
+use memmap2::Mmap;
+use safetensors::tensor::{Dtype, SafeTensors};
+use serde::Deserialize;
+
+/// One tensor of a checkpoint
+#[derive(Debug, Clone)]
+pub struct CheckpointTensor {
+    pub name: String,
+    pub dtype: Dtype,
+    /// Row-major safetensors shape, outermost dimension first
+    pub shape: Vec<u64>,
+    shard: usize,
+    start: usize,
+    end: usize,
+}
+
+impl CheckpointTensor {
+    /// GGUF dimensions: the shape reversed, innermost dimension first
+    pub fn dims(&self) -> Vec<u64> {
+        self.shape.iter().rev().copied().collect()
+    }
+
+    /// The ggml type with the same in-memory layout. Only float dtypes have
+    /// one; any other dtype is an error naming the tensor.
+    pub fn ggml_type(&self) -> io::Result<GgmlType> {
+        match self.dtype {
+            Dtype::F32 => Ok(GgmlType::F32),
+            Dtype::F16 => Ok(GgmlType::F16),
+            Dtype::BF16 => Ok(GgmlType::BF16),
+            dtype => Err(invalid(format!(
+                "tensor {} has dtype {dtype:?}; only F32, F16 and BF16 tensors can be converted",
+                self.name
+            ))),
+        }
+    }
+
+    pub fn nbytes(&self) -> u64 {
+        (self.end - self.start) as u64
+    }
+}
+
+pub struct Checkpoint {
+    shards: Vec<Mmap>,
+    tensors: Vec<CheckpointTensor>,
+}
+
+/// Index file Hugging Face writes next to a sharded checkpoint
+pub const INDEX_FILE: &str = "model.safetensors.index.json";
+
+#[derive(Deserialize)]
+struct Index {
+    weight_map: BTreeMap<String, String>,
+}
+
+impl Checkpoint {
+    /// Opens a `.safetensors` file, every shard of a `.json` index, or a
+    /// model directory. A directory is read through its [`INDEX_FILE`] when
+    /// it has one, and otherwise as all of its `.safetensors` files.
+    pub fn open(path: &Path) -> io::Result<Checkpoint> {
+        if path.is_dir() {
+            let index = path.join(INDEX_FILE);
+            if index.is_file() {
+                Checkpoint::from_index(&index)
+            } else {
+                Checkpoint::from_shards(&safetensors_files(path)?)
+            }
+        } else if path.extension().is_some_and(|e| e == "json") {
+            Checkpoint::from_index(path)
+        } else {
+            Checkpoint::from_shards(&[path.to_path_buf()])
+        }
+    }
+
+    /// Maps every shard, refusing tensors stored in more than one
+    fn from_shards(paths: &[PathBuf]) -> io::Result<Checkpoint> {
+        let mut checkpoint = Checkpoint {
+            shards: Vec::new(),
+            tensors: Vec::new(),
+        };
+        for path in paths {
+            checkpoint.add_shard(path)?;
+        }
+
+        let mut seen = HashMap::new();
+        for t in &checkpoint.tensors {
+            if let Some(first) = seen.insert(t.name.as_str(), t.shard) {
+                return Err(invalid(format!(
+                    "{} is stored in both {} and {}",
+                    t.name,
+                    paths[first].display(),
+                    paths[t.shard].display()
+                )));
+            }
+        }
+        Ok(checkpoint)
+    }
+
+    fn from_index(path: &Path) -> io::Result<Checkpoint> {
+        let index: Index = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| invalid(format!("{}: {e}", path.display())))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        let files: Vec<&String> = index.weight_map.values().collect::<BTreeSet<_>>().into_iter().collect();
+        let paths: Vec<PathBuf> = files.iter().map(|file| dir.join(file)).collect();
+        let checkpoint = Checkpoint::from_shards(&paths)?;
+
+        // Every tensor must be where the index says, and nowhere else
+        for t in &checkpoint.tensors {
+            match index.weight_map.get(&t.name) {
+                Some(file) if file == files[t.shard] => {}
+                Some(file) => {
+                    return Err(invalid(format!(
+                        "{} lists {} in {file}, but it is stored in {}",
+                        path.display(),
+                        t.name,
+                        files[t.shard]
+                    )))
+                }
+                None => {
+                    return Err(invalid(format!(
+                        "{} holds {}, which {} does not list",
+                        files[t.shard],
+                        t.name,
+                        path.display()
+                    )))
+                }
+            }
+        }
+
+        let loaded: HashSet<&str> = checkpoint.tensors.iter().map(|t| t.name.as_str()).collect();
+        if let Some(name) = index.weight_map.keys().find(|name| !loaded.contains(name.as_str())) {
+            return Err(invalid(format!(
+                "{} lists {name} in {}, but the shard has no such tensor",
+                path.display(),
+                index.weight_map[name]
+            )));
+        }
+        Ok(checkpoint)
+    }
+
+    /// Maps a safetensors file and adds its tensors
+    fn add_shard(&mut self, path: &Path) -> io::Result<()> {
+        let file = File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+        // SAFETY: the checkpoint is only read, and is expected not to change
+        // while converting
+        let mmap = unsafe { Mmap::map(&file)? };
+        let (header_len, metadata) =
+            SafeTensors::read_metadata(&mmap).map_err(|e| invalid(format!("{}: {e}", path.display())))?;
+        let data_start = 8 + header_len;
+
+        let shard = self.shards.len();
+        let mut tensors: Vec<_> = metadata
+            .tensors()
+            .into_iter()
+            .map(|(name, info)| CheckpointTensor {
+                name,
+                dtype: info.dtype,
+                shape: info.shape.iter().map(|&d| d as u64).collect(),
+                shard,
+                start: data_start + info.data_offsets.0,
+                end: data_start + info.data_offsets.1,
+            })
+            .collect();
+        tensors.sort_by_key(|t| t.start);
+
+        self.shards.push(mmap);
+        self.tensors.extend(tensors);
+        Ok(())
+    }
+
+    /// Number of safetensors files the tensors come from
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn tensors(&self) -> &[CheckpointTensor] {
+        &self.tensors
+    }
+
+    /// Raw little-endian bytes of a tensor
+    pub fn data(&self, tensor: &CheckpointTensor) -> &[u8] {
+        &self.shards[tensor.shard][tensor.start..tensor.end]
+    }
+}
+
+/// The `.safetensors` files of a directory without an index, by name
+fn safetensors_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|e| e == "safetensors") {
+            files.push(path);
+        }
+    }
+    if files.is_empty() {
+        return Err(invalid(format!(
+            "{} has no {INDEX_FILE} or .safetensors files",
+            dir.display()
+        )));
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -1,12 +1,23 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::fs::File;
-use std::io::{self, BufReader};
+use std::io::{self, BufReader, Read};
 use std::path::Path;
-use std::collections::VecDeque;
 
 use serde_json::Value;
 use gguf_core::types::GGUFValue;
 
-pub fn convert_config_to_metadata<P: AsRef<Path>>(config_path: P) -> io::Result<Vec<(String, GGUFValue)>> {
+use crate::tensor_map::gguf_architecture;
+
+/// Name of the per-dimension RoPE frequency factors llama.cpp generates for
+/// `llama3` RoPE scaling
+pub const ROPE_FREQS: &str = "rope_freqs.weight";
+
+/// Config fields promoted to metadata. A `rope_scaling` type llama.cpp has
+/// no equivalent for is an error unless `ignore_rope_scaling` is set.
+pub fn convert_config_to_metadata<P: AsRef<Path>>(
+    config_path: P,
+    ignore_rope_scaling: bool,
+) -> io::Result<Vec<(String, GGUFValue)>> {
     let file = File::open(config_path)?;
     let reader = BufReader::new(file);
     let json: Value = serde_json::from_reader(reader)?;
@@ -20,16 +31,6 @@ pub fn convert_config_to_metadata<P: AsRef<Path>>(config_path: P) -> io::Result<
         }
     };
 
-    // === Architecture ===
-    promote("hidden_size", to_u64);
-    promote("intermediate_size", to_u64);
-    promote("num_attention_heads", to_u64);
-    promote("num_hidden_layers", to_u64);
-    promote("layer_norm_eps", to_f64);
-    promote("tie_word_embeddings", to_bool);
-    promote("kv_cache", to_bool);
-    promote("rotary_dim", to_u64);
-
     // === Tokenizer ===
     promote("vocab_size", to_u64);
     promote("pad_token_id", to_u64);
@@ -47,13 +48,207 @@ pub fn convert_config_to_metadata<P: AsRef<Path>>(config_path: P) -> io::Result<
     promote("training_steps", to_u64);
     promote("learning_rate", to_f64);
 
+    // === Architecture ===
+    out.extend(architecture_metadata(&json, ignore_rope_scaling)?);
+
     Ok(out.into())
 }
 
+/// `general.architecture` and the `{arch}.*` hyperparameters llama.cpp
+/// loads a model by, stored as U32 and F32 like its converter writes them.
+///
+/// The architecture comes from `architectures` for the models gguf-writer
+/// has tensor names for, and from `model_type` otherwise. GPT-2 style
+/// configs name the same hyperparameters differently (`n_embd`, `n_layer`).
+fn architecture_metadata(json: &Value, ignore_rope_scaling: bool) -> io::Result<Vec<(String, GGUFValue)>> {
+    let arch = json["architectures"]
+        .get(0)
+        .and_then(Value::as_str)
+        .and_then(gguf_architecture)
+        .map(str::to_string)
+        .or_else(|| json["model_type"].as_str().map(str::to_string));
+    let Some(arch) = arch else {
+        return Ok(Vec::new());
+    };
+
+    let mut out = vec![("general.architecture".to_string(), GGUFValue::String(arch.clone()))];
+    let first = |keys: &[&str]| keys.iter().find_map(|k| json.get(*k).filter(|v| !v.is_null()));
+    let mut put = |key: &str, value: Option<GGUFValue>| {
+        if let Some(v) = value {
+            out.push((format!("{arch}.{key}"), v));
+        }
+    };
+
+    let n_embd = first(&["hidden_size", "n_embd"]).and_then(Value::as_u64);
+    put("context_length", first(&["max_position_embeddings", "n_positions", "n_ctx"]).and_then(to_u32));
+    put("embedding_length", n_embd.and_then(u32_value));
+    put("block_count", first(&["num_hidden_layers", "n_layer"]).and_then(to_u32));
+    put(
+        "feed_forward_length",
+        match first(&["intermediate_size", "n_inner"]) {
+            Some(v) => to_u32(v),
+            // GPT-2 leaves n_inner unset for the default of 4 × n_embd
+            None if arch == "gpt2" => n_embd.and_then(|n| u32_value(4 * n)),
+            None => None,
+        },
+    );
+    put("attention.head_count", first(&["num_attention_heads", "n_head"]).and_then(to_u32));
+    put("attention.head_count_kv", first(&["num_key_value_heads"]).and_then(to_u32));
+    put("attention.key_length", first(&["head_dim"]).and_then(to_u32));
+    put("attention.value_length", first(&["head_dim"]).and_then(to_u32));
+    put("attention.layer_norm_rms_epsilon", first(&["rms_norm_eps"]).and_then(to_f32));
+    put("attention.layer_norm_epsilon", first(&["layer_norm_eps", "layer_norm_epsilon"]).and_then(to_f32));
+    put("rope.freq_base", first(&["rope_theta"]).and_then(to_f32));
+    put("rope.dimension_count", first(&["rotary_dim"]).and_then(to_u32));
+
+    if let Some(scaling) = first(&["rope_scaling"]) {
+        match rope_scaling_type(scaling) {
+            // llama3 scaling is carried by the rope_freqs tensor instead
+            "default" | "llama3" => {}
+            kind @ ("linear" | "yarn") => {
+                put("rope.scaling.type", Some(GGUFValue::String(kind.to_string())));
+                put("rope.scaling.factor", scaling.get("factor").and_then(to_f32));
+                put(
+                    "rope.scaling.original_context_length",
+                    scaling.get("original_max_position_embeddings").and_then(to_u32),
+                );
+            }
+            other if ignore_rope_scaling => {
+                eprintln!("⚠️  rope_scaling type '{other}' is not converted; llama.cpp will not scale RoPE")
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "rope_scaling type '{other}' cannot be converted (supported: linear, yarn, llama3); \
+                         pass --ignore-rope-scaling to convert the model without it"
+                    ),
+                ))
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn rope_scaling_type(scaling: &Value) -> &str {
+    scaling["rope_type"].as_str().or(scaling["type"].as_str()).unwrap_or("")
+}
+
+/// The [`ROPE_FREQS`] factors for a config with `llama3` RoPE scaling, as
+/// `LlamaModel.generate_extra_tensors` in llama.cpp's converter computes
+/// them: 1 for high frequencies, `factor` for low ones and a smooth blend
+/// in between. `None` for any other config.
+pub fn rope_freqs(config_path: &Path) -> io::Result<Option<Vec<f32>>> {
+    let json: Value = serde_json::from_reader(BufReader::new(File::open(config_path)?))?;
+    let scaling = &json["rope_scaling"];
+    if rope_scaling_type(scaling) != "llama3" {
+        return Ok(None);
+    }
+
+    let dim = match json["head_dim"].as_u64() {
+        Some(dim) => dim,
+        None => match (json["hidden_size"].as_u64(), json["num_attention_heads"].as_u64()) {
+            (Some(n_embd), Some(n_head)) if n_head > 0 => n_embd / n_head,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "llama3 rope_scaling needs head_dim, or hidden_size and num_attention_heads",
+                ))
+            }
+        },
+    };
+    let base = json["rope_theta"].as_f64().unwrap_or(10000.0) as f32;
+    let param = |key: &str, default: f32| scaling[key].as_f64().map_or(default, |v| v as f32);
+    let factor = param("factor", 8.0);
+    let low_freq_factor = param("low_freq_factor", 1.0);
+    let high_freq_factor = param("high_freq_factor", 4.0);
+    let old_context_len = param("original_max_position_embeddings", 8192.0);
+
+    let low_freq_wavelen = old_context_len / low_freq_factor;
+    let high_freq_wavelen = old_context_len / high_freq_factor;
+    let factors = (0..dim)
+        .step_by(2)
+        .map(|i| {
+            let freq = 1.0 / base.powf(i as f32 / dim as f32);
+            let wavelen = 2.0 * std::f32::consts::PI / freq;
+            if wavelen < high_freq_wavelen {
+                1.0
+            } else if wavelen > low_freq_wavelen {
+                factor
+            } else {
+                let smooth = (old_context_len / wavelen - low_freq_factor) / (high_freq_factor - low_freq_factor);
+                1.0 / ((1.0 - smooth) / factor + smooth)
+            }
+        })
+        .collect();
+    Ok(Some(factors))
+}
+
+/// Metadata for a converted checkpoint: a few general keys, plus fields
+/// promoted from the Hugging Face `config.json` at `cfg_path`, if given,
+/// including `general.architecture` and its `{arch}.*` hyperparameters
+pub fn build_default_metadata(
+    cfg_path: Option<&Path>,
+    is_quantized: bool,
+    quant_fmt: &str,
+    ignore_rope_scaling: bool,
+) -> io::Result<BTreeMap<String, GGUFValue>> {
+    let mut meta = BTreeMap::new();
+
+    // —— Core defaults ——
+    meta.insert("gguf_version".into(), GGUFValue::String("3".into()));
+    meta.insert(
+        "description".into(),
+        GGUFValue::String("Model converted from HuggingFace format".into()),
+    );
+    meta.insert("precision".into(), GGUFValue::F64(1.0));
+    meta.insert("is_quantized".into(), GGUFValue::Bool(is_quantized));
+    if is_quantized {
+        meta.insert(
+            "quantization_format".into(),
+            GGUFValue::String(quant_fmt.into()),
+        );
+    }
+
+    // —— Promote fields from HF config if provided ——
+    if let Some(p) = cfg_path {
+        let mut buf = Vec::new();
+        File::open(p)?.read_to_end(&mut buf)?;
+        let cfg: serde_json::Value = serde_json::from_slice(&buf)?;
+
+        if let Some(name) = cfg["architectures"]
+            .get(0)
+            .and_then(|v| v.as_str())
+            .map(str::to_owned)
+        {
+            meta.insert("name".into(), GGUFValue::String(name));
+        }
+
+        // merge any extra keys via helper
+        for (k, v) in convert_config_to_metadata(p, ignore_rope_scaling)? {
+            meta.entry(k).or_insert(v);
+        }
+    }
+
+    Ok(meta)
+}
+
 fn to_u64(val: &Value) -> Option<GGUFValue> {
     val.as_u64().map(GGUFValue::U64)
 }
 
+fn to_u32(val: &Value) -> Option<GGUFValue> {
+    val.as_u64().and_then(u32_value)
+}
+
+fn u32_value(v: u64) -> Option<GGUFValue> {
+    u32::try_from(v).ok().map(GGUFValue::U32)
+}
+
+fn to_f32(val: &Value) -> Option<GGUFValue> {
+    val.as_f64().map(|v| GGUFValue::F32(v as f32))
+}
+
 fn to_f64(val: &Value) -> Option<GGUFValue> {
     val.as_f64().map(GGUFValue::F64)
 }
@@ -0,0 +1,8 @@
+//! Hugging Face → GGUF conversion: safetensors checkpoints, tensor names and
+//! `config.json` metadata. The gguf-writer binary writes these as GGUF;
+//! quantize-rs uses the same loaders to quantize a checkpoint without an F32
+//! GGUF in between.
+
+pub mod checkpoint;
+pub mod hf_config_to_gguf;
+pub mod tensor_map;
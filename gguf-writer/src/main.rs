@@ -1,19 +1,21 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use log::info;
+use std::borrow::Cow;
 use std::collections::BTreeMap;
-use std::fs::{self, File};
-use std::io::{self, Read};
+use std::fs::File;
+use std::io;
 use std::path::Path;
 
 use byteorder::{LittleEndian, WriteBytesExt};
-use gguf_core::types::{GGUFValue, GGUFTensor};
-use gguf_core::writer::write_gguf_file;
-use safetensors::tensor::Dtype;
-use safetensors::SafeTensors as SafeTensorFile;
+use gguf_core::decoder;
+use gguf_core::types::{GGUFValue, GGUFTensor, GgmlType, TensorInfo};
+use gguf_core::writer::{write_gguf_file, GgufWriter, WriteOptions};
+use half::{bf16, f16};
 use serde::Deserialize;
 
-mod hf_config_to_gguf;
-use hf_config_to_gguf::convert_config_to_metadata;
+use gguf_writer::checkpoint::Checkpoint;
+use gguf_writer::hf_config_to_gguf::{build_default_metadata, rope_freqs, ROPE_FREQS};
+use gguf_writer::tensor_map::{TensorMap, Transform};
 
 /// ------------------------------
 /// CLI
@@ -33,13 +35,57 @@ struct Cli {
     #[arg(short, long)]
     tensors: Option<String>,
 
-    /// Tensors in safetensors format
+    /// Safetensors checkpoint: a `.safetensors` file, a
+    /// `model.safetensors.index.json`, or a model directory. Every shard
+    /// of a sharded checkpoint is converted.
     #[arg(short = 's', long)]
     safetensors: Option<String>,
 
-    /// HuggingFace `config.json`
+    /// HuggingFace `config.json`. Its `architectures` entry also picks how
+    /// safetensors tensor names are renamed to llama.cpp's.
     #[arg(long)]
     config: Option<String>,
+
+    /// JSON object of extra `"hf.name.{bid}": "blk.{bid}.gguf_name"` tensor
+    /// names, taking precedence over the architecture's; `null` drops a tensor
+    #[arg(long, value_name = "FILE")]
+    tensor_map: Option<String>,
+
+    /// Type of the written tensors. `auto` keeps each tensor's source
+    /// precision; 1-D tensors are always written as F32.
+    #[arg(long, value_enum, default_value_t = OutType::Auto)]
+    outtype: OutType,
+
+    /// Convert a model whose `rope_scaling` type llama.cpp cannot express,
+    /// leaving RoPE unscaled, instead of stopping with an error
+    #[arg(long)]
+    ignore_rope_scaling: bool,
+}
+
+/// Float type written for each tensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutType {
+    F32,
+    F16,
+    Bf16,
+    Auto,
+}
+
+impl OutType {
+    /// The type a tensor of `source` type with `n_dims` dimensions is written
+    /// as. Like llama.cpp's converter, norms, biases and other 1-D tensors
+    /// stay F32 whatever the requested type.
+    fn ggml_type(self, source: GgmlType, n_dims: usize) -> GgmlType {
+        if n_dims <= 1 {
+            return GgmlType::F32;
+        }
+        match self {
+            OutType::F32 => GgmlType::F32,
+            OutType::F16 => GgmlType::F16,
+            OutType::Bf16 => GgmlType::BF16,
+            OutType::Auto => source,
+        }
+    }
 }
 
 /// ------------------------------
@@ -60,161 +106,176 @@ struct TensorDef {
 fn parse_metadata(raw: BTreeMap<String, serde_json::Value>) -> BTreeMap<String, GGUFValue> {
     let mut out = BTreeMap::new();
     for (k, v) in raw {
-        let val = match v {
-            serde_json::Value::String(s) => GGUFValue::String(s),
-            serde_json::Value::Number(n) => {
-                if let Some(u) = n.as_u64() {
-                    GGUFValue::U64(u)
-                } else if let Some(i) = n.as_i64() {
-                    GGUFValue::I64(i)
-                } else if let Some(f) = n.as_f64() {
-                    GGUFValue::F64(f)
-                } else {
-                    eprintln!("⚠️  Unsupported number for key {k}");
-                    continue;
-                }
+        match json_to_value(v) {
+            Some(val) => {
+                out.insert(k, val);
             }
-            serde_json::Value::Bool(b) => GGUFValue::Bool(b),
-            _ => {
-                eprintln!("⚠️  Skipping unsupported metadata key {k}");
-                continue;
-            }
-        };
-        out.insert(k, val);
+            None => eprintln!("⚠️  Skipping unsupported metadata key {k}"),
+        }
     }
     out
 }
 
+/// Converts a JSON value; arrays must be non-empty and hold a single element type
+fn json_to_value(v: serde_json::Value) -> Option<GGUFValue> {
+    match v {
+        serde_json::Value::String(s) => Some(GGUFValue::String(s)),
+        serde_json::Value::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                Some(GGUFValue::U64(u))
+            } else if let Some(i) = n.as_i64() {
+                Some(GGUFValue::I64(i))
+            } else {
+                n.as_f64().map(GGUFValue::F64)
+            }
+        }
+        serde_json::Value::Bool(b) => Some(GGUFValue::Bool(b)),
+        serde_json::Value::Array(items) => {
+            let items = items
+                .into_iter()
+                .map(json_to_value)
+                .collect::<Option<Vec<_>>>()?;
+            let elem_type = items.first()?.value_type();
+            items
+                .iter()
+                .all(|item| item.value_type() == elem_type)
+                .then_some(GGUFValue::Array(elem_type, items))
+        }
+        _ => None,
+    }
+}
+
 fn parse_metadata_file(path: &str) -> io::Result<BTreeMap<String, GGUFValue>> {
     let file = File::open(path)?;
     let raw: BTreeMap<String, serde_json::Value> = serde_json::from_reader(file)?;
     Ok(parse_metadata(raw))
 }
 
-fn build_default_metadata(
-    cfg_path: &Option<String>,
-    is_quantized: bool,
-    quant_fmt: &str,
-) -> io::Result<BTreeMap<String, GGUFValue>> {
-    let mut meta = BTreeMap::new();
-
-    // —— Core defaults ——
-    meta.insert("gguf_version".into(), GGUFValue::String("2".into()));
-    meta.insert(
-        "description".into(),
-        GGUFValue::String("Model converted from HuggingFace format".into()),
-    );
-    meta.insert("precision".into(), GGUFValue::F64(1.0));
-    meta.insert("is_quantized".into(), GGUFValue::Bool(is_quantized));
-    if is_quantized {
-        meta.insert(
-            "quantization_format".into(),
-            GGUFValue::String(quant_fmt.into()),
-        );
+/// ------------------------------
+/// Tensor loaders
+/// ------------------------------
+/// Converts float tensor bytes from `source` to `target`, applying
+/// `transform` on the way, and borrows them when there is nothing to do
+fn convert<'a>(
+    name: &str,
+    data: &'a [u8],
+    dims: &[u64],
+    source: GgmlType,
+    target: GgmlType,
+    transform: Option<Transform>,
+) -> io::Result<Cow<'a, [u8]>> {
+    if source == target && transform.is_none() {
+        return Ok(Cow::Borrowed(data));
     }
-
-    // —— Promote fields from HF config if provided ——
-    if let Some(p) = cfg_path {
-        let mut buf = Vec::new();
-        File::open(p)?.read_to_end(&mut buf)?;
-        let cfg: serde_json::Value = serde_json::from_slice(&buf)?;
-
-        if let Some(u) = cfg["max_position_embeddings"].as_u64() {
-            meta.insert("context_length".into(), GGUFValue::U64(u));
-        }
-        if let Some(u) = cfg["hidden_size"].as_u64() {
-            meta.insert("embedding_size".into(), GGUFValue::U64(u));
-        }
-        if let Some(name) = cfg["architectures"]
-            .get(0)
-            .and_then(|v| v.as_str())
-            .map(str::to_owned)
-        {
-            meta.insert("name".into(), GGUFValue::String(name));
-        }
-
-        // merge any extra keys via helper
-        if let Ok(extra) = convert_config_to_metadata(p) {
-            for (k, v) in extra {
-                meta.entry(k).or_insert(v);
-            }
-        }
+    let mut floats = decoder::dequantize(source, data, dims)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{name}: {e}")))?;
+    if let Some(transform) = transform {
+        floats = transform.apply(name, &floats, dims)?;
     }
-
-    Ok(meta)
+    Ok(Cow::Owned(match target {
+        GgmlType::F16 => floats.iter().flat_map(|&v| f16::from_f32(v).to_le_bytes()).collect(),
+        GgmlType::BF16 => floats.iter().flat_map(|&v| bf16::from_f32(v).to_le_bytes()).collect(),
+        _ => floats.iter().flat_map(|v| v.to_le_bytes()).collect(),
+    }))
 }
 
-/// ------------------------------
-/// Tensor loaders
-/// ------------------------------
-fn load_tensors_from_json(path: &str) -> io::Result<Vec<GGUFTensor>> {
+fn load_tensors_from_json(path: &str, outtype: OutType) -> io::Result<Vec<GGUFTensor>> {
     let file = File::open(path)?;
     let defs: Vec<TensorDef> = serde_json::from_reader(file)?;
 
-    Ok(defs
-        .into_iter()
+    // JSON values are always written as little-endian f32
+    if let Some(def) = defs.iter().find(|d| d.type_id != GgmlType::F32.id()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Tensor '{}' has type {}, but JSON tensors must be F32 ({})",
+                def.name,
+                def.type_id,
+                GgmlType::F32.id()
+            ),
+        ));
+    }
+
+    defs.into_iter()
         .map(|def| {
             let mut buf = Vec::with_capacity(def.values.len() * 4);
             for v in def.values {
                 buf.write_f32::<LittleEndian>(v).unwrap();
             }
-            GGUFTensor {
+            let ty = outtype.ggml_type(GgmlType::F32, def.dims.len());
+            let values = convert(&def.name, &buf, &def.dims, GgmlType::F32, ty, None)?.into_owned();
+            Ok(GGUFTensor {
                 name: def.name,
-                type_id: def.type_id,
+                type_id: ty.id(),
                 dims: def.dims,
                 offset: 0,
-                values: buf,
-            }
+                values,
+            })
         })
-        .collect())
+        .collect()
 }
 
-fn load_tensors_from_safetensors(path: &str) -> io::Result<(Vec<GGUFTensor>, bool)> {
-    let data = fs::read(path)?;
-    let st = SafeTensorFile::deserialize(&data)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-
-    let mut out = Vec::new();
-    let mut all_f32 = true;
-
-    for (name, tv) in st.tensors() {
-        let dims = tv.shape().iter().map(|&d| d as u64).collect::<Vec<_>>();
-        let bytes = match tv.dtype() {
-            Dtype::F32 => tv.data().to_vec(),
-            Dtype::F16 | Dtype::BF16 => {
-                all_f32 = false;
-                // convert to f32 little-endian
-                tv.data()
-                    .chunks_exact(2)
-                    .flat_map(|c| {
-                        let [lo, hi] = [c[0], c[1]];
-                        let bits = match tv.dtype() {
-                            Dtype::F16 => {
-                                use half::f16;
-                                f16::from_le_bytes([lo, hi]).to_f32().to_bits()
-                            }
-                            Dtype::BF16 => ((hi as u32) << 24) | ((lo as u32) << 16),
-                            _ => unreachable!(),
-                        };
-                        bits.to_le_bytes()
-                    })
-                    .collect()
-            }
-            other => {
-                eprintln!("⚠️  Unsupported dtype {:?} for {}", other, name);
-                continue;
-            }
+/// Streams every tensor of a safetensors checkpoint into `output` as
+/// `outtype`, holding one tensor in memory at a time. With a `names` map,
+/// tensors are written under their GGUF names with their architecture's
+/// value transforms applied. `extra` F32 tensors generated from the config
+/// follow the checkpoint's.
+fn write_from_safetensors(
+    path: &Path,
+    output: &str,
+    metadata: &BTreeMap<String, GGUFValue>,
+    outtype: OutType,
+    names: Option<&TensorMap>,
+    extra: &[(&str, Vec<f32>)],
+) -> io::Result<()> {
+    let checkpoint = Checkpoint::open(path)?;
+    let gguf_names = match names {
+        Some(map) => map.gguf_names(checkpoint.tensors().iter().map(|t| t.name.as_str()))?,
+        None => checkpoint.tensors().iter().map(|t| Some(t.name.clone())).collect(),
+    };
+    let mut tensors = Vec::new();
+    for (t, name) in checkpoint.tensors().iter().zip(gguf_names) {
+        let Some(name) = name else {
+            continue;
         };
+        let ty = t.ggml_type()?;
+        let transform = names.and_then(|map| map.transform(&t.name));
+        tensors.push((t, name, ty, outtype.ggml_type(ty, t.shape.len()), transform));
+    }
+    println!(
+        "📦 Converting {} tensors from {} safetensors file(s)",
+        tensors.len(),
+        checkpoint.shard_count()
+    );
 
-        out.push(GGUFTensor {
-            name: name.to_string(),
-            type_id: 0,
-            dims,
+    let mut directory: Vec<_> = tensors
+        .iter()
+        .map(|(t, name, _, target, transform)| TensorInfo {
+            name: name.clone(),
+            type_id: target.id(),
+            dims: transform.map_or_else(|| t.dims(), |tr| tr.dims(&t.dims())),
             offset: 0,
-            values: bytes,
-        });
+            size: t.shape.iter().product::<u64>() * target.type_size(),
+        })
+        .collect();
+    directory.extend(extra.iter().map(|(name, values)| TensorInfo {
+        name: name.to_string(),
+        type_id: GgmlType::F32.id(),
+        dims: vec![values.len() as u64],
+        offset: 0,
+        size: values.len() as u64 * 4,
+    }));
+    let mut writer = GgufWriter::create(output, metadata, directory, &WriteOptions::default())?;
+    for (t, _, source, target, transform) in tensors {
+        let values = convert(&t.name, checkpoint.data(t), &t.dims(), source, target, transform)?;
+        writer.write_tensor_data(&values)?;
     }
-    Ok((out, all_f32))
+    for (_, values) in extra {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        writer.write_tensor_data(&bytes)?;
+    }
+    writer.finish()?;
+    Ok(())
 }
 
 /// ------------------------------
@@ -234,19 +295,34 @@ fn main() -> io::Result<()> {
     let quant_fmt = if is_quantized { "UNKNOWN" } else { "NA" };
 
     // -------- metadata ------------
-    let mut metadata: BTreeMap<String, GGUFValue> = if let Some(path) = &cli.metadata {
+    let metadata: BTreeMap<String, GGUFValue> = if let Some(path) = &cli.metadata {
         parse_metadata_file(path)?
     } else {
-        build_default_metadata(&cli.config, is_quantized, quant_fmt)?
+        build_default_metadata(
+            cli.config.as_deref().map(Path::new),
+            is_quantized,
+            quant_fmt,
+            cli.ignore_rope_scaling,
+        )?
     };
 
     // -------- tensors -------------
-    let (tensors, _native_f32) = if let Some(safe) = &cli.safetensors {
+    let tensors = if let Some(safe) = &cli.safetensors {
         info!("📦  Loading tensors from safetensors: {safe}");
-        load_tensors_from_safetensors(safe)?
+        let names = TensorMap::load(
+            cli.config.as_deref().map(Path::new),
+            cli.tensor_map.as_deref().map(Path::new),
+        )?;
+        let mut extra = Vec::new();
+        if let Some(factors) = cli.config.as_deref().map(|c| rope_freqs(Path::new(c))).transpose()?.flatten() {
+            extra.push((ROPE_FREQS, factors));
+        }
+        write_from_safetensors(Path::new(safe), &cli.output, &metadata, cli.outtype, names.as_ref(), &extra)?;
+        println!("✅ GGUF file written to '{}'", cli.output);
+        return Ok(());
     } else if let Some(json) = &cli.tensors {
         info!("📦  Loading tensors from JSON: {json}");
-        (load_tensors_from_json(json)?, true)
+        load_tensors_from_json(json, cli.outtype)?
     } else {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
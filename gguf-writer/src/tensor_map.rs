@@ -0,0 +1,398 @@
+//! Hugging Face → GGUF tensor names, following llama.cpp's `TensorNameMap`.
+//!
+//! Each supported architecture has a table of Hugging Face names (without
+//! their `.weight` / `.bias` suffix, with `{bid}` for the layer number) and
+//! the GGUF names llama.cpp loads them by. The table is picked from the
+//! `architectures` list in `config.json`. A tensor the table does not cover
+//! is an error, so nothing reaches the GGUF under a name llama.cpp ignores;
+//! an override file supplies names for custom models and layers.
+//!
+//! Some architectures also store values differently from llama.cpp, and
+//! their [`Transform`]s are applied along with the new names, as
+//! `modify_tensors` does in `convert_hf_to_gguf.py`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use gguf_core::decoder;
+use gguf_core::types::GgmlType;
+use half::{bf16, f16};
+
+/// A name pattern and the GGUF name it maps to, or `None` for tensors that
+/// are dropped, such as precomputed rotary frequencies
+type Table = &'static [(&'static str, Option<&'static str>)];
+
+const LLAMA: Table = &[
+    ("model.embed_tokens", Some("token_embd")),
+    ("model.norm", Some("output_norm")),
+    ("lm_head", Some("output")),
+    ("model.layers.{bid}.input_layernorm", Some("blk.{bid}.attn_norm")),
+    ("model.layers.{bid}.self_attn.q_proj", Some("blk.{bid}.attn_q")),
+    ("model.layers.{bid}.self_attn.k_proj", Some("blk.{bid}.attn_k")),
+    ("model.layers.{bid}.self_attn.v_proj", Some("blk.{bid}.attn_v")),
+    ("model.layers.{bid}.self_attn.o_proj", Some("blk.{bid}.attn_output")),
+    ("model.layers.{bid}.self_attn.rotary_emb.inv_freq", None),
+    ("model.layers.{bid}.post_attention_layernorm", Some("blk.{bid}.ffn_norm")),
+    ("model.layers.{bid}.mlp.gate_proj", Some("blk.{bid}.ffn_gate")),
+    ("model.layers.{bid}.mlp.up_proj", Some("blk.{bid}.ffn_up")),
+    ("model.layers.{bid}.mlp.down_proj", Some("blk.{bid}.ffn_down")),
+];
+
+/// Phi-3 fuses the attention projections, and the gate with the up projection
+const PHI3: Table = &[
+    ("model.embed_tokens", Some("token_embd")),
+    ("model.norm", Some("output_norm")),
+    ("lm_head", Some("output")),
+    ("model.layers.{bid}.input_layernorm", Some("blk.{bid}.attn_norm")),
+    ("model.layers.{bid}.self_attn.qkv_proj", Some("blk.{bid}.attn_qkv")),
+    ("model.layers.{bid}.self_attn.o_proj", Some("blk.{bid}.attn_output")),
+    ("model.layers.{bid}.self_attn.rotary_emb.inv_freq", None),
+    ("model.layers.{bid}.post_attention_layernorm", Some("blk.{bid}.ffn_norm")),
+    ("model.layers.{bid}.mlp.gate_up_proj", Some("blk.{bid}.ffn_up")),
+    ("model.layers.{bid}.mlp.down_proj", Some("blk.{bid}.ffn_down")),
+];
+
+/// GPT-2 checkpoints are saved with or without the `transformer.` prefix
+const GPT2: Table = &[
+    ("wte", Some("token_embd")),
+    ("wpe", Some("position_embd")),
+    ("ln_f", Some("output_norm")),
+    ("lm_head", Some("output")),
+    ("h.{bid}.ln_1", Some("blk.{bid}.attn_norm")),
+    ("h.{bid}.attn.c_attn", Some("blk.{bid}.attn_qkv")),
+    ("h.{bid}.attn.c_proj", Some("blk.{bid}.attn_output")),
+    ("h.{bid}.attn.bias", None),
+    ("h.{bid}.attn.masked_bias", None),
+    ("h.{bid}.ln_2", Some("blk.{bid}.ffn_norm")),
+    ("h.{bid}.mlp.c_fc", Some("blk.{bid}.ffn_up")),
+    ("h.{bid}.mlp.c_proj", Some("blk.{bid}.ffn_down")),
+];
+
+/// Hugging Face name suffixes whose values change on the way to GGUF
+type Changes = &'static [(&'static str, Change)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    /// RoPE permute by `num_attention_heads`
+    PermuteQ,
+    /// RoPE permute by `num_key_value_heads`
+    PermuteK,
+    AddOne,
+    Transpose,
+}
+
+/// llama.cpp's llama RoPE rotates adjacent pairs, Hugging Face's the two
+/// halves of each head
+const LLAMA_CHANGES: Changes = &[
+    ("self_attn.q_proj.weight", Change::PermuteQ),
+    ("self_attn.q_proj.bias", Change::PermuteQ),
+    ("self_attn.k_proj.weight", Change::PermuteK),
+    ("self_attn.k_proj.bias", Change::PermuteK),
+];
+
+/// Gemma's RMS norms scale by `1 + weight`
+const GEMMA_CHANGES: Changes = &[("norm.weight", Change::AddOne)];
+
+/// GPT-2's `Conv1D` layers store their weights as `[in, out]`
+const GPT2_CHANGES: Changes = &[
+    (".c_attn.weight", Change::Transpose),
+    (".c_proj.weight", Change::Transpose),
+    (".c_fc.weight", Change::Transpose),
+];
+
+struct Architecture {
+    /// Name in `config.json`'s `architectures`
+    hf: &'static str,
+    /// `general.architecture` llama.cpp converts it to
+    gguf: &'static str,
+    table: Table,
+    /// Prefix some checkpoints put before every name in `table`
+    prefix: Option<&'static str>,
+    changes: Changes,
+}
+
+/// Mistral loads as llama
+const ARCHITECTURES: &[Architecture] = &[
+    Architecture { hf: "LlamaForCausalLM", gguf: "llama", table: LLAMA, prefix: None, changes: LLAMA_CHANGES },
+    Architecture { hf: "MistralForCausalLM", gguf: "llama", table: LLAMA, prefix: None, changes: LLAMA_CHANGES },
+    Architecture { hf: "Qwen2ForCausalLM", gguf: "qwen2", table: LLAMA, prefix: None, changes: &[] },
+    Architecture { hf: "Phi3ForCausalLM", gguf: "phi3", table: PHI3, prefix: None, changes: &[] },
+    Architecture { hf: "GemmaForCausalLM", gguf: "gemma", table: LLAMA, prefix: None, changes: GEMMA_CHANGES },
+    Architecture {
+        hf: "GPT2LMHeadModel",
+        gguf: "gpt2",
+        table: GPT2,
+        prefix: Some("transformer."),
+        changes: GPT2_CHANGES,
+    },
+];
+
+fn find_architecture(hf_architecture: &str) -> Option<&'static Architecture> {
+    ARCHITECTURES.iter().find(|a| a.hf == hf_architecture)
+}
+
+/// The GGUF `general.architecture` for a `config.json` architecture
+pub fn gguf_architecture(hf_architecture: &str) -> Option<&'static str> {
+    find_architecture(hf_architecture).map(|a| a.gguf)
+}
+
+fn read_config(config: &Path) -> io::Result<serde_json::Value> {
+    serde_json::from_slice(&fs::read(config)?).map_err(|e| invalid(format!("{}: {e}", config.display())))
+}
+
+fn architecture_of(json: &serde_json::Value) -> Option<String> {
+    json.get("architectures")
+        .and_then(|a| a.get(0))
+        .and_then(|a| a.as_str())
+        .map(str::to_string)
+}
+
+/// A change to a tensor's values that llama.cpp's converter makes besides
+/// renaming it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    /// `LlamaModel.permute`: within each of `n_head` heads, rows from the
+    /// first and second halves are interleaved
+    RopePermute { n_head: u64 },
+    /// Adds 1 to every value
+    AddOne,
+    /// Swaps the two dimensions of a matrix
+    Transpose,
+}
+
+impl Transform {
+    /// GGUF dims of a tensor with `dims` after the transform
+    pub fn dims(self, dims: &[u64]) -> Vec<u64> {
+        match self {
+            Transform::Transpose => dims.iter().rev().copied().collect(),
+            _ => dims.to_vec(),
+        }
+    }
+
+    /// Applies the transform to the values of tensor `name`, which has GGUF
+    /// `dims` (innermost first)
+    pub fn apply(self, name: &str, values: &[f32], dims: &[u64]) -> io::Result<Vec<f32>> {
+        let rows = dims.last().copied().unwrap_or(1) as usize;
+        let cols = values.len() / rows.max(1);
+        match self {
+            Transform::RopePermute { n_head } => {
+                let n_head = n_head as usize;
+                if n_head == 0 || !rows.is_multiple_of(2 * n_head) {
+                    return Err(invalid(format!(
+                        "{name}: {rows} rows cannot be RoPE-permuted for {n_head} heads"
+                    )));
+                }
+                // Row (h, 2, r) of the input becomes row (h, r, 2)
+                let half = rows / n_head / 2;
+                let mut out = Vec::with_capacity(values.len());
+                for h in 0..n_head {
+                    for i in 0..half {
+                        for j in 0..2 {
+                            let row = h * 2 * half + j * half + i;
+                            out.extend_from_slice(&values[row * cols..(row + 1) * cols]);
+                        }
+                    }
+                }
+                Ok(out)
+            }
+            Transform::AddOne => Ok(values.iter().map(|v| v + 1.0).collect()),
+            Transform::Transpose => {
+                if dims.len() != 2 {
+                    return Err(invalid(format!("{name}: cannot transpose dims {dims:?}")));
+                }
+                let mut out = Vec::with_capacity(values.len());
+                for c in 0..cols {
+                    out.extend((0..rows).map(|r| values[r * cols + c]));
+                }
+                Ok(out)
+            }
+        }
+    }
+
+    /// [`Transform::apply`] to F32, F16 or BF16 data, keeping its type
+    pub fn apply_bytes(self, name: &str, ty: GgmlType, data: &[u8], dims: &[u64]) -> io::Result<Vec<u8>> {
+        let values = decoder::dequantize(ty, data, dims).map_err(|e| invalid(format!("{name}: {e}")))?;
+        let values = self.apply(name, &values, dims)?;
+        Ok(match ty {
+            GgmlType::F16 => values.iter().flat_map(|&v| f16::from_f32(v).to_le_bytes()).collect(),
+            GgmlType::BF16 => values.iter().flat_map(|&v| bf16::from_f32(v).to_le_bytes()).collect(),
+            _ => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        })
+    }
+}
+
+struct Entry {
+    hf: String,
+    gguf: Option<String>,
+}
+
+impl Entry {
+    /// The GGUF name for `name` if this entry's pattern matches it, with
+    /// `{bid}` standing for a layer number
+    fn apply(&self, name: &str) -> Option<Option<String>> {
+        match self.hf.split_once("{bid}") {
+            None => (name == self.hf).then(|| self.gguf.clone()),
+            Some((prefix, suffix)) => {
+                let bid = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                if bid.is_empty() || !bid.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                Some(self.gguf.as_ref().map(|g| g.replace("{bid}", bid)))
+            }
+        }
+    }
+}
+
+/// Tensor names for one model: the override file's entries, then the
+/// architecture's table
+pub struct TensorMap {
+    /// What the names are for, in error messages
+    source: String,
+    entries: Vec<Entry>,
+    /// The architecture's value changes, by Hugging Face name suffix
+    transforms: Vec<(&'static str, Transform)>,
+}
+
+impl TensorMap {
+    /// The map for a model with `config` and an optional override file.
+    ///
+    /// Without either there is nothing to map by and names are kept as they
+    /// are (`None`). A config naming an architecture without a table is an
+    /// error unless an override file covers its tensors instead.
+    ///
+    /// The override file is a JSON object from Hugging Face name patterns to
+    /// GGUF names, or to `null` to drop a tensor, e.g.
+    /// `{"model.layers.{bid}.mlp.router": "blk.{bid}.ffn_gate_inp"}`.
+    pub fn load(config: Option<&Path>, overrides: Option<&Path>) -> io::Result<Option<TensorMap>> {
+        let json = match config {
+            Some(config) => read_config(config)?,
+            None => serde_json::Value::Null,
+        };
+        let architecture = architecture_of(&json);
+
+        let mut map = TensorMap {
+            source: String::new(),
+            entries: Vec::new(),
+            transforms: Vec::new(),
+        };
+        if let Some(path) = overrides {
+            let entries: BTreeMap<String, Option<String>> = serde_json::from_slice(&fs::read(path)?)
+                .map_err(|e| invalid(format!("{}: {e}", path.display())))?;
+            map.entries
+                .extend(entries.into_iter().map(|(hf, gguf)| Entry { hf, gguf }));
+            map.source = path.display().to_string();
+        }
+
+        let known = match architecture.as_deref() {
+            Some(arch) => match find_architecture(arch) {
+                Some(known) => Some(known),
+                None if overrides.is_some() => None,
+                None => {
+                    let names: Vec<_> = ARCHITECTURES.iter().map(|a| a.hf).collect();
+                    return Err(invalid(format!(
+                        "no tensor names for architecture {arch} (known: {}); pass --tensor-map with names for its tensors",
+                        names.join(", ")
+                    )));
+                }
+            },
+            None => None,
+        };
+        let Some(known) = known else {
+            return Ok(overrides.is_some().then_some(map));
+        };
+
+        map.source = match overrides {
+            Some(_) => format!("{} and {}", known.hf, map.source),
+            None => known.hf.to_string(),
+        };
+        for &(hf, gguf) in known.table {
+            let gguf = gguf.map(str::to_string);
+            if let Some(prefix) = known.prefix {
+                map.entries.push(Entry {
+                    hf: format!("{prefix}{hf}"),
+                    gguf: gguf.clone(),
+                });
+            }
+            map.entries.push(Entry { hf: hf.to_string(), gguf });
+        }
+
+        for &(suffix, change) in known.changes {
+            let transform = match change {
+                Change::PermuteQ | Change::PermuteK => {
+                    let Some(n_head) = json.get("num_attention_heads").and_then(|v| v.as_u64()) else {
+                        return Err(invalid(format!(
+                            "{} needs num_attention_heads in config.json to permute its q/k projections",
+                            known.hf
+                        )));
+                    };
+                    let n_head_kv = json.get("num_key_value_heads").and_then(|v| v.as_u64());
+                    match (change, n_head_kv) {
+                        (Change::PermuteK, Some(n_head_kv)) => Transform::RopePermute { n_head: n_head_kv },
+                        _ => Transform::RopePermute { n_head },
+                    }
+                }
+                Change::AddOne => Transform::AddOne,
+                Change::Transpose => Transform::Transpose,
+            };
+            map.transforms.push((suffix, transform));
+        }
+        Ok(Some(map))
+    }
+
+    /// The change to the values of the Hugging Face tensor `name`, if its
+    /// architecture needs one
+    pub fn transform(&self, name: &str) -> Option<Transform> {
+        self.transforms
+            .iter()
+            .find(|(suffix, _)| name.ends_with(suffix))
+            .map(|&(_, t)| t)
+    }
+
+    /// The GGUF name of one tensor, `Some(None)` if it is dropped, or `None`
+    /// if nothing maps it. The `.weight` or `.bias` suffix carries over.
+    pub fn gguf_name(&self, name: &str) -> Option<Option<String>> {
+        if let Some(found) = self.entries.iter().find_map(|e| e.apply(name)) {
+            return Some(found);
+        }
+        let (base, suffix) = [".weight", ".bias"]
+            .iter()
+            .find_map(|s| name.strip_suffix(s).map(|base| (base, *s)))?;
+        let gguf = self.entries.iter().find_map(|e| e.apply(base))?;
+        Some(gguf.map(|g| format!("{g}{suffix}")))
+    }
+
+    /// GGUF names for every tensor, `None` for dropped ones. All unmapped
+    /// tensors are reported together, as are two tensors given one name.
+    pub fn gguf_names<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> io::Result<Vec<Option<String>>> {
+        let mut out = Vec::new();
+        let mut unmapped = Vec::new();
+        let mut seen: HashMap<String, &str> = HashMap::new();
+        for name in names {
+            match self.gguf_name(name) {
+                Some(Some(gguf)) => {
+                    if let Some(first) = seen.insert(gguf.clone(), name) {
+                        return Err(invalid(format!("{first} and {name} both map to {gguf}")));
+                    }
+                    out.push(Some(gguf));
+                }
+                Some(None) => out.push(None),
+                None => unmapped.push(name),
+            }
+        }
+        if !unmapped.is_empty() {
+            return Err(invalid(format!(
+                "{} tensors have no GGUF name for {}: {}; pass --tensor-map with names for them",
+                unmapped.len(),
+                self.source,
+                unmapped.join(", ")
+            )));
+        }
+        Ok(out)
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
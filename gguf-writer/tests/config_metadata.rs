@@ -0,0 +1,215 @@
+//! Checks `config.json` hyperparameters become llama.cpp's `{arch}.*` keys
+//! with the value types it reads them as, and that RoPE scaling llama.cpp
+//! cannot express is refused.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+use gguf_core::decoder;
+use gguf_core::file::GgufFile;
+use gguf_core::types::{GGUFValue, GgmlType};
+use gguf_test_support::{write_safetensors, TempDir};
+use gguf_writer::hf_config_to_gguf::{build_default_metadata, rope_freqs};
+
+fn try_metadata(tag: &str, config: &str, ignore_rope_scaling: bool) -> io::Result<BTreeMap<String, GGUFValue>> {
+    let dir = TempDir::new(&format!("config-{tag}"));
+    let path = dir.join("config.json");
+    fs::write(&path, config).unwrap();
+    build_default_metadata(Some(&path), false, "NA", ignore_rope_scaling)
+}
+
+fn metadata(tag: &str, config: &str) -> BTreeMap<String, GGUFValue> {
+    try_metadata(tag, config, false).unwrap()
+}
+
+fn string(s: &str) -> GGUFValue {
+    GGUFValue::String(s.to_string())
+}
+
+#[test]
+fn mistral_config_gives_llama_keys() {
+    let meta = metadata(
+        "mistral",
+        r#"{
+            "architectures": ["MistralForCausalLM"],
+            "model_type": "mistral",
+            "hidden_size": 4096,
+            "intermediate_size": 14336,
+            "max_position_embeddings": 32768,
+            "num_attention_heads": 32,
+            "num_hidden_layers": 32,
+            "num_key_value_heads": 8,
+            "rms_norm_eps": 1e-05,
+            "rope_theta": 1000000.0,
+            "rope_scaling": {"type": "linear", "factor": 2.0},
+            "vocab_size": 32000
+        }"#,
+    );
+
+    let expected = [
+        ("general.architecture", string("llama")),
+        ("llama.context_length", GGUFValue::U32(32768)),
+        ("llama.embedding_length", GGUFValue::U32(4096)),
+        ("llama.block_count", GGUFValue::U32(32)),
+        ("llama.feed_forward_length", GGUFValue::U32(14336)),
+        ("llama.attention.head_count", GGUFValue::U32(32)),
+        ("llama.attention.head_count_kv", GGUFValue::U32(8)),
+        ("llama.attention.layer_norm_rms_epsilon", GGUFValue::F32(1e-5)),
+        ("llama.rope.freq_base", GGUFValue::F32(1e6)),
+        ("llama.rope.scaling.type", string("linear")),
+        ("llama.rope.scaling.factor", GGUFValue::F32(2.0)),
+    ];
+    for (key, value) in expected {
+        assert_eq!(meta.get(key), Some(&value), "{key}");
+    }
+    for key in ["hidden_size", "num_attention_heads", "context_length", "embedding_size"] {
+        assert!(!meta.contains_key(key), "{key} copied verbatim");
+    }
+}
+
+#[test]
+fn gpt2_config_uses_its_own_names() {
+    let meta = metadata(
+        "gpt2",
+        r#"{
+            "architectures": ["GPT2LMHeadModel"],
+            "n_ctx": 1024,
+            "n_embd": 768,
+            "n_head": 12,
+            "n_inner": null,
+            "n_layer": 12,
+            "n_positions": 1024,
+            "layer_norm_epsilon": 1e-05
+        }"#,
+    );
+
+    let expected = [
+        ("general.architecture", string("gpt2")),
+        ("gpt2.context_length", GGUFValue::U32(1024)),
+        ("gpt2.embedding_length", GGUFValue::U32(768)),
+        ("gpt2.block_count", GGUFValue::U32(12)),
+        ("gpt2.feed_forward_length", GGUFValue::U32(3072)),
+        ("gpt2.attention.head_count", GGUFValue::U32(12)),
+        ("gpt2.attention.layer_norm_epsilon", GGUFValue::F32(1e-5)),
+    ];
+    for (key, value) in expected {
+        assert_eq!(meta.get(key), Some(&value), "{key}");
+    }
+    assert!(!meta.contains_key("gpt2.attention.head_count_kv"));
+}
+
+#[test]
+fn gemma_head_dim_and_yarn_scaling() {
+    let meta = metadata(
+        "gemma",
+        r#"{
+            "architectures": ["GemmaForCausalLM"],
+            "hidden_size": 3072,
+            "head_dim": 256,
+            "num_attention_heads": 16,
+            "rope_scaling": {"rope_type": "yarn", "factor": 4.0, "original_max_position_embeddings": 8192}
+        }"#,
+    );
+
+    assert_eq!(meta.get("general.architecture"), Some(&string("gemma")));
+    assert_eq!(meta.get("gemma.attention.key_length"), Some(&GGUFValue::U32(256)));
+    assert_eq!(meta.get("gemma.attention.value_length"), Some(&GGUFValue::U32(256)));
+    assert_eq!(meta.get("gemma.rope.scaling.type"), Some(&string("yarn")));
+    assert_eq!(meta.get("gemma.rope.scaling.factor"), Some(&GGUFValue::F32(4.0)));
+    assert_eq!(meta.get("gemma.rope.scaling.original_context_length"), Some(&GGUFValue::U32(8192)));
+}
+
+#[test]
+fn unreadable_config_is_an_error() {
+    let dir = TempDir::new("config-bad");
+    let path = dir.join("config.json");
+    fs::write(&path, r#"{"architectures": ["LlamaForCausalLM"], "hidden_size": 4096,"#).unwrap();
+    assert!(build_default_metadata(Some(&path), false, "NA", false).is_err());
+    assert!(build_default_metadata(Some(&dir.join("missing.json")), false, "NA", false).is_err());
+}
+
+#[test]
+fn unsupported_rope_scaling_needs_an_explicit_override() {
+    let config = r#"{
+        "architectures": ["LlamaForCausalLM"],
+        "rope_scaling": {"type": "dynamic", "factor": 2.0}
+    }"#;
+    let err = try_metadata("dynamic", config, false).unwrap_err().to_string();
+    assert!(err.contains("dynamic") && err.contains("--ignore-rope-scaling"), "{err}");
+
+    let meta = try_metadata("dynamic-ignored", config, true).unwrap();
+    assert_eq!(meta.get("general.architecture"), Some(&string("llama")));
+    assert!(!meta.keys().any(|k| k.starts_with("llama.rope.scaling")));
+}
+
+const LLAMA3_CONFIG: &str = r#"{
+    "architectures": ["LlamaForCausalLM"],
+    "hidden_size": 256,
+    "num_attention_heads": 4,
+    "rope_theta": 500000.0,
+    "rope_scaling": {
+        "rope_type": "llama3",
+        "factor": 8.0,
+        "low_freq_factor": 1.0,
+        "high_freq_factor": 4.0,
+        "original_max_position_embeddings": 8192
+    }
+}"#;
+
+/// llama3 factors computed in f64 from the formula in Meta's reference code
+fn llama3_factors(dim: u32) -> Vec<f64> {
+    let (base, factor, low, high, old_context) = (500000.0f64, 8.0, 1.0, 4.0, 8192.0);
+    (0..dim / 2)
+        .map(|i| {
+            let wavelen = 2.0 * std::f64::consts::PI * base.powf(2.0 * i as f64 / dim as f64);
+            if wavelen < old_context / high {
+                1.0
+            } else if wavelen > old_context / low {
+                factor
+            } else {
+                let smooth = (old_context / wavelen - low) / (high - low);
+                1.0 / ((1.0 - smooth) / factor + smooth)
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn llama3_rope_scaling_becomes_rope_freqs() {
+    let meta = metadata("llama3", LLAMA3_CONFIG);
+    assert!(!meta.keys().any(|k| k.starts_with("llama.rope.scaling")));
+
+    let dir = TempDir::new("llama3");
+    let config = dir.join("config.json");
+    fs::write(&config, LLAMA3_CONFIG).unwrap();
+
+    // 64-dim heads give 32 factors, running from 1 up to the full factor
+    let factors = rope_freqs(&config).unwrap().unwrap();
+    let expected = llama3_factors(64);
+    assert_eq!(factors.len(), expected.len());
+    for (i, (&f, &e)) in factors.iter().zip(&expected).enumerate() {
+        assert!((f as f64 - e).abs() < 1e-4 * e, "factor {i}: {f} vs {e}");
+    }
+    assert_eq!((factors[0], factors[31]), (1.0, 8.0));
+    assert!(factors.iter().any(|&f| f > 1.0 && f < 8.0));
+
+    // gguf-writer stores them as the F32 rope_freqs.weight llama.cpp loads
+    write_safetensors(&dir.join("model.safetensors"), &[("model.norm.weight", "F32", &[4], vec![0; 16])]);
+    let output = dir.join("out.gguf");
+    let status = Command::new(env!("CARGO_BIN_EXE_gguf-writer"))
+        .arg("--safetensors")
+        .arg(dir.join("model.safetensors"))
+        .arg("--config")
+        .arg(&config)
+        .arg("--output")
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let gguf = GgufFile::open(&output).unwrap();
+    let t = gguf.tensor("rope_freqs.weight").unwrap();
+    assert_eq!((t.ggml_type(), t.dims.clone()), (Some(GgmlType::F32), vec![32]));
+    assert_eq!(decoder::dequantize(GgmlType::F32, &gguf.tensor_data(t).unwrap(), &t.dims).unwrap(), factors);
+}
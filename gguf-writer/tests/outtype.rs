@@ -0,0 +1,61 @@
+//! Checks `--outtype`: `auto` keeps each safetensors dtype, the explicit
+//! types convert every matrix, and 1-D tensors are always F32.
+
+use std::process::Command;
+
+use gguf_core::decoder;
+use gguf_core::file::GgufFile;
+use gguf_core::types::GgmlType;
+use gguf_test_support::{encode, write_safetensors, TempDir};
+
+/// Name, safetensors dtype and row-major shape
+const TENSORS: &[(&str, &str, &[u64])] = &[
+    ("model.layers.0.self_attn.q_proj.weight", "BF16", &[8, 16]),
+    ("model.layers.0.mlp.up_proj.weight", "F16", &[4, 16]),
+    ("model.layers.0.mlp.down_proj.weight", "F32", &[16, 4]),
+    ("model.layers.0.input_layernorm.weight", "BF16", &[16]),
+];
+
+/// Values exactly representable in F16 and BF16, so every conversion is lossless
+fn values(n: u64) -> Vec<f32> {
+    (0..n).map(|j| (j % 64) as f32 * 0.25 - 8.0).collect()
+}
+
+#[test]
+fn outtype_picks_tensor_types() {
+    let dir = TempDir::new("outtype");
+    let input = dir.join("model.safetensors");
+    let tensors: Vec<_> = TENSORS
+        .iter()
+        .map(|&(name, dtype, shape)| (name, dtype, shape, encode(dtype, &values(shape.iter().product()))))
+        .collect();
+    write_safetensors(&input, &tensors);
+
+    for (outtype, expected) in [
+        ("auto", [GgmlType::BF16, GgmlType::F16, GgmlType::F32]),
+        ("f32", [GgmlType::F32; 3]),
+        ("f16", [GgmlType::F16; 3]),
+        ("bf16", [GgmlType::BF16; 3]),
+    ] {
+        let output = dir.join(format!("{outtype}.gguf"));
+        let status = Command::new(env!("CARGO_BIN_EXE_gguf-writer"))
+            .arg("--safetensors")
+            .arg(&input)
+            .arg("--output")
+            .arg(&output)
+            .args(["--outtype", outtype])
+            .status()
+            .unwrap();
+        assert!(status.success(), "--outtype {outtype} failed");
+
+        let file = GgufFile::open(&output).unwrap();
+        let expected = expected.iter().copied().chain([GgmlType::F32]);
+        for (&(name, _, shape), ty) in TENSORS.iter().zip(expected) {
+            let t = file.tensor(name).unwrap();
+            assert_eq!(t.ggml_type(), Some(ty), "{name} with --outtype {outtype}");
+            let data = file.tensor_data(t).unwrap();
+            let decoded = decoder::dequantize(ty, &data, &t.dims).unwrap();
+            assert_eq!(decoded, values(shape.iter().product()), "{name} with --outtype {outtype}");
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Converts a sharded safetensors checkpoint and checks every shard's
+//! tensors reach the GGUF, whichever way the checkpoint is named, and that
+//! shards disagreeing with each other or with the index, or holding tensors
+//! that cannot be converted, are refused.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+use gguf_core::file::GgufFile;
+use gguf_test_support::{write_safetensors, TempDir};
+
+/// Name, row-major shape and shard of each tensor
+const TENSORS: &[(&str, &[u64], &str)] = &[
+    ("model.embed_tokens.weight", &[8, 4], "model-00001-of-00002.safetensors"),
+    ("model.layers.0.mlp.up_proj.weight", &[6, 4], "model-00001-of-00002.safetensors"),
+    ("model.layers.0.input_layernorm.weight", &[4], "model-00002-of-00002.safetensors"),
+    ("lm_head.weight", &[8, 4], "model-00002-of-00002.safetensors"),
+];
+
+fn values(name: &str, shape: &[u64]) -> Vec<f32> {
+    let n: u64 = shape.iter().product();
+    (0..n).map(|j| j as f32 + name.len() as f32 / 100.0).collect()
+}
+
+/// The F32 bytes of [`values`]
+fn f32_bytes(name: &str, shape: &[u64]) -> Vec<u8> {
+    values(name, shape).iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Writes an F32 safetensors file holding `tensors`
+fn write_shard(path: &Path, tensors: &[(&str, &[u64])]) {
+    let tensors: Vec<_> = tensors
+        .iter()
+        .map(|&(name, shape)| (name, "F32", shape, f32_bytes(name, shape)))
+        .collect();
+    write_safetensors(path, &tensors);
+}
+
+/// Writes the shards of [`TENSORS`] and, with `index`, the index naming them
+fn write_checkpoint(dir: &Path, index: bool) {
+    fs::create_dir_all(dir).unwrap();
+    for shard in ["model-00001-of-00002.safetensors", "model-00002-of-00002.safetensors"] {
+        let tensors: Vec<_> = TENSORS
+            .iter()
+            .filter(|t| t.2 == shard)
+            .map(|&(name, shape, _)| (name, shape))
+            .collect();
+        write_shard(&dir.join(shard), &tensors);
+    }
+    if index {
+        let entries: Vec<_> = TENSORS
+            .iter()
+            .map(|(name, _, shard)| format!(r#""{name}": "{shard}""#))
+            .collect();
+        let json = format!(r#"{{"metadata": {{}}, "weight_map": {{{}}}}}"#, entries.join(", "));
+        fs::write(dir.join("model.safetensors.index.json"), json).unwrap();
+    }
+}
+
+fn convert(input: &Path, output: &Path) -> ExitStatus {
+    Command::new(env!("CARGO_BIN_EXE_gguf-writer"))
+        .arg("--safetensors")
+        .arg(input)
+        .arg("--output")
+        .arg(output)
+        .status()
+        .unwrap()
+}
+
+fn assert_all_tensors(output: &Path) {
+    let file = GgufFile::open(output).unwrap();
+    assert_eq!(file.tensors.len(), TENSORS.len());
+    for &(name, shape, _) in TENSORS {
+        let t = file.tensor(name).unwrap_or_else(|| panic!("{name} missing from the GGUF"));
+        assert_eq!(t.dims, shape.iter().rev().copied().collect::<Vec<_>>(), "{name}");
+        assert!(file.tensor_data(t).unwrap()[..] == f32_bytes(name, shape)[..], "{name} differs");
+    }
+}
+
+#[test]
+fn every_shard_is_converted() {
+    let dir = TempDir::new("sharded");
+    let model = dir.join("model");
+    write_checkpoint(&model, true);
+    let bare = dir.join("bare");
+    write_checkpoint(&bare, false);
+
+    for input in [model.join("model.safetensors.index.json"), model.clone(), bare] {
+        let output = dir.join("out.gguf");
+        assert!(convert(&input, &output).success(), "{} failed", input.display());
+        assert_all_tensors(&output);
+    }
+}
+
+#[test]
+fn missing_and_duplicate_tensors_are_refused() {
+    let dir = TempDir::new("shard-errors");
+    let output = dir.join("out.gguf");
+
+    // A tensor the index lists but its shard lacks
+    let missing = dir.join("missing");
+    write_checkpoint(&missing, true);
+    write_shard(
+        &missing.join("model-00002-of-00002.safetensors"),
+        &[("lm_head.weight", &[8, 4])],
+    );
+    assert!(!convert(&missing, &output).success());
+
+    // The same tensor in both shards, with and without an index
+    for index in [true, false] {
+        let duplicate = dir.join(format!("duplicate-{index}"));
+        write_checkpoint(&duplicate, index);
+        write_shard(
+            &duplicate.join("model-00002-of-00002.safetensors"),
+            &[
+                ("model.layers.0.input_layernorm.weight", &[4]),
+                ("lm_head.weight", &[8, 4]),
+                ("model.embed_tokens.weight", &[8, 4]),
+            ],
+        );
+        assert!(!convert(&duplicate, &output).success(), "duplicate accepted (index: {index})");
+    }
+
+    // A tensor with no float dtype fails the conversion instead of being left out
+    let ints = dir.join("ints");
+    write_checkpoint(&ints, false);
+    write_safetensors(
+        &ints.join("model-00002-of-00002.safetensors"),
+        &[("lm_head.weight", "I32", &[8, 4], vec![0; 128])],
+    );
+    let run = Command::new(env!("CARGO_BIN_EXE_gguf-writer"))
+        .arg("--safetensors")
+        .arg(&ints)
+        .arg("--output")
+        .arg(&output)
+        .output()
+        .unwrap();
+    assert!(!run.status.success());
+    let message = String::from_utf8_lossy(&run.stderr);
+    assert!(message.contains("lm_head.weight") && message.contains("I32"), "{message}");
+}
@@ -0,0 +1,130 @@
+//! Checks Hugging Face tensor names are renamed to llama.cpp's by the table
+//! `config.json` selects, and that gaps in the table are reported.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use gguf_test_support::TempDir;
+use gguf_writer::tensor_map::{gguf_architecture, TensorMap};
+
+fn write_config(dir: &Path, architecture: &str) -> PathBuf {
+    let path = dir.join("config.json");
+    fs::write(&path, format!(r#"{{"architectures": ["{architecture}"], "num_attention_heads": 2}}"#)).unwrap();
+    path
+}
+
+fn rename(map: &TensorMap, names: &[&str]) -> Vec<Option<String>> {
+    map.gguf_names(names.iter().copied()).unwrap()
+}
+
+#[test]
+fn llama_family_names_map_to_gguf() {
+    let dir = TempDir::new("names-llama");
+    for architecture in ["LlamaForCausalLM", "MistralForCausalLM", "Qwen2ForCausalLM", "GemmaForCausalLM"] {
+        let config = write_config(dir.path(), architecture);
+        let map = TensorMap::load(Some(&config), None).unwrap().unwrap();
+        let names = rename(
+            &map,
+            &[
+                "model.embed_tokens.weight",
+                "model.layers.0.self_attn.q_proj.weight",
+                "model.layers.0.self_attn.q_proj.bias",
+                "model.layers.11.mlp.down_proj.weight",
+                "model.layers.3.post_attention_layernorm.weight",
+                "model.layers.3.self_attn.rotary_emb.inv_freq",
+                "model.norm.weight",
+                "lm_head.weight",
+            ],
+        );
+        assert_eq!(
+            names,
+            [
+                Some("token_embd.weight"),
+                Some("blk.0.attn_q.weight"),
+                Some("blk.0.attn_q.bias"),
+                Some("blk.11.ffn_down.weight"),
+                Some("blk.3.ffn_norm.weight"),
+                None,
+                Some("output_norm.weight"),
+                Some("output.weight"),
+            ]
+            .map(|n| n.map(str::to_string)),
+            "{architecture}"
+        );
+    }
+    assert_eq!(gguf_architecture("MistralForCausalLM"), Some("llama"));
+    assert_eq!(gguf_architecture("Qwen2ForCausalLM"), Some("qwen2"));
+}
+
+#[test]
+fn phi3_and_gpt2_fused_projections() {
+    let dir = TempDir::new("names-fused");
+
+    let config = write_config(dir.path(), "Phi3ForCausalLM");
+    let map = TensorMap::load(Some(&config), None).unwrap().unwrap();
+    assert_eq!(
+        rename(&map, &["model.layers.2.self_attn.qkv_proj.weight", "model.layers.2.mlp.gate_up_proj.weight"]),
+        [Some("blk.2.attn_qkv.weight".to_string()), Some("blk.2.ffn_up.weight".to_string())]
+    );
+
+    // With or without the transformer. prefix; attention mask buffers are dropped
+    let config = write_config(dir.path(), "GPT2LMHeadModel");
+    let map = TensorMap::load(Some(&config), None).unwrap().unwrap();
+    assert_eq!(
+        rename(&map, &["transformer.wte.weight", "h.0.attn.c_attn.bias", "transformer.h.1.attn.bias", "ln_f.weight"]),
+        [
+            Some("token_embd.weight".to_string()),
+            Some("blk.0.attn_qkv.bias".to_string()),
+            None,
+            Some("output_norm.weight".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn unmapped_tensors_fail_unless_overridden() {
+    let dir = TempDir::new("names-unmapped");
+    let config = write_config(dir.path(), "LlamaForCausalLM");
+    let map = TensorMap::load(Some(&config), None).unwrap().unwrap();
+
+    let names = ["model.layers.0.mlp.up_proj.weight", "model.layers.0.mlp.router.weight", "vision.patch_embed"];
+    let err = map.gguf_names(names).unwrap_err().to_string();
+    assert!(err.contains("model.layers.0.mlp.router.weight") && err.contains("vision.patch_embed"), "{err}");
+
+    let overrides = dir.join("names.json");
+    fs::write(
+        &overrides,
+        r#"{"model.layers.{bid}.mlp.router": "blk.{bid}.ffn_gate_inp", "vision.patch_embed": null,
+            "model.layers.{bid}.mlp.up_proj": "blk.{bid}.ffn_up_custom",
+            "blk.{bid}.ffn_down": "blk.{bid}.ffn_down"}"#,
+    )
+    .unwrap();
+    let map = TensorMap::load(Some(&config), Some(&overrides)).unwrap().unwrap();
+    assert_eq!(
+        map.gguf_names(names).unwrap(),
+        [Some("blk.0.ffn_up_custom.weight".to_string()), Some("blk.0.ffn_gate_inp.weight".to_string()), None]
+    );
+
+    // Two tensors may not end up with one name
+    let err = map
+        .gguf_names(["model.layers.0.mlp.down_proj.weight", "blk.0.ffn_down.weight"])
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("blk.0.ffn_down.weight"), "{err}");
+}
+
+#[test]
+fn unknown_architectures_need_an_override_file() {
+    let dir = TempDir::new("names-unknown");
+    let config = write_config(dir.path(), "MambaForCausalLM");
+    let err = TensorMap::load(Some(&config), None).err().unwrap().to_string();
+    assert!(err.contains("MambaForCausalLM"), "{err}");
+
+    let overrides = dir.join("names.json");
+    fs::write(&overrides, r#"{"backbone.embeddings": "token_embd"}"#).unwrap();
+    let map = TensorMap::load(Some(&config), Some(&overrides)).unwrap().unwrap();
+    assert_eq!(map.gguf_names(["backbone.embeddings.weight"]).unwrap(), [Some("token_embd.weight".to_string())]);
+
+    // Nothing to go by: names are kept
+    assert!(TensorMap::load(None, None).unwrap().is_none());
+}
@@ -0,0 +1,124 @@
+//! Checks the value changes llama.cpp's converter makes besides renaming:
+//! llama q/k projections are RoPE-permuted, Gemma norms get +1 and GPT-2
+//! Conv1D weights are transposed.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use gguf_core::decoder;
+use gguf_core::file::GgufFile;
+use gguf_test_support::{encode, write_safetensors, TempDir};
+
+/// Writes `model.safetensors` in `dir` from `(name, dtype, shape, values)`
+/// tensors
+fn write_checkpoint(dir: &Path, tensors: &[(&str, &str, &[u64], Vec<f32>)]) {
+    let tensors: Vec<_> = tensors
+        .iter()
+        .map(|&(name, dtype, shape, ref values)| (name, dtype, shape, encode(dtype, values)))
+        .collect();
+    write_safetensors(&dir.join("model.safetensors"), &tensors);
+}
+
+/// Row `r` of a `rows × cols` matrix holds `16 r + c`, exact in BF16 for
+/// up to 16 rows and columns
+fn matrix(rows: u64, cols: u64) -> Vec<f32> {
+    (0..rows * cols).map(|i| (i / cols * 16 + i % cols) as f32).collect()
+}
+
+/// The rows of a `cols`-wide [`matrix`] in the order `rows`
+fn reordered(rows: &[u64], cols: u64) -> Vec<f32> {
+    rows.iter().flat_map(|&r| (0..cols).map(move |c| (r * 16 + c) as f32)).collect()
+}
+
+/// Converts the checkpoint in `dir` with its `config.json` and opens the GGUF
+fn convert(dir: &Path) -> GgufFile {
+    let output = dir.join("out.gguf");
+    let status = Command::new(env!("CARGO_BIN_EXE_gguf-writer"))
+        .arg("--safetensors")
+        .arg(dir.join("model.safetensors"))
+        .arg("--config")
+        .arg(dir.join("config.json"))
+        .arg("--output")
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{} failed", dir.display());
+    GgufFile::open(&output).unwrap()
+}
+
+/// GGUF dims and f32 values of `name`
+fn tensor(file: &GgufFile, name: &str) -> (Vec<u64>, Vec<f32>) {
+    let t = file.tensor(name).unwrap_or_else(|| panic!("{name} missing"));
+    let values = decoder::dequantize(t.ggml_type().unwrap(), &file.tensor_data(t).unwrap(), &t.dims).unwrap();
+    (t.dims.clone(), values)
+}
+
+#[test]
+fn llama_q_and_k_are_rope_permuted() {
+    let dir = TempDir::new("permute");
+    fs::write(
+        dir.join("config.json"),
+        r#"{"architectures": ["LlamaForCausalLM"], "num_attention_heads": 2, "num_key_value_heads": 1}"#,
+    )
+    .unwrap();
+    write_checkpoint(
+        dir.path(),
+        &[
+            ("model.layers.0.self_attn.q_proj.weight", "BF16", &[8, 4], matrix(8, 4)),
+            ("model.layers.0.self_attn.k_proj.weight", "F32", &[4, 4], matrix(4, 4)),
+            ("model.layers.0.self_attn.v_proj.weight", "F32", &[4, 4], matrix(4, 4)),
+        ],
+    );
+
+    let file = convert(dir.path());
+    // Two heads of four rows for q, one head for k: each head's halves are
+    // interleaved, so rows 0 1 | 2 3 become 0 2 1 3
+    assert_eq!(tensor(&file, "blk.0.attn_q.weight"), (vec![4, 8], reordered(&[0, 2, 1, 3, 4, 6, 5, 7], 4)));
+    assert_eq!(tensor(&file, "blk.0.attn_k.weight"), (vec![4, 4], reordered(&[0, 2, 1, 3], 4)));
+    assert_eq!(tensor(&file, "blk.0.attn_v.weight"), (vec![4, 4], matrix(4, 4)));
+
+    // Qwen2 uses NeoX RoPE and keeps Hugging Face's order
+    fs::write(dir.join("config.json"), r#"{"architectures": ["Qwen2ForCausalLM"], "num_attention_heads": 2}"#).unwrap();
+    let file = convert(dir.path());
+    assert_eq!(tensor(&file, "blk.0.attn_q.weight"), (vec![4, 8], matrix(8, 4)));
+}
+
+#[test]
+fn gemma_norms_and_gpt2_conv1d() {
+    let dir = TempDir::new("transforms");
+
+    fs::write(dir.join("config.json"), r#"{"architectures": ["GemmaForCausalLM"], "num_attention_heads": 2}"#).unwrap();
+    let norm = vec![-0.5, 0.0, 0.25, 2.0];
+    write_checkpoint(
+        dir.path(),
+        &[
+            ("model.norm.weight", "BF16", &[4], norm.clone()),
+            ("model.layers.0.input_layernorm.weight", "F32", &[4], norm.clone()),
+            ("model.layers.0.self_attn.q_proj.weight", "F32", &[8, 4], matrix(8, 4)),
+        ],
+    );
+    let file = convert(dir.path());
+    let plus_one: Vec<f32> = norm.iter().map(|v| v + 1.0).collect();
+    assert_eq!(tensor(&file, "output_norm.weight"), (vec![4], plus_one.clone()));
+    assert_eq!(tensor(&file, "blk.0.attn_norm.weight"), (vec![4], plus_one));
+    assert_eq!(tensor(&file, "blk.0.attn_q.weight"), (vec![4, 8], matrix(8, 4)));
+
+    // Conv1D stores [in, out]; llama.cpp wants out rows of in values
+    fs::write(dir.join("config.json"), r#"{"architectures": ["GPT2LMHeadModel"]}"#).unwrap();
+    write_checkpoint(
+        dir.path(),
+        &[
+            ("h.0.attn.c_attn.weight", "F32", &[4, 12], matrix(4, 12)),
+            ("h.0.mlp.c_proj.weight", "F32", &[16, 4], matrix(16, 4)),
+            ("h.0.attn.c_attn.bias", "F32", &[12], matrix(1, 12)),
+        ],
+    );
+    let file = convert(dir.path());
+    let transposed = |rows: u64, cols: u64| -> Vec<f32> {
+        (0..cols).flat_map(|c| (0..rows).map(move |r| (r * 16 + c) as f32)).collect()
+    };
+    assert_eq!(tensor(&file, "blk.0.attn_qkv.weight"), (vec![4, 12], transposed(4, 12)));
+    assert_eq!(tensor(&file, "blk.0.ffn_down.weight"), (vec![16, 4], transposed(16, 4)));
+    assert_eq!(tensor(&file, "blk.0.attn_qkv.bias"), (vec![12], matrix(1, 12)));
+}
@@ -0,0 +1,852 @@
+//! ggml i-quants: IQ4_NL/IQ4_XS map values onto a fixed non-linear set of
+//! 16 levels, while IQ2_XXS–IQ3_S store groups of 8 or 4 magnitudes as an
+//! index into a lattice codebook plus sign bits.
+//!
+//! These are ports of llama.cpp's `quantize_iq*` (what llama-quantize
+//! calls) and produce bit-identical blocks. The codebooks come from
+//! [`gguf_core::iq_grids`]; the lookup tables ggml's `iq2xs_init_impl` and
+//! `iq3xs_init_impl` derive from them are built on first use.
+
+use std::sync::OnceLock;
+
+use gguf_core::iq_grids::{
+    IQ2S_GRID, IQ2XS_GRID, IQ2XXS_GRID, IQ3S_GRID, IQ3XXS_GRID, KVALUES_IQ4NL,
+};
+use half::f16;
+
+use crate::k_quants::{importance, make_qp_quants, nearest_int, sum_squares};
+
+const QK_K: usize = 256;
+const GROUP_MAX_EPS: f32 = 1e-15;
+const GROUP_MAX_EPS_IQ3_XXS: f32 = 1e-8;
+const GROUP_MAX_EPS_IQ2_S: f32 = 1e-8;
+
+/// Coordinate magnitudes of the IQ2 grids, by level
+const IQ2_MAGNITUDES: [u8; 3] = [8, 25, 43];
+/// Coordinate magnitudes of the `IQ3_XXS` grid, by level
+const IQ3XXS_MAGNITUDES: [u8; 8] = [4, 12, 20, 28, 36, 44, 52, 62];
+/// Coordinate magnitudes of the `IQ3_S` grid, by level
+const IQ3S_MAGNITUDES: [u8; 8] = [1, 3, 5, 7, 9, 11, 13, 15];
+
+/// Quantizes to ggml `block_iq4_nl`: an f16 scale and 32 indices into
+/// `KVALUES_IQ4NL`, packed as in `Q4_0`.
+pub fn quantize_tensor_iq4_nl(tensor: &[f32]) -> Vec<u8> {
+    quantize_iq4_nl(tensor, None)
+}
+
+/// `IQ4_NL` for one row with a per-column importance weight in `qw`
+pub fn quantize_row_iq4_nl_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    quantize_iq4_nl(row, Some(qw))
+}
+
+fn quantize_iq4_nl(tensor: &[f32], qw: Option<&[f32]>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(tensor.len() / 32 * 18);
+
+    for (i, x) in tensor.chunks_exact(32).enumerate() {
+        let mut levels = [0u8; 32];
+        let mut scale = [0u8; 1];
+        let d = quantize_iq4_block(x, qw.map(|qw| &qw[32 * i..]), &mut levels, &mut scale);
+
+        out.extend_from_slice(&d.to_le_bytes());
+        pack_iq4(&levels, &mut out);
+    }
+
+    out
+}
+
+/// Quantizes to ggml `block_iq4_xs`: `IQ4_NL` levels in 256-value
+/// super-blocks with an f16 `d` and a 6-bit signed scale per 32 values.
+pub fn quantize_tensor_iq4_xs(tensor: &[f32]) -> Vec<u8> {
+    quantize_iq4_xs(tensor, None)
+}
+
+/// `IQ4_XS` for one row with a per-column importance weight in `qw`
+pub fn quantize_row_iq4_xs_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    quantize_iq4_xs(row, Some(qw))
+}
+
+fn quantize_iq4_xs(tensor: &[f32], qw: Option<&[f32]>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(tensor.len() / QK_K * 136);
+
+    for (i, x) in tensor.chunks_exact(QK_K).enumerate() {
+        let mut levels = [0u8; QK_K];
+        let mut scales = [0u8; QK_K / 32];
+        let d = quantize_iq4_block(x, qw.map(|qw| &qw[QK_K * i..]), &mut levels, &mut scales);
+
+        let mut scales_h = 0u16;
+        let mut scales_l = [0u8; QK_K / 64];
+        for (ib, &ls) in scales.iter().enumerate() {
+            scales_l[ib / 2] |= (ls & 0xF) << (4 * (ib % 2));
+            scales_h |= ((ls >> 4) as u16) << (2 * ib);
+        }
+
+        out.extend_from_slice(&d.to_le_bytes());
+        out.extend_from_slice(&scales_h.to_le_bytes());
+        out.extend_from_slice(&scales_l);
+        pack_iq4(&levels, &mut out);
+    }
+
+    out
+}
+
+/// `quantize_row_iq4_nl_impl` with llama-quantize's 7 extra tries: finds
+/// each 32-value sub-block's scale by trying 15 scales around the one that
+/// maps its largest magnitude to the outermost level. With more than one
+/// sub-block, the scales become 6-bit multiples of the returned `d`, written
+/// to `scales` offset by 32, and `levels` are chosen against those.
+fn quantize_iq4_block(x: &[f32], qw: Option<&[f32]>, levels: &mut [u8], scales: &mut [u8]) -> f16 {
+    const NTRY: i32 = 7;
+    let values = &KVALUES_IQ4NL;
+    let nb = x.len() / 32;
+
+    let mut sigma2 = sum_squares(x);
+    sigma2 *= 2.0 / x.len() as f32;
+
+    let mut sub_scales = [0.0f32; QK_K / 32];
+    let mut max_scale = 0.0f32;
+    let mut amax_scale = 0.0f32;
+    for ib in 0..nb {
+        let xb = &x[32 * ib..32 * ib + 32];
+        let weight: [f32; 32] = match qw {
+            Some(qw) => importance(xb, &qw[32 * ib..], sigma2),
+            None => std::array::from_fn(|j| xb[j] * xb[j]),
+        };
+
+        let mut amax = 0.0f32;
+        let mut max = 0.0f32;
+        for &v in xb {
+            if v.abs() > amax {
+                amax = v.abs();
+                max = v;
+            }
+        }
+        if amax < GROUP_MAX_EPS {
+            continue;
+        }
+
+        let fit = |id: f32| {
+            let mut sumqx = 0.0f32;
+            let mut sumq2 = 0.0f32;
+            for (&v, &w) in xb.iter().zip(&weight) {
+                let q = values[best_index_int8(values, id * v)] as f32;
+                sumqx += w * q * v;
+                sumq2 += w * q * q;
+            }
+            (sumqx, sumq2)
+        };
+
+        let (sumqx, sumq2) = fit(1.0 / (-max / values[0] as f32));
+        let mut d = if sumq2 > 0.0 { sumqx / sumq2 } else { 0.0 };
+        let mut best = d * sumqx;
+        for itry in -NTRY..=NTRY {
+            let (sumqx, sumq2) = fit((itry + values[0] as i32) as f32 / max);
+            if sumq2 > 0.0 && sumqx * sumqx > best * sumq2 {
+                d = sumqx / sumq2;
+                best = d * sumqx;
+            }
+        }
+
+        sub_scales[ib] = d;
+        if d.abs() > amax_scale {
+            amax_scale = d.abs();
+            max_scale = d;
+        }
+    }
+
+    if nb == 1 {
+        let d = sub_scales[0];
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+        for (l, &v) in levels.iter_mut().zip(x) {
+            *l = best_index_int8(values, id * v) as u8;
+        }
+        return f16::from_f32(d);
+    }
+
+    let d = -max_scale / 32.0;
+    let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+    for ib in 0..nb {
+        let ls = nearest_int(id * sub_scales[ib]).clamp(-32, 31);
+        let dl = d * ls as f32;
+        let idl = if dl != 0.0 { 1.0 / dl } else { 0.0 };
+        for j in 32 * ib..32 * ib + 32 {
+            levels[j] = best_index_int8(values, idl * x[j]) as u8;
+        }
+        scales[ib] = (ls + 32) as u8;
+    }
+    f16::from_f32(d)
+}
+
+/// Index of the level in `values` nearest to `x`, by bisection as in
+/// ggml's `best_index_int8`; a tie goes to the upper level
+fn best_index_int8(values: &[i8; 16], x: f32) -> usize {
+    if x <= values[0] as f32 {
+        return 0;
+    }
+    if x >= values[15] as f32 {
+        return 15;
+    }
+    let mut ml = 0;
+    let mut mu = 15;
+    while mu - ml > 1 {
+        let mav = (ml + mu) / 2;
+        if x < values[mav] as f32 {
+            mu = mav;
+        } else {
+            ml = mav;
+        }
+    }
+    if x - (values[mu - 1] as f32) < values[mu] as f32 - x {
+        mu - 1
+    } else {
+        mu
+    }
+}
+
+/// Packs 4-bit indices 32 at a time: byte `j` holds index `j` in its low
+/// nibble and index `j + 16` in its high nibble
+fn pack_iq4(levels: &[u8], out: &mut Vec<u8>) {
+    for l in levels.chunks_exact(32) {
+        out.extend((0..16).map(|j| l[j] | (l[j + 16] << 4)));
+    }
+}
+
+/// Quantizes one row to ggml `block_iq2_xxs` with a per-column importance
+/// weight in `qw`: per 32 values, four indices into the 256-point
+/// `IQ2XXS_GRID`, four 7-bit sign indices and a 4-bit scale, under an f16
+/// `d`. Like llama.cpp, there is no unweighted form.
+pub fn quantize_row_iq2_xxs_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    let grid = iq2xxs_lattice();
+    let mut out = Vec::with_capacity(row.len() / QK_K * 66);
+
+    for (xbl, qw) in row.chunks_exact(QK_K).zip(qw.chunks_exact(QK_K)) {
+        let sigma2 = sum_squares(xbl) / QK_K as f32;
+        let mut q2 = [0u32; QK_K / 16];
+        let mut scales = [0.0f32; QK_K / 32];
+        let mut max_scale = 0.0f32;
+
+        for ib in 0..QK_K / 32 {
+            let xb = &xbl[32 * ib..32 * ib + 32];
+            let weight: [f32; 32] = importance(xb, &qw[32 * ib..], sigma2);
+            let waux = weight.map(f32::sqrt);
+            let mut xval = [0.0f32; 32];
+            let mut signs = [0u8; 4];
+            for (k, s) in signs.iter_mut().enumerate() {
+                *s = fold_even_signs(&xb[8 * k..8 * k + 8], &weight[8 * k..], &mut xval[8 * k..8 * k + 8]);
+            }
+            if max_of(&xval) < GROUP_MAX_EPS {
+                continue;
+            }
+
+            let mut l = [0u8; 32];
+            let mut scale = make_qp_quants(4, &xval, &mut l, &weight);
+            let eff_max = scale * 3.0;
+            if eff_max <= 0.0 {
+                continue;
+            }
+            let mut best = 0.0f32;
+            for is in -6..=6 {
+                let id = (5.0 + is as f32 * 0.1) / eff_max;
+                let mut laux = [0u8; 32];
+                for k in 0..4 {
+                    let g = 8 * k..8 * k + 8;
+                    grid.snap(&xval[g.clone()], &waux[g.clone()], id, 1.0 / id, &mut laux[g]);
+                }
+                let (sumqx, sumq2) = fit(&xval, &weight, &laux);
+                if sumq2 > 0.0 && sumqx * sumqx > best * sumq2 {
+                    scale = sumqx / sumq2;
+                    best = scale * sumqx;
+                    l = laux;
+                }
+            }
+            if scale > 0.0 {
+                let id = 1.0 / scale;
+                for k in 0..4 {
+                    let g = 8 * k..8 * k + 8;
+                    grid.snap(&xval[g.clone()], &waux[g.clone()], id, scale, &mut l[g]);
+                }
+                let (sumqx, sumq2) = fit(&xval, &weight, &l);
+                if sumq2 > 0.0 {
+                    scale = sumqx / sumq2;
+                }
+            }
+            if scale < 0.0 {
+                scale = -scale;
+                signs = signs.map(|s| !s & 127);
+            }
+
+            for k in 0..4 {
+                q2[2 * ib] |= (grid.index(&l[8 * k..8 * k + 8]) as u32) << (8 * k);
+                q2[2 * ib + 1] |= (signs[k] as u32) << (7 * k);
+            }
+            scales[ib] = scale;
+            max_scale = max_scale.max(scale);
+        }
+
+        if max_scale == 0.0 {
+            out.extend_from_slice(&[0; 66]);
+            continue;
+        }
+        let d = max_scale / 31.0;
+        let id = 1.0 / d;
+        for (ib, &scale) in scales.iter().enumerate() {
+            q2[2 * ib + 1] |= (scale_level(id, scale) as u32) << 28;
+        }
+
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        out.extend(q2.iter().flat_map(|q| q.to_le_bytes()));
+    }
+
+    out
+}
+
+/// Quantizes one row to ggml `block_iq2_xs` with a per-column importance
+/// weight in `qw`: per 16 values, two u16s of a 9-bit `IQ2XS_GRID` index and
+/// a 7-bit sign index, then 4-bit scales under an f16 `d`. There is no
+/// unweighted form.
+pub fn quantize_row_iq2_xs_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    let grid = iq2xs_lattice();
+    let mut out = Vec::with_capacity(row.len() / QK_K * 74);
+
+    for (xbl, qw) in row.chunks_exact(QK_K).zip(qw.chunks_exact(QK_K)) {
+        let sigma2 = sum_squares(xbl) / QK_K as f32;
+        let mut q2 = [0u16; QK_K / 8];
+        let mut scales = [0.0f32; QK_K / 16];
+        let mut max_scale = 0.0f32;
+
+        for ib in 0..QK_K / 16 {
+            let xb = &xbl[16 * ib..16 * ib + 16];
+            let weight: [f32; 16] = importance(xb, &qw[16 * ib..], sigma2);
+            let mut xval = [0.0f32; 16];
+            let mut signs = [0u8; 2];
+            for (k, s) in signs.iter_mut().enumerate() {
+                *s = fold_even_signs(&xb[8 * k..8 * k + 8], &weight[8 * k..], &mut xval[8 * k..8 * k + 8]);
+            }
+            let max = max_of(&xval);
+            if max < GROUP_MAX_EPS {
+                continue;
+            }
+
+            let mut l = [0u8; 16];
+            let mut scale = grid.search(&xval, &weight, max, (9, 0.1), false, &mut l);
+            if scale < 0.0 {
+                scale = -scale;
+                signs = signs.map(|s| !s & 127);
+            }
+
+            for k in 0..2 {
+                q2[2 * ib + k] = grid.index(&l[8 * k..8 * k + 8]) as u16 | (signs[k] as u16) << 9;
+            }
+            scales[ib] = scale;
+            max_scale = max_scale.max(scale);
+        }
+
+        if max_scale == 0.0 {
+            out.extend_from_slice(&[0; 74]);
+            continue;
+        }
+        let d = max_scale / 31.0;
+        let id = 1.0 / d;
+
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        out.extend(q2.iter().flat_map(|q| q.to_le_bytes()));
+        out.extend(scales.chunks_exact(2).map(|s| scale_level(id, s[0]) | scale_level(id, s[1]) << 4));
+    }
+
+    out
+}
+
+/// Quantizes one row to ggml `block_iq2_s` with a per-column importance
+/// weight in `qw`: per 8 values, a 10-bit `IQ2S_GRID` index and a full sign
+/// byte, with 4-bit scales per 16 values under an f16 `d`. llama-quantize
+/// requires an imatrix for this type, so only the weighted form is ported.
+pub fn quantize_row_iq2_s_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    let grid = iq2s_lattice();
+    let mut out = Vec::with_capacity(row.len() / QK_K * 82);
+
+    for (xbl, qw) in row.chunks_exact(QK_K).zip(qw.chunks_exact(QK_K)) {
+        let sigma2 = 2.0 * sum_squares(xbl) / QK_K as f32;
+        let mut qs = [0u8; QK_K / 4];
+        let mut qh = [0u8; QK_K / 32];
+        let mut scales = [0.0f32; QK_K / 16];
+        let mut max_scale = 0.0f32;
+
+        for ib in 0..QK_K / 16 {
+            let xb = &xbl[16 * ib..16 * ib + 16];
+            let weight: [f32; 16] = importance(xb, &qw[16 * ib..], sigma2);
+            let mut xval = [0.0f32; 16];
+            let mut signs = [0u8; 2];
+            for (k, s) in signs.iter_mut().enumerate() {
+                *s = fold_signs(&xb[8 * k..8 * k + 8], &mut xval[8 * k..8 * k + 8]);
+            }
+            let max = max_of(&xval);
+            if max < GROUP_MAX_EPS_IQ2_S {
+                continue;
+            }
+
+            let mut l = [0u8; 16];
+            let mut scale = grid.search(&xval, &weight, max, (9, 0.1), false, &mut l);
+            if scale < 0.0 {
+                scale = -scale;
+                signs = signs.map(|s| !s);
+            }
+
+            for k in 0..2 {
+                let i8 = 2 * ib + k;
+                let index = grid.index(&l[8 * k..8 * k + 8]);
+                qs[i8] = index as u8;
+                qh[i8 / 4] |= ((index >> 8) as u8) << (2 * (i8 % 4));
+                qs[QK_K / 8 + i8] = signs[k];
+            }
+            scales[ib] = scale;
+            max_scale = max_scale.max(scale);
+        }
+
+        let mut packed_scales = [0u8; QK_K / 32];
+        let mut d = 0.0f32;
+        if max_scale != 0.0 {
+            d = max_scale / 31.0;
+            let id = 1.0 / d;
+            for (p, s) in packed_scales.iter_mut().zip(scales.chunks_exact(2)) {
+                *p = scale_level(id, s[0]) | scale_level(id, s[1]) << 4;
+            }
+            d *= 0.9875;
+        }
+
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        out.extend_from_slice(&qs);
+        out.extend_from_slice(&qh);
+        out.extend_from_slice(&packed_scales);
+    }
+
+    out
+}
+
+/// Quantizes to ggml `block_iq3_xxs`: per 32 values, eight indices into
+/// the 256-point `IQ3XXS_GRID` and a u32 of four 7-bit sign indices and a
+/// 4-bit scale, under an f16 `d`.
+pub fn quantize_tensor_iq3_xxs(tensor: &[f32]) -> Vec<u8> {
+    quantize_iq3_xxs(tensor, None)
+}
+
+/// `IQ3_XXS` for one row with a per-column importance weight in `qw`
+pub fn quantize_row_iq3_xxs_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    quantize_iq3_xxs(row, Some(qw))
+}
+
+fn quantize_iq3_xxs(tensor: &[f32], qw: Option<&[f32]>) -> Vec<u8> {
+    let grid = iq3xxs_lattice();
+    let mut out = Vec::with_capacity(tensor.len() / QK_K * 98);
+
+    for (i, xbl) in tensor.chunks_exact(QK_K).enumerate() {
+        let sigma2 = 2.0 * sum_squares(xbl) / QK_K as f32;
+        let mut q3 = [0u8; QK_K / 4 + QK_K / 8];
+        let mut scales = [0.0f32; QK_K / 32];
+        let mut max_scale = 0.0f32;
+
+        for ib in 0..QK_K / 32 {
+            let xb = &xbl[32 * ib..32 * ib + 32];
+            let weight: [f32; 32] = iq3_weights(xb, qw.map(|qw| &qw[QK_K * i + 32 * ib..]), sigma2);
+            let mut xval = [0.0f32; 32];
+            let mut signs = [0u8; 4];
+            for (k, s) in signs.iter_mut().enumerate() {
+                *s = fold_even_signs(&xb[8 * k..8 * k + 8], &weight[8 * k..], &mut xval[8 * k..8 * k + 8]);
+            }
+            let max = max_of(&xval);
+            if max < GROUP_MAX_EPS_IQ3_XXS {
+                continue;
+            }
+
+            let mut l = [0u8; 32];
+            let mut scale = grid.search(&xval, &weight, max, (15, 0.2), false, &mut l);
+            if scale < 0.0 {
+                scale = -scale;
+                signs = signs.map(|s| !s & 127);
+            }
+
+            for k in 0..8 {
+                q3[8 * ib + k] = grid.index(&l[4 * k..4 * k + 4]) as u8;
+            }
+            let signs = signs[0] as u32 | (signs[1] as u32) << 7 | (signs[2] as u32) << 14 | (signs[3] as u32) << 21;
+            q3[QK_K / 4 + 4 * ib..QK_K / 4 + 4 * ib + 4].copy_from_slice(&signs.to_le_bytes());
+            scales[ib] = scale;
+            max_scale = max_scale.max(scale);
+        }
+
+        if max_scale == 0.0 {
+            out.extend_from_slice(&[0; 98]);
+            continue;
+        }
+        let d = max_scale / 31.0;
+        let id = 1.0 / d;
+        for (ib, &scale) in scales.iter().enumerate() {
+            q3[QK_K / 4 + 4 * ib + 3] |= scale_level(id, scale) << 4;
+        }
+
+        out.extend_from_slice(&f16::from_f32(d * 1.0125).to_le_bytes());
+        out.extend_from_slice(&q3);
+    }
+
+    out
+}
+
+/// Quantizes to ggml `block_iq3_s`: per 4 values a 9-bit `IQ3S_GRID`
+/// index, split into low bytes and packed high bits, then sign bytes and
+/// odd 4-bit scales per 32 values under an f16 `d`.
+///
+/// As in ggml, a sub-block that is entirely zero writes no indices or signs
+/// and the following sub-blocks' bytes move up to fill the gap.
+pub fn quantize_tensor_iq3_s(tensor: &[f32]) -> Vec<u8> {
+    quantize_iq3_s(tensor, None)
+}
+
+/// `IQ3_S` for one row with a per-column importance weight in `qw`
+pub fn quantize_row_iq3_s_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    quantize_iq3_s(row, Some(qw))
+}
+
+fn quantize_iq3_s(tensor: &[f32], qw: Option<&[f32]>) -> Vec<u8> {
+    let grid = iq3s_lattice();
+    let mut out = Vec::with_capacity(tensor.len() / QK_K * 110);
+
+    for (i, xbl) in tensor.chunks_exact(QK_K).enumerate() {
+        let sigma2 = 2.0 * sum_squares(xbl) / QK_K as f32;
+        let mut qs = [0u8; QK_K / 4];
+        let mut qh = [0u8; QK_K / 32];
+        let mut signs = [0u8; QK_K / 8];
+        let mut written = 0;
+        let mut scales = [0.0f32; QK_K / 32];
+        let mut max_scale = 0.0f32;
+
+        for ib in 0..QK_K / 32 {
+            let xb = &xbl[32 * ib..32 * ib + 32];
+            let weight: [f32; 32] = iq3_weights(xb, qw.map(|qw| &qw[QK_K * i + 32 * ib..]), sigma2);
+            let mut xval = [0.0f32; 32];
+            let mut block_signs = [0u8; 4];
+            for (k, s) in block_signs.iter_mut().enumerate() {
+                *s = fold_signs(&xb[8 * k..8 * k + 8], &mut xval[8 * k..8 * k + 8]);
+            }
+            let max = max_of(&xval);
+            if max == 0.0 {
+                continue;
+            }
+
+            let mut l = [0u8; 32];
+            let mut scale = grid.search(&xval, &weight, max, (9, 0.2), true, &mut l);
+            if scale < 0.0 {
+                scale = -scale;
+                block_signs = block_signs.map(|s| !s);
+            }
+
+            for k in 0..8 {
+                let index = grid.index(&l[4 * k..4 * k + 4]);
+                qs[8 * written + k] = index as u8;
+                qh[ib] |= ((index >> 8) as u8) << k;
+            }
+            signs[4 * written..4 * written + 4].copy_from_slice(&block_signs);
+            written += 1;
+            scales[ib] = scale;
+            max_scale = max_scale.max(scale);
+        }
+
+        let mut packed_scales = [0u8; QK_K / 64];
+        let mut d = 0.0f32;
+        if max_scale != 0.0 {
+            d = max_scale / 31.0;
+            let id = 1.0 / d;
+            for (p, s) in packed_scales.iter_mut().zip(scales.chunks_exact(2)) {
+                *p = scale_level(id, s[0]) | scale_level(id, s[1]) << 4;
+            }
+            d *= 1.033;
+        }
+
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        out.extend_from_slice(&qs);
+        out.extend_from_slice(&qh);
+        out.extend_from_slice(&signs);
+        out.extend_from_slice(&packed_scales);
+    }
+
+    out
+}
+
+/// Weights of the IQ3 searches: the importance weights with `qw`, otherwise
+/// each value's square
+fn iq3_weights(xb: &[f32], qw: Option<&[f32]>, sigma2: f32) -> [f32; 32] {
+    match qw {
+        Some(qw) => importance(xb, qw, sigma2),
+        None => std::array::from_fn(|i| xb[i] * xb[i]),
+    }
+}
+
+/// Stores eight values as magnitudes in `xval` and returns the sign byte
+/// with bit `i` set where value `i` is negative
+fn fold_signs(xb: &[f32], xval: &mut [f32]) -> u8 {
+    let mut s = 0u8;
+    for (i, (&x, v)) in xb.iter().zip(xval).enumerate() {
+        if x >= 0.0 {
+            *v = x;
+        } else {
+            *v = -x;
+            s |= 1 << i;
+        }
+    }
+    s
+}
+
+/// [`fold_signs`] for the formats that keep 7 sign bits per 8 values and
+/// imply the eighth from an even count of negatives. With an odd count, the
+/// value with the smallest weighted square keeps the wrong sign: its
+/// magnitude is negated instead.
+fn fold_even_signs(xb: &[f32], weight: &[f32], xval: &mut [f32]) -> u8 {
+    let mut s = fold_signs(xb, xval);
+    if s.count_ones() % 2 == 1 {
+        let mut imin = 0;
+        let mut min = weight[0] * xb[0] * xb[0];
+        for i in 1..8 {
+            let ax = weight[i] * xb[i] * xb[i];
+            if ax < min {
+                min = ax;
+                imin = i;
+            }
+        }
+        xval[imin] = -xval[imin];
+        s ^= 1 << imin;
+    }
+    s & 127
+}
+
+fn max_of(x: &[f32]) -> f32 {
+    let mut max = x[0];
+    for &v in &x[1..] {
+        if v > max {
+            max = v;
+        }
+    }
+    max
+}
+
+/// Weighted least-squares sums `(Σ w·x·q, Σ w·q²)` of magnitudes `xval`
+/// against levels `l`, where level `l` stands for `2l + 1`
+fn fit(xval: &[f32], weight: &[f32], l: &[u8]) -> (f32, f32) {
+    let mut sumqx = 0.0f32;
+    let mut sumq2 = 0.0f32;
+    for i in 0..xval.len() {
+        let w = weight[i];
+        let q = (2 * l[i] + 1) as f32;
+        sumqx += w * xval[i] * q;
+        sumq2 += w * q * q;
+    }
+    (sumqx, sumq2)
+}
+
+/// 4-bit level of a sub-block scale under the super-block scale `1 / id`,
+/// where level `l` stands for `2l + 1`
+fn scale_level(id: f32, scale: f32) -> u8 {
+    nearest_int(0.5 * (id * scale - 1.0)).clamp(0, 15) as u8
+}
+
+/// An i-quant codebook as its quantizers search it, in groups of `N`
+/// coordinates. Each coordinate has a level, and level `l` stands for the
+/// value `2l + 1` times the scale.
+struct Lattice<const N: usize> {
+    /// Every grid point as its levels
+    points: Vec<[u8; N]>,
+    /// Levels a coordinate can take
+    levels: i32,
+    /// Bits per level in a packed vector of levels
+    bits: usize,
+    /// What each packed vector of levels is
+    cells: Vec<Cell>,
+}
+
+enum Cell {
+    /// Grid point `i`
+    Point(u16),
+    /// Off the grid: the nearest points, closest first
+    Near(Vec<u16>),
+}
+
+impl<const N: usize> Lattice<N> {
+    /// Builds the tables of `iq2xs_init_impl`/`iq3xs_init_impl` for a grid
+    /// whose coordinates take the given `magnitudes`, one per level. Vectors
+    /// off the grid keep every point at the nearest `shells` distances.
+    fn new(grid: impl IntoIterator<Item = u64>, magnitudes: &[u8], shells: usize) -> Self {
+        let levels = magnitudes.len() as i32;
+        let bits = magnitudes.len().next_power_of_two().trailing_zeros() as usize;
+        let points: Vec<[u8; N]> = grid
+            .into_iter()
+            .map(|p| {
+                std::array::from_fn(|i| {
+                    let m = (p >> (8 * i)) as u8;
+                    magnitudes.iter().position(|&v| v == m).expect("magnitude of the grid") as u8
+                })
+            })
+            .collect();
+
+        let mut on_grid = vec![None; 1 << (bits * N)];
+        for (i, p) in points.iter().enumerate() {
+            on_grid[pack(p, bits)] = Some(i as u16);
+        }
+
+        let cells = on_grid
+            .into_iter()
+            .enumerate()
+            .map(|(u, index)| {
+                if let Some(i) = index {
+                    return Cell::Point(i);
+                }
+                let v: [i32; N] = std::array::from_fn(|i| (u >> (bits * i)) as i32 & ((1 << bits) - 1));
+                if v.iter().any(|&l| l >= levels) {
+                    // Rounding never produces these vectors
+                    return Cell::Near(Vec::new());
+                }
+
+                let mut dist: Vec<(i32, u16)> = points
+                    .iter()
+                    .enumerate()
+                    .map(|(j, p)| {
+                        let d2 = p.iter().zip(&v).map(|(&a, &b)| (a as i32 - b) * (a as i32 - b)).sum();
+                        (d2, j as u16)
+                    })
+                    .collect();
+                dist.sort_unstable();
+
+                let mut near = Vec::new();
+                let mut d2 = dist[0].0;
+                let mut nhave = 1;
+                for &(d, j) in &dist {
+                    if d > d2 {
+                        if nhave == shells {
+                            break;
+                        }
+                        d2 = d;
+                        nhave += 1;
+                    }
+                    near.push(j);
+                }
+                Cell::Near(near)
+            })
+            .collect();
+
+        Lattice {
+            points,
+            levels,
+            bits,
+            cells,
+        }
+    }
+
+    /// Rounds magnitudes `xval` to levels at inverse scale `id`. If those
+    /// are off the grid, `l` gets the neighbour closest to `xval` at `scale`
+    /// under `weight` instead. Returns whether the rounding was on the grid.
+    fn snap(&self, xval: &[f32], weight: &[f32], id: f32, scale: f32, l: &mut [u8]) -> bool {
+        for (l, &x) in l.iter_mut().zip(xval) {
+            *l = nearest_int(0.5 * (id * x - 1.0)).clamp(0, self.levels - 1) as u8;
+        }
+        let Cell::Near(near) = &self.cells[pack(l, self.bits)] else {
+            return true;
+        };
+
+        let mut best_d2 = f32::MAX;
+        let mut best = near[0] as usize;
+        for &j in near {
+            let p = &self.points[j as usize];
+            let mut d2 = 0.0f32;
+            for i in 0..N {
+                let diff = scale * (2 * p[i] + 1) as f32 - xval[i];
+                d2 += weight[i] * diff * diff;
+            }
+            if d2 < best_d2 {
+                best_d2 = d2;
+                best = j as usize;
+            }
+        }
+        l.copy_from_slice(&self.points[best]);
+        false
+    }
+
+    /// Scale search shared by `IQ2_XS`, `IQ2_S` and the IQ3 types: tries
+    /// inverse scales `(2 * levels - 1 + is * step) / max` for `is` in
+    /// `-steps..=steps`, snapping each group to the grid, and keeps the best
+    /// weighted fit. Groups that were off the grid on the best try are then
+    /// snapped again at the fitted scale, which `IQ3_S` (`resnap_all`) does
+    /// for every group unless all were on the grid. Returns the scale and
+    /// leaves on-grid levels in `l`.
+    fn search(&self, xval: &[f32], weight: &[f32], max: f32, (steps, step): (i32, f32), resnap_all: bool, l: &mut [u8]) -> f32 {
+        let waux: Vec<f32> = weight.iter().map(|w| w.sqrt()).collect();
+        let groups = xval.len() / N;
+        let group = |k: usize| N * k..N * k + N;
+
+        let mut best = 0.0f32;
+        let mut scale = max / (2 * self.levels - 1) as f32;
+        let mut on_grid = [!resnap_all; 8];
+        let mut laux = vec![0u8; xval.len()];
+        for is in -steps..=steps {
+            let id = ((2 * self.levels - 1) as f32 + is as f32 * step) / max;
+            let mut on_grid_aux = [true; 8];
+            for k in 0..groups {
+                on_grid_aux[k] = self.snap(&xval[group(k)], &waux[group(k)], id, 1.0 / id, &mut laux[group(k)]);
+            }
+            let (sumqx, sumq2) = fit(xval, weight, &laux);
+            if sumq2 > 0.0 && sumqx * sumqx > best * sumq2 {
+                scale = sumqx / sumq2;
+                best = scale * sumqx;
+                l.copy_from_slice(&laux);
+                on_grid = on_grid_aux;
+            }
+        }
+
+        if on_grid[..groups].contains(&false) && scale > 0.0 {
+            let id = 1.0 / scale;
+            for k in 0..groups {
+                if on_grid[k] && !resnap_all {
+                    continue;
+                }
+                self.snap(&xval[group(k)], &waux[group(k)], id, scale, &mut l[group(k)]);
+            }
+            let (sumqx, sumq2) = fit(xval, weight, l);
+            if sumq2 > 0.0 {
+                scale = sumqx / sumq2;
+            }
+        }
+        scale
+    }
+
+    /// Grid index of levels that are on the grid
+    fn index(&self, l: &[u8]) -> usize {
+        match self.cells[pack(l, self.bits)] {
+            Cell::Point(i) => i as usize,
+            Cell::Near(_) => panic!("levels {l:?} are not on the grid"),
+        }
+    }
+}
+
+/// Packs a vector of levels `bits` per coordinate, first coordinate lowest
+fn pack(l: &[u8], bits: usize) -> usize {
+    l.iter().enumerate().fold(0, |u, (i, &v)| u | (v as usize) << (bits * i))
+}
+
+fn iq2xxs_lattice() -> &'static Lattice<8> {
+    static LATTICE: OnceLock<Lattice<8>> = OnceLock::new();
+    LATTICE.get_or_init(|| Lattice::new(IQ2XXS_GRID, &IQ2_MAGNITUDES, 2))
+}
+
+fn iq2xs_lattice() -> &'static Lattice<8> {
+    static LATTICE: OnceLock<Lattice<8>> = OnceLock::new();
+    LATTICE.get_or_init(|| Lattice::new(IQ2XS_GRID, &IQ2_MAGNITUDES, 2))
+}
+
+fn iq2s_lattice() -> &'static Lattice<8> {
+    static LATTICE: OnceLock<Lattice<8>> = OnceLock::new();
+    LATTICE.get_or_init(|| Lattice::new(IQ2S_GRID, &IQ2_MAGNITUDES, 1))
+}
+
+fn iq3xxs_lattice() -> &'static Lattice<4> {
+    static LATTICE: OnceLock<Lattice<4>> = OnceLock::new();
+    LATTICE.get_or_init(|| Lattice::new(IQ3XXS_GRID.map(u64::from), &IQ3XXS_MAGNITUDES, 2))
+}
+
+fn iq3s_lattice() -> &'static Lattice<4> {
+    static LATTICE: OnceLock<Lattice<4>> = OnceLock::new();
+    LATTICE.get_or_init(|| Lattice::new(IQ3S_GRID.map(u64::from), &IQ3S_MAGNITUDES, 3))
+}
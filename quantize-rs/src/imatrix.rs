@@ -0,0 +1,215 @@
+//! Importance matrices from llama.cpp's `llama-imatrix`: for each weight
+//! tensor, the mean squared activation feeding each of its columns, used to
+//! weight the quantizers' scale searches towards the columns that matter.
+//!
+//! Both file formats are read. The legacy `imatrix.dat` is a count of
+//! entries, then per entry a length-prefixed name, the number of calls, the
+//! number of values and the summed squares, optionally followed by the chunk
+//! count and dataset name. The GGUF format stores `<name>.in_sum2` and
+//! `<name>.counts` tensors with one row, and one count, per expert.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use gguf_core::file::GgufFile;
+use gguf_core::types::{GGUFValue, GgmlType, TensorInfo};
+
+pub struct Imatrix {
+    entries: HashMap<String, Vec<f32>>,
+    /// Calibration dataset the statistics were collected on, if recorded
+    pub dataset: Option<String>,
+    /// Number of calibration chunks, if recorded
+    pub chunk_count: Option<u32>,
+}
+
+impl Imatrix {
+    /// Loads a GGUF or legacy imatrix, telling them apart by the GGUF magic
+    pub fn load(path: &Path) -> io::Result<Imatrix> {
+        let bytes = fs::read(path)?;
+        let imatrix = if bytes.starts_with(b"GGUF") {
+            Imatrix::from_gguf(path)?
+        } else {
+            Imatrix::from_legacy(&bytes)?
+        };
+
+        if imatrix.entries.is_empty() {
+            return Err(invalid(format!("no imatrix data in {}", path.display())));
+        }
+        if let Some(name) = imatrix
+            .entries
+            .iter()
+            .find_map(|(name, v)| v.iter().any(|w| !w.is_finite()).then_some(name))
+        {
+            return Err(invalid(format!("imatrix entry {name} contains non-finite values")));
+        }
+        Ok(imatrix)
+    }
+
+    fn from_legacy(bytes: &[u8]) -> io::Result<Imatrix> {
+        let mut r = Cursor(bytes);
+        let n_entries = r.i32("entry count")?;
+
+        let mut entries = HashMap::new();
+        for _ in 0..n_entries {
+            let len = r.i32("name length")?;
+            let name = String::from_utf8_lossy(r.take(len.max(0) as usize, "name")?).into_owned();
+            let ncall = r.i32("call count")?;
+            let nval = r.i32("value count")?;
+            if nval < 1 {
+                return Err(invalid(format!("imatrix entry {name} has no values")));
+            }
+
+            let mut values: Vec<f32> = r
+                .take(nval as usize * 4, "values")?
+                .chunks_exact(4)
+                .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .collect();
+            if ncall > 0 {
+                for v in &mut values {
+                    *v /= ncall as f32;
+                }
+            }
+            entries.insert(name, values);
+        }
+
+        // The trailing chunk count and dataset name are optional
+        let chunk_count = r.i32("chunk count").ok().map(|n| n as u32);
+        let dataset = r
+            .i32("dataset length")
+            .ok()
+            .filter(|&len| len > 0)
+            .and_then(|len| r.take(len as usize, "dataset").ok())
+            .map(|d| String::from_utf8_lossy(d).into_owned());
+
+        Ok(Imatrix {
+            entries,
+            dataset,
+            chunk_count,
+        })
+    }
+
+    fn from_gguf(path: &Path) -> io::Result<Imatrix> {
+        let file = GgufFile::open(path)?;
+
+        let mut entries = HashMap::new();
+        for sums in &file.tensors {
+            let Some(name) = sums.name.strip_suffix(".in_sum2") else {
+                continue;
+            };
+            let Some(counts) = file.tensor(&format!("{name}.counts")) else {
+                return Err(invalid(format!("imatrix has sums but no counts for {name}")));
+            };
+            if sums.ggml_type() != Some(GgmlType::F32) || counts.ggml_type() != Some(GgmlType::F32) {
+                return Err(invalid(format!("imatrix sums and counts for {name} must be F32")));
+            }
+
+            let sums_data = floats(&file.tensor_data(sums)?);
+            let counts_data = floats(&file.tensor_data(counts)?);
+            let row_len = sums.dims.first().copied().unwrap_or(1) as usize;
+            if row_len == 0 {
+                return Err(invalid(format!("imatrix sums for {name} have empty rows")));
+            }
+            if counts_data.len().checked_mul(row_len) != Some(sums_data.len()) {
+                return Err(invalid(format!("imatrix counts for {name} do not match its sums")));
+            }
+
+            // One row of sums per expert; a row that saw no activations
+            // during calibration gets uniform weights
+            let mut values = Vec::with_capacity(sums_data.len());
+            for (row, &count) in sums_data.chunks_exact(row_len).zip(&counts_data) {
+                let count = count.round();
+                if count > 0.0 {
+                    values.extend(row.iter().map(|&s| s / count));
+                } else {
+                    values.extend(std::iter::repeat_n(1.0, row_len));
+                }
+            }
+            entries.insert(name.to_string(), values);
+        }
+
+        let dataset = match file.metadata.get("imatrix.datasets") {
+            Some(GGUFValue::Array(_, items)) => {
+                let names: Vec<_> = items
+                    .iter()
+                    .filter_map(|v| match v {
+                        GGUFValue::String(s) => Some(s.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                (!names.is_empty()).then(|| names.join(", "))
+            }
+            _ => None,
+        };
+        let chunk_count = match file.metadata.get("imatrix.chunk_count") {
+            Some(GGUFValue::U32(n)) => Some(*n),
+            _ => None,
+        };
+
+        Ok(Imatrix {
+            entries,
+            dataset,
+            chunk_count,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Column weights for `tensor`: `dims[0]` values per expert. A tensor
+    /// with no entry gets `Ok(None)`; an entry of the wrong size is an error,
+    /// except for token embeddings, which llama-imatrix never sees as a
+    /// matrix multiplication and so often records with another shape.
+    pub fn weights_for(&self, tensor: &TensorInfo) -> Result<Option<&[f32]>, String> {
+        let Some(values) = self.entries.get(&tensor.name) else {
+            return Ok(None);
+        };
+
+        let n_per_row = tensor.dims.first().copied().unwrap_or(1);
+        let n_expert = tensor.dims.get(2).copied().unwrap_or(1);
+        if values.len() as u64 == n_per_row * n_expert {
+            Ok(Some(values))
+        } else if tensor.name == "token_embd.weight" || tensor.name == "per_layer_token_embd.weight" {
+            Ok(None)
+        } else {
+            Err(format!(
+                "imatrix has {} values for {}, expected {}",
+                values.len(),
+                tensor.name,
+                n_per_row * n_expert
+            ))
+        }
+    }
+}
+
+/// Little-endian reads over the legacy format
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize, what: &str) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid(format!("imatrix truncated reading {what}")));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn i32(&mut self, what: &str) -> io::Result<i32> {
+        let b = self.take(4, what)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+fn floats(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,842 @@
+//! ggml K-quants: 256-value super-blocks split into sub-blocks of 16 or 32
+//! values, each with its own quantized scale (and, for Q2_K/Q4_K/Q5_K, min).
+//!
+//! These are ports of llama.cpp's `quantize_row_q*_K_ref`, including the
+//! error-minimising scale searches, and produce bit-identical blocks. The
+//! `_weighted` variants port `quantize_row_q*_K_impl`, which weight the
+//! searches by an importance matrix and work one row at a time.
+
+use half::f16;
+
+const QK_K: usize = 256;
+const GROUP_MAX_EPS: f32 = 1e-15;
+
+/// Rounds to the nearest integer, ties to even, exactly as ggml's
+/// `nearest_int` does by adding 1.5 * 2^23
+pub(crate) fn nearest_int(fval: f32) -> i32 {
+    let val = fval + 12582912.0;
+    (val.to_bits() & 0x007f_ffff) as i32 - 0x0040_0000
+}
+
+/// Symmetric scale search used by Q6_K and the weighted Q3_K, Q4_0 and
+/// Q5_0: tries 18 scales around `-nmax / max` and keeps the one with the
+/// smallest weighted squared error. Levels are written to `out` offset by
+/// `nmax`. Without `qw` each value is weighted by its square.
+pub(crate) fn make_qx_quants(nmax: i32, x: &[f32], out: &mut [i8], qw: Option<&[f32]>) -> f32 {
+    let mut max = 0.0f32;
+    let mut amax = 0.0f32;
+    for &v in x {
+        if v.abs() > amax {
+            amax = v.abs();
+            max = v;
+        }
+    }
+    if amax < GROUP_MAX_EPS {
+        out.fill(0);
+        return 0.0;
+    }
+
+    let weight = |i: usize| qw.map_or(x[i] * x[i], |qw| qw[i]);
+    let level = |iscale: f32, v: f32| nearest_int(iscale * v).clamp(-nmax, nmax - 1);
+
+    let mut iscale = -(nmax as f32) / max;
+    let mut sumlx = 0.0f32;
+    let mut suml2 = 0.0f32;
+    for (i, &v) in x.iter().enumerate() {
+        let l = level(iscale, v);
+        out[i] = (l + nmax) as i8;
+        let w = weight(i);
+        sumlx += w * v * l as f32;
+        suml2 += w * l as f32 * l as f32;
+    }
+    let mut scale = if suml2 != 0.0 { sumlx / suml2 } else { 0.0 };
+    let mut best = scale * sumlx;
+
+    for is in -9..=9 {
+        if is == 0 {
+            continue;
+        }
+        iscale = -(nmax as f32 + 0.1 * is as f32) / max;
+        sumlx = 0.0;
+        suml2 = 0.0;
+        for (i, &v) in x.iter().enumerate() {
+            let l = level(iscale, v);
+            let w = weight(i);
+            sumlx += w * v * l as f32;
+            suml2 += w * l as f32 * l as f32;
+        }
+        if suml2 > 0.0 && sumlx * sumlx > best * suml2 {
+            for (o, &v) in out.iter_mut().zip(x) {
+                *o = (nmax + level(iscale, v)) as i8;
+            }
+            scale = sumlx / suml2;
+            best = scale * sumlx;
+        }
+    }
+    scale
+}
+
+/// Symmetric scale search used by Q3_K: starts from `-nmax / max` and then
+/// moves single values to other levels for as long as that lowers the
+/// error weighted by each value's square. Levels are offset by `nmax`.
+fn make_q3_quants(nmax: i32, x: &[f32], out: &mut [i8]) -> f32 {
+    let mut max = 0.0f32;
+    let mut amax = 0.0f32;
+    for &v in x {
+        if v.abs() > amax {
+            amax = v.abs();
+            max = v;
+        }
+    }
+    if amax < GROUP_MAX_EPS {
+        out.fill(0);
+        return 0.0;
+    }
+
+    let iscale = -(nmax as f32) / max;
+    let mut sumlx = 0.0f32;
+    let mut suml2 = 0.0f32;
+    for (i, &v) in x.iter().enumerate() {
+        let l = nearest_int(iscale * v).clamp(-nmax, nmax - 1);
+        out[i] = l as i8;
+        let w = v * v;
+        sumlx += w * v * l as f32;
+        suml2 += w * l as f32 * l as f32;
+    }
+
+    for _ in 0..5 {
+        let mut n_changed = 0;
+        for (i, &v) in x.iter().enumerate() {
+            let w = v * v;
+            let cur = out[i] as f32;
+            let mut slx = sumlx - w * v * cur;
+            if slx > 0.0 {
+                let mut sl2 = suml2 - w * cur * cur;
+                let new_l = nearest_int(v * sl2 / slx).clamp(-nmax, nmax - 1);
+                if new_l != out[i] as i32 {
+                    slx += w * v * new_l as f32;
+                    sl2 += w * new_l as f32 * new_l as f32;
+                    if sl2 > 0.0 && slx * slx * suml2 > sumlx * sumlx * sl2 {
+                        out[i] = new_l as i8;
+                        sumlx = slx;
+                        suml2 = sl2;
+                        n_changed += 1;
+                    }
+                }
+            }
+        }
+        if n_changed == 0 {
+            break;
+        }
+    }
+
+    for o in out.iter_mut() {
+        *o += nmax as i8;
+    }
+    if suml2 > 0.0 {
+        sumlx / suml2
+    } else {
+        0.0
+    }
+}
+
+/// Asymmetric scale and min search used by Q2_K, Q4_K and Q5_K. Starting
+/// from the min/max range it tries `nstep + 1` scales, solving the weighted
+/// least squares scale and min for each, and keeps the lowest error (squared,
+/// or absolute with `use_mad`). Returns `(scale, min)` where the value is
+/// approximately `scale * level - min`.
+///
+/// With explicit weights this is also ggml's `make_qkx3_quants`, which the
+/// importance-weighted quantizers use.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn make_qkx2_quants(
+    nmax: i32,
+    x: &[f32],
+    weights: &[f32],
+    out: &mut [u8],
+    rmin: f32,
+    rdelta: f32,
+    nstep: i32,
+    use_mad: bool,
+) -> (f32, f32) {
+    let n = x.len();
+    let mut min = x[0];
+    let mut max = x[0];
+    let mut sum_w = weights[0];
+    let mut sum_x = sum_w * x[0];
+    for i in 1..n {
+        if x[i] < min {
+            min = x[i];
+        }
+        if x[i] > max {
+            max = x[i];
+        }
+        let w = weights[i];
+        sum_w += w;
+        sum_x += w * x[i];
+    }
+    if min > 0.0 {
+        min = 0.0;
+    }
+    if max == min {
+        out.fill(0);
+        return (0.0, -min);
+    }
+
+    let error = |diff: f32| if use_mad { diff.abs() } else { diff * diff };
+
+    let mut iscale = nmax as f32 / (max - min);
+    let mut scale = 1.0 / iscale;
+    let mut best_error = 0.0f32;
+    for i in 0..n {
+        let l = nearest_int(iscale * (x[i] - min)).clamp(0, nmax);
+        out[i] = l as u8;
+        best_error += weights[i] * error(scale * l as f32 + min - x[i]);
+    }
+
+    let mut aux = [0u8; 32];
+    for is in 0..=nstep {
+        iscale = (rmin + rdelta * is as f32 + nmax as f32) / (max - min);
+        let mut sum_l = 0.0f32;
+        let mut sum_l2 = 0.0f32;
+        let mut sum_xl = 0.0f32;
+        for i in 0..n {
+            let l = nearest_int(iscale * (x[i] - min)).clamp(0, nmax);
+            aux[i] = l as u8;
+            let w = weights[i];
+            sum_l += w * l as f32;
+            sum_l2 += w * l as f32 * l as f32;
+            sum_xl += w * l as f32 * x[i];
+        }
+        let d = sum_w * sum_l2 - sum_l * sum_l;
+        if d > 0.0 {
+            let mut this_scale = (sum_w * sum_xl - sum_x * sum_l) / d;
+            let mut this_min = (sum_l2 * sum_x - sum_l * sum_xl) / d;
+            if this_min > 0.0 {
+                this_min = 0.0;
+                this_scale = sum_xl / sum_l2;
+            }
+            let mut cur_error = 0.0f32;
+            for i in 0..n {
+                cur_error += weights[i] * error(this_scale * aux[i] as f32 + this_min - x[i]);
+            }
+            if cur_error < best_error {
+                out.copy_from_slice(&aux[..n]);
+                best_error = cur_error;
+                scale = this_scale;
+                min = this_min;
+            }
+        }
+    }
+    (scale, -min)
+}
+
+/// Quantizes non-negative sub-block scales or mins to `0..=nmax` for the
+/// weighted K-quants (and the first guess of `IQ2_XXS`): tries 9 scales around `nmax / max`, then moves single
+/// levels while that lowers the error weighted by `qw`. Returns the scale.
+pub(crate) fn make_qp_quants(nmax: i32, x: &[f32], out: &mut [u8], qw: &[f32]) -> f32 {
+    let mut max = 0.0f32;
+    for &v in x {
+        if v > max {
+            max = v;
+        }
+    }
+    if max < GROUP_MAX_EPS {
+        out.fill(0);
+        return 0.0;
+    }
+
+    let mut iscale = nmax as f32 / max;
+    for (o, &v) in out.iter_mut().zip(x) {
+        *o = nearest_int(iscale * v) as u8;
+    }
+    let scale = 1.0 / iscale;
+    let mut best_mse = 0.0f32;
+    for i in 0..x.len() {
+        let diff = x[i] - scale * out[i] as f32;
+        best_mse += qw[i] * diff * diff;
+    }
+
+    for is in -4..=4 {
+        if is == 0 {
+            continue;
+        }
+        let iscale_is = (0.1 * is as f32 + nmax as f32) / max;
+        let scale_is = 1.0 / iscale_is;
+        let mut mse = 0.0f32;
+        for i in 0..x.len() {
+            let l = nearest_int(iscale_is * x[i]).min(nmax);
+            let diff = x[i] - scale_is * l as f32;
+            mse += qw[i] * diff * diff;
+        }
+        if mse < best_mse {
+            best_mse = mse;
+            iscale = iscale_is;
+        }
+    }
+
+    let mut sumlx = 0.0f32;
+    let mut suml2 = 0.0f32;
+    for i in 0..x.len() {
+        let l = nearest_int(iscale * x[i]).min(nmax);
+        out[i] = l as u8;
+        let w = qw[i];
+        sumlx += w * x[i] * l as f32;
+        suml2 += w * l as f32 * l as f32;
+    }
+
+    for _ in 0..5 {
+        let mut n_changed = 0;
+        for i in 0..x.len() {
+            let w = qw[i];
+            let cur = out[i] as f32;
+            let mut slx = sumlx - w * x[i] * cur;
+            let mut sl2 = suml2 - w * cur * cur;
+            if slx > 0.0 && sl2 > 0.0 {
+                let new_l = nearest_int(x[i] * sl2 / slx).min(nmax);
+                if new_l != out[i] as i32 {
+                    slx += w * x[i] * new_l as f32;
+                    sl2 += w * new_l as f32 * new_l as f32;
+                    if slx * slx * suml2 > sumlx * sumlx * sl2 {
+                        out[i] = new_l as u8;
+                        sumlx = slx;
+                        suml2 = sl2;
+                        n_changed += 1;
+                    }
+                }
+            }
+        }
+        if n_changed == 0 {
+            break;
+        }
+    }
+
+    if suml2 > 0.0 {
+        sumlx / suml2
+    } else {
+        0.0
+    }
+}
+
+/// Importance weights for one sub-block of the weighted quantizers: the
+/// imatrix entry scaled by how far each value stands out from `sigma2`
+pub(crate) fn importance<const N: usize>(x: &[f32], qw: &[f32], sigma2: f32) -> [f32; N] {
+    std::array::from_fn(|l| qw[l] * (sigma2 + x[l] * x[l]).sqrt())
+}
+
+/// Sum of squares, accumulated in order as ggml does
+pub(crate) fn sum_squares(x: &[f32]) -> f32 {
+    let mut sum = 0.0f32;
+    for &v in x {
+        sum += v * v;
+    }
+    sum
+}
+
+/// Packs eight 6-bit scales and mins into the 12-byte Q4_K/Q5_K layout
+fn pack_scale_min_k4(ls: &[u8; 8], lm: &[u8; 8]) -> [u8; 12] {
+    let mut q = [0u8; 12];
+    for j in 0..8 {
+        if j < 4 {
+            q[j] = ls[j];
+            q[j + 4] = lm[j];
+        } else {
+            q[j + 4] = (ls[j] & 0xF) | ((lm[j] & 0xF) << 4);
+            q[j - 4] |= (ls[j] >> 4) << 6;
+            q[j] |= (lm[j] >> 4) << 6;
+        }
+    }
+    q
+}
+
+/// Reads back sub-block `j`'s 6-bit scale and min from the 12-byte layout
+fn get_scale_min_k4(j: usize, q: &[u8; 12]) -> (u8, u8) {
+    if j < 4 {
+        (q[j] & 63, q[j + 4] & 63)
+    } else {
+        (
+            (q[j + 4] & 0xF) | ((q[j - 4] >> 6) << 4),
+            (q[j + 4] >> 4) | ((q[j] >> 6) << 4),
+        )
+    }
+}
+
+/// Packs 2-bit levels four to a byte: each 128-value half fills 32 bytes,
+/// with values `l`, `l + 32`, `l + 64` and `l + 96` in bits 0, 2, 4 and 6
+fn pack_2bit(levels: &[u8; QK_K], out: &mut Vec<u8>) {
+    for j in (0..QK_K).step_by(128) {
+        for l in 0..32 {
+            out.push(
+                levels[j + l]
+                    | (levels[j + l + 32] << 2)
+                    | (levels[j + l + 64] << 4)
+                    | (levels[j + l + 96] << 6),
+            );
+        }
+    }
+}
+
+/// Shared by Q4_K and Q5_K: per 32-value sub-block weights, then the scale
+/// and min search, returning the packed scales with `d` and `dmin`. `rmin`
+/// and `nstep` tune the search without importance weights `qw`; with them
+/// the search and the 6-bit scales follow `quantize_row_q4_K_impl`.
+fn quantize_scale_min_k4(
+    x: &[f32],
+    qw: Option<&[f32]>,
+    nmax: i32,
+    rmin: f32,
+    nstep: i32,
+    levels: &mut [u8; QK_K],
+) -> ([u8; 12], f16, f16) {
+    let mut scales = [0.0f32; 8];
+    let mut mins = [0.0f32; 8];
+    let mut ls = [0u8; 8];
+    let mut lm = [0u8; 8];
+
+    let (d, dmin) = if let Some(qw) = qw {
+        let sigma2 = 2.0 * sum_squares(x) / QK_K as f32;
+        let mut sw = [0.0f32; 8];
+        for j in 0..QK_K / 32 {
+            let xs = &x[32 * j..32 * j + 32];
+            let weights: [f32; 32] = importance(xs, &qw[32 * j..], sigma2);
+            sw[j] = weights.iter().fold(0.0, |sum, &w| sum + w);
+            (scales[j], mins[j]) = make_qkx2_quants(
+                nmax,
+                xs,
+                &weights,
+                &mut levels[32 * j..32 * j + 32],
+                -0.9,
+                0.05,
+                36,
+                false,
+            );
+        }
+        let d = make_qp_quants(63, &scales, &mut ls, &sw);
+        let dmin = make_qp_quants(63, &mins, &mut lm, &sw);
+        (f16::from_f32(d), f16::from_f32(dmin))
+    } else {
+        let mut max_scale = 0.0f32;
+        let mut max_min = 0.0f32;
+        for j in 0..QK_K / 32 {
+            let xs = &x[32 * j..32 * j + 32];
+            let av_x = (sum_squares(xs) / 32.0).sqrt();
+            let weights: [f32; 32] = std::array::from_fn(|l| av_x + xs[l].abs());
+            let (scale, min) = make_qkx2_quants(
+                nmax,
+                xs,
+                &weights,
+                &mut levels[32 * j..32 * j + 32],
+                rmin,
+                0.1,
+                nstep,
+                false,
+            );
+            scales[j] = scale;
+            mins[j] = min;
+            if scale > max_scale {
+                max_scale = scale;
+            }
+            if min > max_min {
+                max_min = min;
+            }
+        }
+
+        let inv_scale = if max_scale > 0.0 { 63.0 / max_scale } else { 0.0 };
+        let inv_min = if max_min > 0.0 { 63.0 / max_min } else { 0.0 };
+        for j in 0..QK_K / 32 {
+            ls[j] = (nearest_int(inv_scale * scales[j]) as u8).min(63);
+            lm[j] = (nearest_int(inv_min * mins[j]) as u8).min(63);
+        }
+        (f16::from_f32(max_scale / 63.0), f16::from_f32(max_min / 63.0))
+    };
+
+    let packed = pack_scale_min_k4(&ls, &lm);
+
+    // Requantize against the rounded scales that will actually be stored
+    for j in 0..QK_K / 32 {
+        let (sc, m) = get_scale_min_k4(j, &packed);
+        let dj = d.to_f32() * sc as f32;
+        if dj == 0.0 {
+            continue;
+        }
+        let dm = dmin.to_f32() * m as f32;
+        for ii in 0..32 {
+            let l = nearest_int((x[32 * j + ii] + dm) / dj);
+            levels[32 * j + ii] = l.clamp(0, nmax) as u8;
+        }
+    }
+
+    (packed, d, dmin)
+}
+
+/// Quantizes to ggml `block_q2_K`: 16 sub-blocks of 16 values, each with
+/// a 4-bit scale and min, then 2-bit levels, then the f16 `d` and `dmin`
+/// the scales and mins are multiplied by.
+pub fn quantize_tensor_q2_k(tensor: &[f32]) -> Vec<u8> {
+    quantize_q2_k(tensor, None)
+}
+
+/// `Q2_K` for one row with a per-column importance weight in `qw`
+pub fn quantize_row_q2_k_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    quantize_q2_k(row, Some(qw))
+}
+
+fn quantize_q2_k(tensor: &[f32], qw: Option<&[f32]>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(tensor.len() / QK_K * 84);
+
+    for (i, x) in tensor.chunks_exact(QK_K).enumerate() {
+        let mut levels = [0u8; QK_K];
+        let (sc, d, dmin) = match qw {
+            Some(qw) => q2_k_scales_weighted(x, &qw[QK_K * i..], &mut levels),
+            None => q2_k_scales(x, &mut levels),
+        };
+
+        for j in 0..QK_K / 16 {
+            let dj = d.to_f32() * (sc[j] & 0xF) as f32;
+            if dj == 0.0 {
+                continue;
+            }
+            let dm = dmin.to_f32() * (sc[j] >> 4) as f32;
+            for ii in 0..16 {
+                let l = nearest_int((x[16 * j + ii] + dm) / dj);
+                levels[16 * j + ii] = l.clamp(0, 3) as u8;
+            }
+        }
+
+        out.extend_from_slice(&sc);
+        pack_2bit(&levels, &mut out);
+        out.extend_from_slice(&d.to_le_bytes());
+        out.extend_from_slice(&dmin.to_le_bytes());
+    }
+
+    out
+}
+
+/// Q2_K sub-block scales and mins from `quantize_row_q2_K_ref`, as packed
+/// 4-bit pairs with the `d` and `dmin` they multiply
+fn q2_k_scales(x: &[f32], levels: &mut [u8; QK_K]) -> ([u8; 16], f16, f16) {
+    const Q4SCALE: f32 = 15.0;
+    let mut scales = [0.0f32; 16];
+    let mut mins = [0.0f32; 16];
+    let mut max_scale = 0.0f32;
+    let mut max_min = 0.0f32;
+
+    for j in 0..QK_K / 16 {
+        let xs = &x[16 * j..16 * j + 16];
+        let weights: [f32; 16] = std::array::from_fn(|l| xs[l].abs());
+        let (scale, min) = make_qkx2_quants(
+            3,
+            xs,
+            &weights,
+            &mut levels[16 * j..16 * j + 16],
+            -0.5,
+            0.1,
+            15,
+            true,
+        );
+        scales[j] = scale;
+        mins[j] = min;
+        if scale > max_scale {
+            max_scale = scale;
+        }
+        if min > max_min {
+            max_min = min;
+        }
+    }
+
+    let mut sc = [0u8; 16];
+    let d = if max_scale > 0.0 {
+        let iscale = Q4SCALE / max_scale;
+        for j in 0..QK_K / 16 {
+            sc[j] = nearest_int(iscale * scales[j]) as u8;
+        }
+        f16::from_f32(max_scale / Q4SCALE)
+    } else {
+        f16::ZERO
+    };
+    let dmin = if max_min > 0.0 {
+        let iscale = Q4SCALE / max_min;
+        for j in 0..QK_K / 16 {
+            sc[j] |= (nearest_int(iscale * mins[j]) << 4) as u8;
+        }
+        f16::from_f32(max_min / Q4SCALE)
+    } else {
+        f16::ZERO
+    };
+
+    (sc, d, dmin)
+}
+
+/// Q2_K sub-block scales and mins from `quantize_row_q2_K_impl`: the search
+/// is weighted by `qw` and the 4-bit scales are fitted rather than rounded
+fn q2_k_scales_weighted(x: &[f32], qw: &[f32], levels: &mut [u8; QK_K]) -> ([u8; 16], f16, f16) {
+    let mut scales = [0.0f32; 16];
+    let mut mins = [0.0f32; 16];
+    let mut sw = [0.0f32; 16];
+    let sigma2 = sum_squares(x) / QK_K as f32;
+
+    for j in 0..QK_K / 16 {
+        let xs = &x[16 * j..16 * j + 16];
+        let weights: [f32; 16] = importance(xs, &qw[16 * j..], sigma2);
+        sw[j] = weights.iter().fold(0.0, |sum, &w| sum + w);
+        (scales[j], mins[j]) = make_qkx2_quants(
+            3,
+            xs,
+            &weights,
+            &mut levels[16 * j..16 * j + 16],
+            -0.9,
+            0.05,
+            36,
+            false,
+        );
+    }
+
+    let mut ls = [0u8; 16];
+    let mut lm = [0u8; 16];
+    let d = make_qp_quants(15, &scales, &mut ls, &sw);
+    let dmin = make_qp_quants(15, &mins, &mut lm, &sw);
+    let sc = std::array::from_fn(|j| ls[j] | (lm[j] << 4));
+    (sc, f16::from_f32(d), f16::from_f32(dmin))
+}
+
+/// Quantizes to ggml `block_q3_K`: a mask with the high bit of every 3-bit
+/// level, the low 2 bits packed as in Q2_K, 16 signed 6-bit scales packed
+/// into 12 bytes, and the f16 super-block scale.
+pub fn quantize_tensor_q3_k(tensor: &[f32]) -> Vec<u8> {
+    quantize_q3_k(tensor, None)
+}
+
+/// `Q3_K` for one row with a per-column importance weight in `qw`
+pub fn quantize_row_q3_k_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    quantize_q3_k(row, Some(qw))
+}
+
+fn quantize_q3_k(tensor: &[f32], qw: Option<&[f32]>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(tensor.len() / QK_K * 110);
+
+    for (i, x) in tensor.chunks_exact(QK_K).enumerate() {
+        let mut levels = [0i8; QK_K];
+        let mut scales = [0.0f32; 16];
+        // 6-bit sub-block scales offset by 32
+        let mut ls = [0u8; 16];
+
+        let d = if let Some(qw) = qw {
+            let qw = &qw[QK_K * i..];
+            let sigma2 = 2.0 * sum_squares(x) / QK_K as f32;
+            let mut sw = [0.0f32; 16];
+            for j in 0..QK_K / 16 {
+                let xs = &x[16 * j..16 * j + 16];
+                let weights: [f32; 16] = importance(xs, &qw[16 * j..], sigma2);
+                sw[j] = weights.iter().fold(0.0, |sum, &w| sum + w);
+                scales[j] = make_qx_quants(4, xs, &mut levels[16 * j..16 * j + 16], Some(&weights));
+            }
+            let mut offset = [0i8; 16];
+            let d = make_qx_quants(32, &scales, &mut offset, Some(&sw));
+            ls = offset.map(|l| l as u8);
+            f16::from_f32(d)
+        } else {
+            let mut max_scale = 0.0f32;
+            let mut amax = 0.0f32;
+            for j in 0..QK_K / 16 {
+                scales[j] = make_q3_quants(4, &x[16 * j..16 * j + 16], &mut levels[16 * j..16 * j + 16]);
+                if scales[j].abs() > amax {
+                    amax = scales[j].abs();
+                    max_scale = scales[j];
+                }
+            }
+            if max_scale != 0.0 {
+                let iscale = -32.0 / max_scale;
+                for j in 0..QK_K / 16 {
+                    ls[j] = ((nearest_int(iscale * scales[j]) as i8).clamp(-32, 31) + 32) as u8;
+                }
+                f16::from_f32(1.0 / iscale)
+            } else {
+                f16::ZERO
+            }
+        };
+
+        let mut sc = [0u8; 12];
+        for (j, &l) in ls.iter().enumerate() {
+            if j < 8 {
+                sc[j] = l & 0xF;
+            } else {
+                sc[j - 8] |= (l & 0xF) << 4;
+            }
+            sc[j % 4 + 8] |= (l >> 4) << (2 * (j / 4));
+        }
+
+        for j in 0..QK_K / 16 {
+            let lo = if j < 8 { sc[j] & 0xF } else { sc[j - 8] >> 4 };
+            let hi = (sc[8 + j % 4] >> (2 * (j / 4))) & 3;
+            let dj = d.to_f32() * ((lo | (hi << 4)) as i32 - 32) as f32;
+            if dj == 0.0 {
+                continue;
+            }
+            for ii in 0..16 {
+                let l = nearest_int(x[16 * j + ii] / dj).clamp(-4, 3);
+                levels[16 * j + ii] = (l + 4) as i8;
+            }
+        }
+
+        // Bit `j / 32` of byte `j % 32` holds the high bit of level `j`
+        let mut hmask = [0u8; QK_K / 8];
+        let mut low = [0u8; QK_K];
+        for (j, &l) in levels.iter().enumerate() {
+            if l > 3 {
+                hmask[j % 32] |= 1 << (j / 32);
+            }
+            low[j] = (l & 3) as u8;
+        }
+
+        out.extend_from_slice(&hmask);
+        pack_2bit(&low, &mut out);
+        out.extend_from_slice(&sc);
+        out.extend_from_slice(&d.to_le_bytes());
+    }
+
+    out
+}
+
+/// Quantizes to ggml `block_q4_K`: f16 `d` and `dmin`, eight 6-bit scales
+/// and mins for 32-value sub-blocks, then 4-bit levels. Each 64-value pair
+/// of sub-blocks shares 32 bytes, the first in the low nibbles.
+pub fn quantize_tensor_q4_k(tensor: &[f32]) -> Vec<u8> {
+    quantize_q4_k(tensor, None)
+}
+
+/// `Q4_K` for one row with a per-column importance weight in `qw`
+pub fn quantize_row_q4_k_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    quantize_q4_k(row, Some(qw))
+}
+
+fn quantize_q4_k(tensor: &[f32], qw: Option<&[f32]>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(tensor.len() / QK_K * 144);
+
+    for (i, x) in tensor.chunks_exact(QK_K).enumerate() {
+        let mut levels = [0u8; QK_K];
+        let qw = qw.map(|qw| &qw[QK_K * i..]);
+        let (scales, d, dmin) = quantize_scale_min_k4(x, qw, 15, -1.0, 20, &mut levels);
+
+        out.extend_from_slice(&d.to_le_bytes());
+        out.extend_from_slice(&dmin.to_le_bytes());
+        out.extend_from_slice(&scales);
+        for j in (0..QK_K).step_by(64) {
+            for l in 0..32 {
+                out.push(levels[j + l] | (levels[j + l + 32] << 4));
+            }
+        }
+    }
+
+    out
+}
+
+/// Quantizes to ggml `block_q5_K`: the Q4_K layout plus a 32-byte mask with
+/// the fifth bit of each level, stored before the nibbles.
+pub fn quantize_tensor_q5_k(tensor: &[f32]) -> Vec<u8> {
+    quantize_q5_k(tensor, None)
+}
+
+/// `Q5_K` for one row with a per-column importance weight in `qw`
+pub fn quantize_row_q5_k_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    quantize_q5_k(row, Some(qw))
+}
+
+fn quantize_q5_k(tensor: &[f32], qw: Option<&[f32]>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(tensor.len() / QK_K * 176);
+
+    for (i, x) in tensor.chunks_exact(QK_K).enumerate() {
+        let mut levels = [0u8; QK_K];
+        let qw = qw.map(|qw| &qw[QK_K * i..]);
+        let (scales, d, dmin) = quantize_scale_min_k4(x, qw, 31, -0.5, 15, &mut levels);
+
+        // Sub-block pair `n` keeps its fifth bits in bits 2n and 2n + 1
+        let mut qh = [0u8; QK_K / 8];
+        let mut qs = [0u8; QK_K / 2];
+        for (n, pair) in levels.chunks_exact(64).enumerate() {
+            for j in 0..32 {
+                let (l1, l2) = (pair[j], pair[j + 32]);
+                qh[j] |= ((l1 >> 4) << (2 * n)) | ((l2 >> 4) << (2 * n + 1));
+                qs[32 * n + j] = (l1 & 0xF) | ((l2 & 0xF) << 4);
+            }
+        }
+
+        out.extend_from_slice(&d.to_le_bytes());
+        out.extend_from_slice(&dmin.to_le_bytes());
+        out.extend_from_slice(&scales);
+        out.extend_from_slice(&qh);
+        out.extend_from_slice(&qs);
+    }
+
+    out
+}
+
+/// Quantizes to ggml `block_q6_K`: low 4 bits and high 2 bits of each 6-bit
+/// level in separate arrays, 16 signed 8-bit scales and an f16 `d`.
+pub fn quantize_tensor_q6_k(tensor: &[f32]) -> Vec<u8> {
+    quantize_q6_k(tensor, None)
+}
+
+/// `Q6_K` for one row with a per-column importance weight in `qw`, which
+/// replaces the squared values as the scale search's weights
+pub fn quantize_row_q6_k_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    quantize_q6_k(row, Some(qw))
+}
+
+fn quantize_q6_k(tensor: &[f32], qw: Option<&[f32]>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(tensor.len() / QK_K * 210);
+
+    for (i, x) in tensor.chunks_exact(QK_K).enumerate() {
+        let mut levels = [0i8; QK_K];
+        let mut scales = [0.0f32; 16];
+        let mut max_scale = 0.0f32;
+        let mut max_abs_scale = 0.0f32;
+
+        for ib in 0..QK_K / 16 {
+            let qw = qw.map(|qw| &qw[QK_K * i + 16 * ib..QK_K * i + 16 * ib + 16]);
+            let scale = make_qx_quants(32, &x[16 * ib..16 * ib + 16], &mut levels[16 * ib..16 * ib + 16], qw);
+            scales[ib] = scale;
+            if scale.abs() > max_abs_scale {
+                max_abs_scale = scale.abs();
+                max_scale = scale;
+            }
+        }
+
+        if max_abs_scale < GROUP_MAX_EPS {
+            out.extend_from_slice(&[0u8; 210]);
+            continue;
+        }
+
+        let iscale = -128.0 / max_scale;
+        let d = f16::from_f32(1.0 / iscale);
+        let sc: [i8; 16] = std::array::from_fn(|ib| nearest_int(iscale * scales[ib]).min(127) as i8);
+
+        for j in 0..QK_K / 16 {
+            let dj = d.to_f32() * sc[j] as f32;
+            if dj == 0.0 {
+                continue;
+            }
+            for ii in 0..16 {
+                let l = nearest_int(x[16 * j + ii] / dj).clamp(-32, 31);
+                levels[16 * j + ii] = (l + 32) as i8;
+            }
+        }
+
+        let mut ql = [0u8; QK_K / 2];
+        let mut qh = [0u8; QK_K / 4];
+        for (n, half) in levels.chunks_exact(128).enumerate() {
+            for l in 0..32 {
+                let q: [u8; 4] = std::array::from_fn(|k| half[l + 32 * k] as u8);
+                ql[64 * n + l] = (q[0] & 0xF) | ((q[2] & 0xF) << 4);
+                ql[64 * n + l + 32] = (q[1] & 0xF) | ((q[3] & 0xF) << 4);
+                qh[32 * n + l] = (q[0] >> 4) | ((q[1] >> 4) << 2) | ((q[2] >> 4) << 4) | ((q[3] >> 4) << 6);
+            }
+        }
+
+        out.extend_from_slice(&ql);
+        out.extend_from_slice(&qh);
+        out.extend(sc.iter().map(|&s| s as u8));
+        out.extend_from_slice(&d.to_le_bytes());
+    }
+
+    out
+}
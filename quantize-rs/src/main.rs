@@ -1,188 +1,765 @@
+use std::borrow::Cow;
+use std::io;
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use byteorder::{LittleEndian, WriteBytesExt};
 use clap::Parser;
+use rayon::prelude::*;
 
-use gguf_core::reader::read_gguf_file;
-use gguf_core::types::{GGUFValue, GGUFTensor};
-use gguf_core::writer::write_gguf_file;
+use gguf_core::types::{GGUFValue, GgmlType, TensorInfo};
+use half::{bf16, f16};
+use gguf_core::writer::{GgufWriter, WriteOptions};
+
+mod i_quants;
+mod imatrix;
+mod k_quants;
+mod presets;
+mod rules;
+mod source;
+
+use imatrix::Imatrix;
+use k_quants::{importance, make_qkx2_quants, make_qx_quants, sum_squares};
+use presets::{Choice, Preset, TypePlanner};
+use rules::Pattern;
+use source::Input;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Input GGUF file path
+    /// Input model: a GGUF file, or a safetensors checkpoint (`.safetensors`,
+    /// `model.safetensors.index.json` or a model directory)
     #[arg(short, long)]
     input: PathBuf,
 
+    /// Hugging Face `config.json` to take metadata and tensor names from,
+    /// for safetensors input
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// JSON object of extra Hugging Face → GGUF tensor names for safetensors
+    /// input, as for gguf-writer
+    #[arg(long, value_name = "FILE")]
+    tensor_map: Option<PathBuf>,
+
+    /// Quantize safetensors input whose `rope_scaling` type llama.cpp cannot
+    /// express, leaving RoPE unscaled, instead of stopping with an error
+    #[arg(long)]
+    ignore_rope_scaling: bool,
+
     /// Output GGUF file path
     #[arg(short, long)]
     output: PathBuf,
 
-    /// Quantization format (e.g., Q4_0, Q5_1)
+    /// Quantization preset, as in llama-quantize (e.g. Q4_K_M, Q5_K_S, Q8_0)
     #[arg(short, long)]
     format: String,
+
+    /// Use the preset's base type for every quantizable tensor instead of
+    /// varying it by tensor role
+    #[arg(long)]
+    pure: bool,
+
+    /// TOML or JSON file of ordered `pattern → type` rules. The first rule
+    /// matching a quantizable tensor overrides the preset's choice.
+    #[arg(long, value_name = "FILE")]
+    rules: Option<PathBuf>,
+
+    /// Copy tensors matching this glob or /regex/ unchanged (repeatable)
+    #[arg(long, value_name = "PATTERN", value_parser = Pattern::parse)]
+    keep: Vec<Pattern>,
+
+    /// Skip quantizing tensors matching this glob or /regex/, copying them
+    /// unchanged even where a rule matches (repeatable)
+    #[arg(long, value_name = "PATTERN", value_parser = Pattern::parse)]
+    exclude: Vec<Pattern>,
+
+    /// List the type chosen for each tensor and why, without writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Allow tensors that are already quantized to be dequantized and
+    /// quantized again to another type, which loses quality
+    #[arg(long)]
+    allow_requantize: bool,
+
+    /// Importance matrix from llama-imatrix (legacy .dat or GGUF) to weight
+    /// the quantizers' scale searches by activation statistics
+    #[arg(long, value_name = "FILE")]
+    imatrix: Option<PathBuf>,
+
+    /// Worker threads for quantization (defaults to the number of CPUs).
+    /// The output is identical for any thread count.
+    #[arg(short, long)]
+    threads: Option<usize>,
 }
 
-#[derive(Debug)]
-enum QuantizationType {
-    Q4_0,
-    Q5_1,
-    Unknown(String),
+/// What happens to one input tensor
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Copy,
+    Quantize(GgmlType),
 }
 
-impl QuantizationType {
-    fn as_str(&self) -> &'static str {
-        match self {
-            QuantizationType::Q4_0 => "Q4_0",
-            QuantizationType::Q5_1 => "Q5_1",
-            QuantizationType::Unknown(_) => "Unknown",
-        }
-    }
+/// A tensor's planned action, why it was chosen, and its imatrix weights
+struct Step<'a> {
+    action: Action,
+    reason: String,
+    imatrix: Option<&'a [f32]>,
 }
 
-impl From<String> for QuantizationType {
-    fn from(s: String) -> Self {
-        match s.to_lowercase().as_str() {
-            "q4_0" => QuantizationType::Q4_0,
-            "q5_1" => QuantizationType::Q5_1,
-            _ => QuantizationType::Unknown(s),
+/// Input bytes of consecutive tensors quantized together. Small tensors are
+/// processed in parallel within a batch, while large ones fill a batch on
+/// their own and are split by rows instead; this bounds how much quantized
+/// data waits in memory to be written in order.
+const BATCH_BYTES: u64 = 256 << 20;
+
+/// Values per unit of work when a tensor is split across threads
+const ROW_CHUNK_VALUES: usize = 1 << 16;
+
+/// Quantizes to ggml `block_q4_0`: an f16 scale `d` followed by 16 bytes of
+/// 4-bit values offset by 8. Byte `j` holds value `j` in its low nibble and
+/// value `j + 16` in its high nibble. The scale maps the value with the
+/// largest magnitude to -8, as in llama.cpp's `quantize_row_q4_0_ref`.
+fn quantize_tensor_q4_0(tensor: &[f32]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 32;
+    let mut out = Vec::with_capacity(tensor.len() / BLOCK_SIZE * 18);
+
+    for chunk in tensor.chunks_exact(BLOCK_SIZE) {
+        let mut amax = 0.0f32;
+        let mut max = 0.0f32;
+        for &v in chunk {
+            if amax < v.abs() {
+                amax = v.abs();
+                max = v;
+            }
+        }
+
+        let d = max / -8.0;
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+
+        for j in 0..BLOCK_SIZE / 2 {
+            let lo = ((chunk[j] * id + 8.5) as i8).min(15) as u8;
+            let hi = ((chunk[j + BLOCK_SIZE / 2] * id + 8.5) as i8).min(15) as u8;
+            out.push(lo | (hi << 4));
         }
     }
+
+    out
 }
 
-fn quantize_tensor_q4_0(tensor: &[f32]) -> Vec<u8> {
+/// Quantizes to ggml `block_q4_1`: an f16 scale `d` and minimum `m`, then
+/// 4-bit steps of `d` above the block minimum packed as in `Q4_0`.
+fn quantize_tensor_q4_1(tensor: &[f32]) -> Vec<u8> {
     const BLOCK_SIZE: usize = 32;
-    let mut out = Vec::new();
+    let mut out = Vec::with_capacity(tensor.len() / BLOCK_SIZE * 20);
 
-    for chunk in tensor.chunks(BLOCK_SIZE) {
-        let min = chunk.iter().copied().fold(f32::INFINITY, f32::min);
-        let max = chunk.iter().copied().fold(f32::NEG_INFINITY, f32::max);
-        let scale = (max - min).max(1e-6) / 15.0;
-        let zero = min;
+    for chunk in tensor.chunks_exact(BLOCK_SIZE) {
+        let min = chunk.iter().copied().fold(f32::MAX, f32::min);
+        let max = chunk.iter().copied().fold(-f32::MAX, f32::max);
 
-        let values: Vec<u8> = chunk
-            .iter()
-            .map(|v| ((*v - zero) / scale).round().clamp(0.0, 15.0) as u8)
-            .collect();
+        let d = (max - min) / 15.0;
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
 
-        out.write_f32::<LittleEndian>(scale).unwrap();
-        out.write_f32::<LittleEndian>(zero).unwrap();
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        out.extend_from_slice(&f16::from_f32(min).to_le_bytes());
 
-        for pair in values.chunks(2) {
-            let byte = if pair.len() == 2 {
-                (pair[0] & 0x0F) | ((pair[1] & 0x0F) << 4)
-            } else {
-                pair[0] & 0x0F
-            };
-            out.push(byte);
+        for j in 0..BLOCK_SIZE / 2 {
+            let lo = (((chunk[j] - min) * id + 0.5) as i8).min(15) as u8;
+            let hi = (((chunk[j + BLOCK_SIZE / 2] - min) * id + 0.5) as i8).min(15) as u8;
+            out.push(lo | (hi << 4));
         }
     }
 
     out
 }
 
+/// Quantizes to ggml `block_q5_0`: an f16 scale `d`, a 32-bit mask holding
+/// the fifth bit of each value, then the low nibbles packed as in `Q4_0`.
+/// Values are offset by 16 and the largest magnitude maps to -16.
+fn quantize_tensor_q5_0(tensor: &[f32]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 32;
+    let mut out = Vec::with_capacity(tensor.len() / BLOCK_SIZE * 22);
+
+    for chunk in tensor.chunks_exact(BLOCK_SIZE) {
+        let mut amax = 0.0f32;
+        let mut max = 0.0f32;
+        for &v in chunk {
+            if amax < v.abs() {
+                amax = v.abs();
+                max = v;
+            }
+        }
+
+        let d = max / -16.0;
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+
+        let q: [u8; BLOCK_SIZE] = std::array::from_fn(|j| ((chunk[j] * id + 16.5) as i8).min(31) as u8);
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        pack_q5(&q, &mut out);
+    }
+
+    out
+}
+
+/// Quantizes to ggml `block_q5_1`: an f16 scale `d` and minimum `m`, then
+/// the same high-bit mask and nibble layout as `Q5_0`. Values are unsigned
+/// steps of `d` above the block minimum.
 fn quantize_tensor_q5_1(tensor: &[f32]) -> Vec<u8> {
     const BLOCK_SIZE: usize = 32;
-    let mut out = Vec::new();
+    let mut out = Vec::with_capacity(tensor.len() / BLOCK_SIZE * 24);
 
-    for chunk in tensor.chunks(BLOCK_SIZE) {
-        let min = chunk.iter().copied().fold(f32::INFINITY, f32::min);
-        let max = chunk.iter().copied().fold(f32::NEG_INFINITY, f32::max);
-        let scale = (max - min).max(1e-6) / 31.0;
-        let zero = min;
+    for chunk in tensor.chunks_exact(BLOCK_SIZE) {
+        let min = chunk.iter().copied().fold(f32::MAX, f32::min);
+        let max = chunk.iter().copied().fold(-f32::MAX, f32::max);
 
-        let levels: Vec<u8> = chunk
-            .iter()
-            .map(|v| ((*v - zero) / scale).round().clamp(0.0, 31.0) as u8)
-            .collect();
+        let d = (max - min) / 31.0;
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+
+        let q: [u8; BLOCK_SIZE] = std::array::from_fn(|j| ((chunk[j] - min) * id + 0.5) as u8);
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        out.extend_from_slice(&f16::from_f32(min).to_le_bytes());
+        pack_q5(&q, &mut out);
+    }
 
-        let mut packed = Vec::new();
-        let mut buffer = 0u64;
-        let mut bits = 0;
+    out
+}
 
-        for &val in &levels {
-            buffer |= (val as u64) << bits;
-            bits += 5;
+/// Quantizes to ggml `block_q8_0`: an f16 scale `d` and 32 signed bytes,
+/// with the largest magnitude mapped to ±127.
+fn quantize_tensor_q8_0(tensor: &[f32]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 32;
+    let mut out = Vec::with_capacity(tensor.len() / BLOCK_SIZE * 34);
 
-            while bits >= 8 {
-                packed.push((buffer & 0xFF) as u8);
-                buffer >>= 8;
-                bits -= 8;
-            }
+    for chunk in tensor.chunks_exact(BLOCK_SIZE) {
+        let amax = chunk.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+
+        let d = amax / 127.0;
+        let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        out.extend(chunk.iter().map(|&x| (x * id).round() as i8 as u8));
+    }
+
+    out
+}
+
+/// Importance-weighted `Q4_0` for one row, as llama.cpp's
+/// `quantize_row_q4_0_impl`: the scale minimises the error weighted by `qw`
+/// instead of mapping the largest value to -8.
+fn quantize_row_q4_0_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 32;
+    let mut out = Vec::with_capacity(row.len() / BLOCK_SIZE * 18);
+    let sigma2 = sum_squares(row) / row.len() as f32;
+
+    for (xb, qw) in row.chunks_exact(BLOCK_SIZE).zip(qw.chunks_exact(BLOCK_SIZE)) {
+        let weights: [f32; BLOCK_SIZE] = importance(xb, qw, sigma2);
+        let mut q = [0i8; BLOCK_SIZE];
+        let d = make_qx_quants(8, xb, &mut q, Some(&weights));
+
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        for j in 0..BLOCK_SIZE / 2 {
+            out.push(q[j] as u8 | ((q[j + 16] as u8) << 4));
         }
+    }
+
+    out
+}
 
-        if bits > 0 {
-            packed.push(buffer as u8);
+/// Importance-weighted `Q4_1` for one row: the scale and minimum come from
+/// a weighted least-squares search rather than the block's range
+fn quantize_row_q4_1_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 32;
+    let mut out = Vec::with_capacity(row.len() / BLOCK_SIZE * 20);
+    let sigma2 = sum_squares(row) / row.len() as f32;
+
+    for (xb, qw) in row.chunks_exact(BLOCK_SIZE).zip(qw.chunks_exact(BLOCK_SIZE)) {
+        let weights: [f32; BLOCK_SIZE] = importance(xb, qw, sigma2);
+        let mut q = [0u8; BLOCK_SIZE];
+        let (d, min) = make_qkx2_quants(15, xb, &weights, &mut q, -0.9, 0.05, 36, false);
+
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        out.extend_from_slice(&f16::from_f32(-min).to_le_bytes());
+        for j in 0..BLOCK_SIZE / 2 {
+            out.push(q[j] | (q[j + 16] << 4));
         }
+    }
+
+    out
+}
+
+/// Importance-weighted `Q5_0` for one row, searched as in `Q4_0`
+fn quantize_row_q5_0_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 32;
+    let mut out = Vec::with_capacity(row.len() / BLOCK_SIZE * 22);
+    let sigma2 = sum_squares(row) / row.len() as f32;
 
-        out.write_f32::<LittleEndian>(scale).unwrap();
-        out.write_f32::<LittleEndian>(zero).unwrap();
-        out.extend_from_slice(&packed);
+    for (xb, qw) in row.chunks_exact(BLOCK_SIZE).zip(qw.chunks_exact(BLOCK_SIZE)) {
+        let weights: [f32; BLOCK_SIZE] = importance(xb, qw, sigma2);
+        let mut q = [0i8; BLOCK_SIZE];
+        let d = make_qx_quants(16, xb, &mut q, Some(&weights));
+
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        pack_q5(&q.map(|l| l as u8), &mut out);
     }
 
     out
 }
 
-fn quantize_tensor(tensor: &[f32], format: &QuantizationType) -> (Vec<u8>, u32) {
-    match format {
-        QuantizationType::Q4_0 => (quantize_tensor_q4_0(tensor), 100),
-        QuantizationType::Q5_1 => (quantize_tensor_q5_1(tensor), 101),
-        QuantizationType::Unknown(s) => panic!("Unsupported quantization format: {s}"),
+/// Importance-weighted `Q5_1` for one row, searched as in `Q4_1`
+fn quantize_row_q5_1_weighted(row: &[f32], qw: &[f32]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 32;
+    let mut out = Vec::with_capacity(row.len() / BLOCK_SIZE * 24);
+    let sigma2 = sum_squares(row) / row.len() as f32;
+
+    for (xb, qw) in row.chunks_exact(BLOCK_SIZE).zip(qw.chunks_exact(BLOCK_SIZE)) {
+        let weights: [f32; BLOCK_SIZE] = importance(xb, qw, sigma2);
+        let mut q = [0u8; BLOCK_SIZE];
+        let (d, min) = make_qkx2_quants(31, xb, &weights, &mut q, -0.9, 0.05, 36, false);
+
+        out.extend_from_slice(&f16::from_f32(d).to_le_bytes());
+        out.extend_from_slice(&f16::from_f32(-min).to_le_bytes());
+        pack_q5(&q, &mut out);
     }
+
+    out
 }
 
-fn main() -> std::io::Result<()> {
-    let cli = Cli::parse();
-    let format = QuantizationType::from(cli.format.clone());
+/// Appends the `qh` mask and `qs` nibbles shared by `Q5_0` and `Q5_1`
+fn pack_q5(q: &[u8], out: &mut Vec<u8>) {
+    let mut qh = 0u32;
+    for (j, &v) in q.iter().enumerate() {
+        qh |= ((v >> 4) as u32 & 1) << j;
+    }
+    out.extend_from_slice(&qh.to_le_bytes());
+    for j in 0..16 {
+        out.push((q[j] & 0x0F) | ((q[j + 16] & 0x0F) << 4));
+    }
+}
 
-    if matches!(format, QuantizationType::Unknown(_)) {
-        eprintln!("❌ Unsupported quantization format: {}", cli.format);
-        std::process::exit(1);
+/// Whether [`quantize_tensor`] can produce `ggml_type`, with imatrix
+/// weights if it has no unweighted quantizer
+fn has_quantizer(ggml_type: GgmlType) -> bool {
+    has_unweighted_quantizer(ggml_type) || weighted_quantizer(ggml_type).is_some()
+}
+
+/// Whether [`quantize_rows`] can produce `ggml_type` without imatrix weights
+fn has_unweighted_quantizer(ggml_type: GgmlType) -> bool {
+    matches!(
+        ggml_type,
+        GgmlType::F32
+            | GgmlType::F16
+            | GgmlType::BF16
+            | GgmlType::Q4_0
+            | GgmlType::Q4_1
+            | GgmlType::Q5_0
+            | GgmlType::Q5_1
+            | GgmlType::Q8_0
+            | GgmlType::Q2_K
+            | GgmlType::Q3_K
+            | GgmlType::Q4_K
+            | GgmlType::Q5_K
+            | GgmlType::Q6_K
+            | GgmlType::IQ3_XXS
+            | GgmlType::IQ3_S
+            | GgmlType::IQ4_NL
+            | GgmlType::IQ4_XS
+    )
+}
+
+/// Quantizes one row given a weight per column
+type WeightedRowQuantizer = fn(&[f32], &[f32]) -> Vec<u8>;
+
+/// Row quantizer that takes per-column importance weights, for the types
+/// whose llama.cpp quantizer uses an imatrix
+fn weighted_quantizer(ggml_type: GgmlType) -> Option<WeightedRowQuantizer> {
+    Some(match ggml_type {
+        GgmlType::Q4_0 => quantize_row_q4_0_weighted,
+        GgmlType::Q4_1 => quantize_row_q4_1_weighted,
+        GgmlType::Q5_0 => quantize_row_q5_0_weighted,
+        GgmlType::Q5_1 => quantize_row_q5_1_weighted,
+        GgmlType::Q2_K => k_quants::quantize_row_q2_k_weighted,
+        GgmlType::Q3_K => k_quants::quantize_row_q3_k_weighted,
+        GgmlType::Q4_K => k_quants::quantize_row_q4_k_weighted,
+        GgmlType::Q5_K => k_quants::quantize_row_q5_k_weighted,
+        GgmlType::Q6_K => k_quants::quantize_row_q6_k_weighted,
+        GgmlType::IQ2_XXS => i_quants::quantize_row_iq2_xxs_weighted,
+        GgmlType::IQ2_XS => i_quants::quantize_row_iq2_xs_weighted,
+        GgmlType::IQ2_S => i_quants::quantize_row_iq2_s_weighted,
+        GgmlType::IQ3_XXS => i_quants::quantize_row_iq3_xxs_weighted,
+        GgmlType::IQ3_S => i_quants::quantize_row_iq3_s_weighted,
+        GgmlType::IQ4_NL => i_quants::quantize_row_iq4_nl_weighted,
+        GgmlType::IQ4_XS => i_quants::quantize_row_iq4_xs_weighted,
+        _ => return None,
+    })
+}
+
+/// Whether quantizing `name` to `ggml_type` needs imatrix weights. The IQ2
+/// quantizers cannot run without them, and as in llama-quantize IQ3_XXS is
+/// only allowed unweighted for the token embeddings and output.
+fn requires_imatrix(name: &str, ggml_type: GgmlType) -> bool {
+    match ggml_type {
+        GgmlType::IQ3_XXS => name != "token_embd.weight" && name != "output.weight",
+        ty => !has_unweighted_quantizer(ty),
     }
+}
 
-    let (mut metadata, tensors) = read_gguf_file(&cli.input)?;
+/// Converts f32 values of a tensor with `dims` to `ggml_type`. Every type a
+/// preset can choose, including fallbacks, has a quantizer; rule types are
+/// checked with [`has_quantizer`] when the rules are loaded, and planning
+/// refuses types that [`requires_imatrix`] without weights for the tensor.
+///
+/// Rows are independent, so the tensor is split into runs of whole rows that
+/// are quantized in parallel and joined in order, giving the same bytes as a
+/// single pass.
+fn quantize_tensor(tensor: &[f32], ggml_type: GgmlType, dims: &[u64], imatrix: Option<&[f32]>) -> Vec<u8> {
+    let n_per_row = dims.first().copied().unwrap_or(1).max(1) as usize;
+    let rows_per_chunk = (ROW_CHUNK_VALUES / n_per_row).max(1);
+
+    tensor
+        .par_chunks(rows_per_chunk * n_per_row)
+        .enumerate()
+        .map(|(c, rows)| quantize_rows(rows, ggml_type, dims, c * rows_per_chunk, imatrix))
+        .collect::<Vec<_>>()
+        .concat()
+}
 
-    let quantized: Vec<GGUFTensor> = tensors
-        .into_iter()
-        .map(|t| {
-            let float_count = t.dims.iter().product::<u64>() as usize;
-            let mut floats = Vec::with_capacity(float_count);
+/// Quantizes whole rows of a tensor starting at row `first_row`.
+///
+/// `imatrix` holds `dims[0]` column weights per expert (`dims[2]`). With it,
+/// types that support importance weighting are quantized row by row, each
+/// row using its expert's weights.
+fn quantize_rows(
+    tensor: &[f32],
+    ggml_type: GgmlType,
+    dims: &[u64],
+    first_row: usize,
+    imatrix: Option<&[f32]>,
+) -> Vec<u8> {
+    if let (Some(imatrix), Some(quantize_row)) = (imatrix, weighted_quantizer(ggml_type)) {
+        let n_per_row = dims[0] as usize;
+        let rows_per_expert = dims.get(1).copied().unwrap_or(1) as usize;
+        let mut out = Vec::new();
+        for (r, row) in tensor.chunks_exact(n_per_row).enumerate() {
+            let expert = (first_row + r) / rows_per_expert;
+            out.extend(quantize_row(row, &imatrix[expert * n_per_row..(expert + 1) * n_per_row]));
+        }
+        return out;
+    }
 
-            for chunk in t.values.chunks_exact(4) {
-                let val = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
-                floats.push(val);
-            }
+    match ggml_type {
+        GgmlType::F32 => tensor.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        GgmlType::F16 => tensor
+            .iter()
+            .flat_map(|&v| f16::from_f32(v).to_le_bytes())
+            .collect(),
+        GgmlType::BF16 => tensor
+            .iter()
+            .flat_map(|&v| bf16::from_f32(v).to_le_bytes())
+            .collect(),
+        GgmlType::Q4_0 => quantize_tensor_q4_0(tensor),
+        GgmlType::Q4_1 => quantize_tensor_q4_1(tensor),
+        GgmlType::Q5_0 => quantize_tensor_q5_0(tensor),
+        GgmlType::Q5_1 => quantize_tensor_q5_1(tensor),
+        GgmlType::Q8_0 => quantize_tensor_q8_0(tensor),
+        GgmlType::Q2_K => k_quants::quantize_tensor_q2_k(tensor),
+        GgmlType::Q3_K => k_quants::quantize_tensor_q3_k(tensor),
+        GgmlType::Q4_K => k_quants::quantize_tensor_q4_k(tensor),
+        GgmlType::Q5_K => k_quants::quantize_tensor_q5_k(tensor),
+        GgmlType::Q6_K => k_quants::quantize_tensor_q6_k(tensor),
+        GgmlType::IQ3_XXS => i_quants::quantize_tensor_iq3_xxs(tensor),
+        GgmlType::IQ3_S => i_quants::quantize_tensor_iq3_s(tensor),
+        GgmlType::IQ4_NL => i_quants::quantize_tensor_iq4_nl(tensor),
+        GgmlType::IQ4_XS => i_quants::quantize_tensor_iq4_xs(tensor),
+        other => unreachable!("{other} needs imatrix weights, which planning checks for"),
+    }
+}
 
-            if floats.len() != float_count {
-                panic!(
-                    "Tensor '{}' has {} floats, expected {}",
-                    t.name,
-                    floats.len(),
-                    float_count
-                );
-            }
+fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let Some(preset) = Preset::from_name(&cli.format) else {
+        let names: Vec<_> = Preset::ALL.iter().map(|p| p.name()).collect();
+        eprintln!(
+            "❌ Unsupported quantization format: {} (expected one of {})",
+            cli.format,
+            names.join(", ")
+        );
+        std::process::exit(1);
+    };
+
+    let rules = match &cli.rules {
+        Some(path) => rules::load_rules(path)?,
+        None => Vec::new(),
+    };
+    if let Some(rule) = rules.iter().find(|r| !has_quantizer(r.ggml_type)) {
+        eprintln!("❌ Rule '{}' asks for {}, which quantize-rs cannot produce", rule.pattern, rule.ggml_type);
+        std::process::exit(1);
+    }
 
-            let (values, new_type_id) = quantize_tensor(&floats, &format);
+    let imatrix = match &cli.imatrix {
+        Some(path) => {
+            let imatrix = Imatrix::load(path)?;
+            println!("📊 Loaded imatrix with {} entries from {}", imatrix.len(), path.display());
+            Some(imatrix)
+        }
+        None => None,
+    };
 
-            GGUFTensor {
-                name: t.name,
-                type_id: new_type_id,
-                dims: t.dims,
-                offset: 0,
-                values,
-            }
-        })
-        .collect();
+    let input = Input::open(
+        &cli.input,
+        cli.config.as_deref(),
+        cli.tensor_map.as_deref(),
+        cli.ignore_rope_scaling,
+    )?;
+    let mut metadata = input.metadata.clone();
 
     // ⬇ Inject quantization metadata
     metadata.insert("quantized".to_string(), GGUFValue::Bool(true));
     metadata.insert(
         "quantization_format".to_string(),
-        GGUFValue::String(format.as_str().to_string()),
+        GGUFValue::String(preset.name().to_string()),
     );
     metadata.insert("precision".to_string(), GGUFValue::F64(1.0)); // You can later replace this with a real loss metric
+    metadata.insert(
+        "general.file_type".to_string(),
+        GGUFValue::U32(preset.file_type()),
+    );
+    if let (Some(path), Some(imatrix)) = (&cli.imatrix, &imatrix) {
+        // Same keys llama-quantize records
+        metadata.insert(
+            "quantize.imatrix.file".to_string(),
+            GGUFValue::String(path.display().to_string()),
+        );
+        metadata.insert(
+            "quantize.imatrix.entries_count".to_string(),
+            GGUFValue::I32(imatrix.len() as i32),
+        );
+        if let Some(dataset) = &imatrix.dataset {
+            metadata.insert("quantize.imatrix.dataset".to_string(), GGUFValue::String(dataset.clone()));
+        }
+        if let Some(chunks) = imatrix.chunk_count {
+            metadata.insert("quantize.imatrix.chunks_count".to_string(), GGUFValue::I32(chunks as i32));
+        }
+    }
 
-    write_gguf_file(&cli.output, &metadata, &quantized)?;
-    println!("✅ Wrote quantized GGUF to {}", cli.output.display());
+    // --exclude and --keep come first and copy tensors unquantized, then
+    // the rules and the preset
+    let mut planner = TypePlanner::new(preset, cli.pure, &input.metadata, &input.tensors)
+        .with_rules(rules.clone())
+        .with_imatrix(imatrix.is_some());
+    let mut plan = Vec::with_capacity(input.tensors.len());
+    let mut requantized = 0;
+    let mut missing_imatrix = 0;
+    for t in &input.tensors {
+        let (mut action, mut reason) = if let Some(p) = cli.exclude.iter().find(|p| p.matches(&t.name)) {
+            (Action::Copy, format!("--exclude {p}"))
+        } else if let Some(p) = cli.keep.iter().find(|p| p.matches(&t.name)) {
+            (Action::Copy, format!("--keep {p}"))
+        } else {
+            match planner.tensor_type(t) {
+                // Norms and biases are F32 in llama.cpp's output whatever the input holds
+                Choice::Unquantizable if presets::n_dims(t) == 1 => {
+                    (Action::Quantize(GgmlType::F32), "not quantizable, kept at F32".to_string())
+                }
+                Choice::Unquantizable => (Action::Copy, "not quantizable".to_string()),
+                Choice::Preset(ty) => (Action::Quantize(ty), format!("preset {}", preset.name())),
+                Choice::Rule(i, ty) => (
+                    Action::Quantize(ty),
+                    format!("rule {}: {}", i + 1, rules[i].pattern),
+                ),
+            }
+        };
+
+        // A tensor already of the chosen type is copied as it is; one of
+        // another quantized type has to be decoded and quantized again
+        if let (Action::Quantize(ty), Some(source)) = (action, t.ggml_type()) {
+            if source == ty {
+                action = Action::Copy;
+                reason = format!("{reason}, already {ty}");
+            } else if source.is_quantized() {
+                if !cli.allow_requantize {
+                    eprintln!(
+                        "❌ Tensor '{}' is already quantized as {source}; pass --allow-requantize to requantize it to {ty}",
+                        t.name
+                    );
+                    std::process::exit(1);
+                }
+                requantized += 1;
+            }
+        }
+
+        let weights = match (action, &imatrix) {
+            (Action::Quantize(ty), Some(imatrix)) if weighted_quantizer(ty).is_some() => {
+                match imatrix.weights_for(t) {
+                    Ok(Some(weights)) => Some(weights),
+                    Ok(None) => {
+                        println!("     no imatrix entry for {}, quantizing unweighted", t.name);
+                        None
+                    }
+                    Err(e) => {
+                        eprintln!("❌ {e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            _ => None,
+        };
+        if let Action::Quantize(ty) = action {
+            if weights.is_none() && requires_imatrix(&t.name, ty) {
+                if !cli.dry_run {
+                    eprintln!(
+                        "❌ Tensor '{}' needs an importance matrix to be quantized to {ty} ({reason}); pass --imatrix with an entry for it",
+                        t.name
+                    );
+                    std::process::exit(1);
+                }
+                missing_imatrix += 1;
+            }
+        }
+        plan.push(Step {
+            action,
+            reason,
+            imatrix: weights,
+        });
+    }
+
+    if requantized > 0 {
+        println!(
+            "⚠️  Requantizing {requantized} already-quantized tensors; quality will be lower than quantizing the original weights"
+        );
+    }
+
+    if missing_imatrix > 0 {
+        println!("⚠️  {missing_imatrix} tensors need an importance matrix for this quantization; pass --imatrix");
+    }
+
+    if cli.dry_run {
+        for (t, step) in input.tensors.iter().zip(&plan) {
+            println!("  {}", step_line(t, step));
+        }
+        return Ok(());
+    }
+
+    // The output directory is known up front, so tensors are quantized and
+    // written a batch at a time instead of being held in memory together.
+    let mut directory = Vec::with_capacity(input.tensors.len());
+    for (t, step) in input.tensors.iter().zip(&plan) {
+        let (type_id, size) = match step.action {
+            Action::Copy => (t.type_id, t.size),
+            Action::Quantize(ty) => match ty.tensor_nbytes(&t.dims) {
+                Some(size) => (ty.id(), size),
+                None => {
+                    eprintln!(
+                        "❌ Tensor '{}' with dims {:?} cannot be quantized to {}: rows must be a multiple of {}",
+                        t.name,
+                        t.dims,
+                        ty,
+                        ty.block_size()
+                    );
+                    std::process::exit(1);
+                }
+            },
+        };
+        directory.push(TensorInfo {
+            name: t.name.clone(),
+            type_id,
+            dims: t.dims.clone(),
+            offset: 0,
+            size,
+        });
+    }
+    let mut writer = GgufWriter::create(&cli.output, &metadata, directory, &WriteOptions::default())?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(cli.threads.unwrap_or(0))
+        .build()
+        .map_err(io::Error::other)?;
+    println!("🧵 Threads: {}", pool.current_num_threads());
+
+    let steps: Vec<_> = input.tensors.iter().zip(plan.iter().enumerate()).collect();
+    let n = steps.len();
+    let width = n.to_string().len();
+    let mut done = 0;
+    let started = Instant::now();
+
+    for batch in batches(&steps) {
+        // Batch results come back in tensor order whatever order they finish in
+        let results: Vec<_> = pool.install(|| {
+            batch
+                .par_iter()
+                .map(|&(_, (i, step))| {
+                    let start = Instant::now();
+                    let data = tensor_output(&input, i, step)?;
+                    Ok::<_, io::Error>((data, start.elapsed()))
+                })
+                .collect()
+        });
+
+        for (&(t, (_, step)), result) in batch.iter().zip(results) {
+            let (data, elapsed) = result?;
+            done += 1;
+            writer.write_tensor_data(&data)?;
+            println!("  [{done:>width$}/{n}] {} {}", step_line(t, step), format_elapsed(elapsed));
+        }
+    }
+
+    writer.finish()?;
+    println!(
+        "✅ Wrote {} GGUF to {} in {:.1}s",
+        preset.name(),
+        cli.output.display(),
+        started.elapsed().as_secs_f64()
+    );
     Ok(())
 }
+
+/// Splits the plan into runs of consecutive tensors of at most
+/// [`BATCH_BYTES`] input, or a single larger tensor
+fn batches<'a, T>(steps: &'a [(&TensorInfo, T)]) -> Vec<&'a [(&'a TensorInfo, T)]> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut bytes = 0;
+    for (i, (t, _)) in steps.iter().enumerate() {
+        if i > start && bytes + t.size > BATCH_BYTES {
+            batches.push(&steps[start..i]);
+            start = i;
+            bytes = 0;
+        }
+        bytes += t.size;
+    }
+    if start < steps.len() {
+        batches.push(&steps[start..]);
+    }
+    batches
+}
+
+/// The bytes the `i`th tensor contributes to the output
+fn tensor_output<'f>(input: &'f Input, i: usize, step: &Step) -> io::Result<Cow<'f, [u8]>> {
+    let t = &input.tensors[i];
+    match step.action {
+        Action::Copy => input.tensor_data(i),
+        Action::Quantize(ty) => {
+            let floats = input.tensor_f32(i)?;
+            Ok(Cow::Owned(quantize_tensor(&floats, ty, &t.dims, step.imatrix)))
+        }
+    }
+}
+
+/// One line of the plan: name, source and output type, and what decided it
+fn step_line(t: &TensorInfo, step: &Step) -> String {
+    let source = t.ggml_type().map_or("?", |ty| ty.name());
+    let target = match step.action {
+        Action::Copy => source,
+        Action::Quantize(ty) => ty.name(),
+    };
+    format!("{:<40} {:>6} → {:<6} ({})", t.name, source, target, step.reason)
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    if elapsed.as_secs_f64() >= 1.0 {
+        format!("{:.1}s", elapsed.as_secs_f64())
+    } else {
+        format!("{}ms", elapsed.as_millis())
+    }
+}
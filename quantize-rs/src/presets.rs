@@ -0,0 +1,675 @@
+//! Quantization presets mirroring llama.cpp's `llama_ftype` mixes. A preset
+//! has a default ggml type; [`TypePlanner`] then adjusts it per tensor by
+//! role and layer the way `llama_tensor_get_type` does.
+
+use std::collections::{BTreeMap, HashSet};
+
+use gguf_core::types::{GGUFValue, GgmlType, TensorInfo};
+
+use crate::rules::Rule;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum Preset {
+    F16,
+    BF16,
+    Q4_0,
+    Q4_1,
+    Q5_0,
+    Q5_1,
+    Q8_0,
+    Q2_K,
+    Q2_K_S,
+    Q3_K_S,
+    Q3_K_M,
+    Q3_K_L,
+    Q4_K_S,
+    Q4_K_M,
+    Q5_K_S,
+    Q5_K_M,
+    Q6_K,
+    IQ2_XXS,
+    IQ2_XS,
+    IQ2_S,
+    IQ2_M,
+    IQ3_XXS,
+    IQ3_XS,
+    IQ3_S,
+    IQ3_M,
+    IQ4_NL,
+    IQ4_XS,
+}
+
+impl Preset {
+    pub const ALL: &'static [Preset] = &[
+        Preset::F16,
+        Preset::BF16,
+        Preset::Q4_0,
+        Preset::Q4_1,
+        Preset::Q5_0,
+        Preset::Q5_1,
+        Preset::Q8_0,
+        Preset::Q2_K,
+        Preset::Q2_K_S,
+        Preset::Q3_K_S,
+        Preset::Q3_K_M,
+        Preset::Q3_K_L,
+        Preset::Q4_K_S,
+        Preset::Q4_K_M,
+        Preset::Q5_K_S,
+        Preset::Q5_K_M,
+        Preset::Q6_K,
+        Preset::IQ2_XXS,
+        Preset::IQ2_XS,
+        Preset::IQ2_S,
+        Preset::IQ2_M,
+        Preset::IQ3_XXS,
+        Preset::IQ3_XS,
+        Preset::IQ3_S,
+        Preset::IQ3_M,
+        Preset::IQ4_NL,
+        Preset::IQ4_XS,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::F16 => "F16",
+            Preset::BF16 => "BF16",
+            Preset::Q4_0 => "Q4_0",
+            Preset::Q4_1 => "Q4_1",
+            Preset::Q5_0 => "Q5_0",
+            Preset::Q5_1 => "Q5_1",
+            Preset::Q8_0 => "Q8_0",
+            Preset::Q2_K => "Q2_K",
+            Preset::Q2_K_S => "Q2_K_S",
+            Preset::Q3_K_S => "Q3_K_S",
+            Preset::Q3_K_M => "Q3_K_M",
+            Preset::Q3_K_L => "Q3_K_L",
+            Preset::Q4_K_S => "Q4_K_S",
+            Preset::Q4_K_M => "Q4_K_M",
+            Preset::Q5_K_S => "Q5_K_S",
+            Preset::Q5_K_M => "Q5_K_M",
+            Preset::Q6_K => "Q6_K",
+            Preset::IQ2_XXS => "IQ2_XXS",
+            Preset::IQ2_XS => "IQ2_XS",
+            Preset::IQ2_S => "IQ2_S",
+            Preset::IQ2_M => "IQ2_M",
+            Preset::IQ3_XXS => "IQ3_XXS",
+            Preset::IQ3_XS => "IQ3_XS",
+            Preset::IQ3_S => "IQ3_S",
+            Preset::IQ3_M => "IQ3_M",
+            Preset::IQ4_NL => "IQ4_NL",
+            Preset::IQ4_XS => "IQ4_XS",
+        }
+    }
+
+    /// Looks a preset up by name, case-insensitively. As in llama-quantize,
+    /// `Q3_K`, `Q4_K` and `Q5_K` mean the `_M` mixes.
+    pub fn from_name(name: &str) -> Option<Preset> {
+        let name = name.to_ascii_uppercase();
+        let name = match name.as_str() {
+            "Q3_K" => "Q3_K_M",
+            "Q4_K" => "Q4_K_M",
+            "Q5_K" => "Q5_K_M",
+            other => other,
+        };
+        Preset::ALL.iter().copied().find(|p| p.name() == name)
+    }
+
+    /// The `llama_ftype` value stored in `general.file_type`
+    pub fn file_type(self) -> u32 {
+        match self {
+            Preset::F16 => 1,
+            Preset::Q4_0 => 2,
+            Preset::Q4_1 => 3,
+            Preset::Q8_0 => 7,
+            Preset::Q5_0 => 8,
+            Preset::Q5_1 => 9,
+            Preset::Q2_K => 10,
+            Preset::Q3_K_S => 11,
+            Preset::Q3_K_M => 12,
+            Preset::Q3_K_L => 13,
+            Preset::Q4_K_S => 14,
+            Preset::Q4_K_M => 15,
+            Preset::Q5_K_S => 16,
+            Preset::Q5_K_M => 17,
+            Preset::Q6_K => 18,
+            Preset::IQ2_XXS => 19,
+            Preset::IQ2_XS => 20,
+            Preset::Q2_K_S => 21,
+            Preset::IQ3_XS => 22,
+            Preset::IQ3_XXS => 23,
+            Preset::IQ4_NL => 25,
+            Preset::IQ3_S => 26,
+            Preset::IQ3_M => 27,
+            Preset::IQ2_S => 28,
+            Preset::IQ2_M => 29,
+            Preset::IQ4_XS => 30,
+            Preset::BF16 => 32,
+        }
+    }
+
+    /// Type used for every quantizable tensor the mix does not single out
+    pub fn default_type(self) -> GgmlType {
+        match self {
+            Preset::F16 => GgmlType::F16,
+            Preset::BF16 => GgmlType::BF16,
+            Preset::Q4_0 => GgmlType::Q4_0,
+            Preset::Q4_1 => GgmlType::Q4_1,
+            Preset::Q5_0 => GgmlType::Q5_0,
+            Preset::Q5_1 => GgmlType::Q5_1,
+            Preset::Q8_0 => GgmlType::Q8_0,
+            Preset::Q2_K | Preset::Q2_K_S => GgmlType::Q2_K,
+            Preset::Q3_K_S | Preset::Q3_K_M | Preset::Q3_K_L => GgmlType::Q3_K,
+            Preset::Q4_K_S | Preset::Q4_K_M => GgmlType::Q4_K,
+            Preset::Q5_K_S | Preset::Q5_K_M => GgmlType::Q5_K,
+            Preset::Q6_K => GgmlType::Q6_K,
+            Preset::IQ2_XXS => GgmlType::IQ2_XXS,
+            // As in llama.cpp the IQ2_S mix is mostly IQ2_XS; IQ2_M uses the IQ2_S type
+            Preset::IQ2_XS | Preset::IQ2_S => GgmlType::IQ2_XS,
+            Preset::IQ2_M => GgmlType::IQ2_S,
+            Preset::IQ3_XXS => GgmlType::IQ3_XXS,
+            Preset::IQ3_XS | Preset::IQ3_S | Preset::IQ3_M => GgmlType::IQ3_S,
+            Preset::IQ4_NL => GgmlType::IQ4_NL,
+            Preset::IQ4_XS => GgmlType::IQ4_XS,
+        }
+    }
+}
+
+/// Tensor roles that the mixes treat differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Category {
+    Output,
+    TokenEmbd,
+    AttentionQkv,
+    AttentionKvB,
+    AttentionV,
+    AttentionK,
+    AttentionQ,
+    AttentionOutput,
+    FfnUp,
+    FfnGate,
+    FfnDown,
+    Other,
+}
+
+impl Category {
+    fn of(name: &str) -> Category {
+        if name == "output.weight" {
+            Category::Output
+        } else if name == "token_embd.weight" || name == "per_layer_token_embd.weight" {
+            Category::TokenEmbd
+        } else if name.contains("attn_qkv.weight") {
+            Category::AttentionQkv
+        } else if name.contains("attn_kv_b.weight") {
+            Category::AttentionKvB
+        } else if name.contains("attn_v.weight") {
+            Category::AttentionV
+        } else if name.contains("attn_k.weight") {
+            Category::AttentionK
+        } else if name.contains("attn_q.weight") {
+            Category::AttentionQ
+        } else if name.contains("attn_output.weight") {
+            Category::AttentionOutput
+        } else if name.contains("ffn_up") {
+            Category::FfnUp
+        } else if name.contains("ffn_gate") {
+            Category::FfnGate
+        } else if name.contains("ffn_down") {
+            Category::FfnDown
+        } else {
+            Category::Other
+        }
+    }
+
+    /// Fused QKV and MLA KV projections count as value projections
+    fn is_attn_v(self) -> bool {
+        matches!(
+            self,
+            Category::AttentionV | Category::AttentionQkv | Category::AttentionKvB
+        )
+    }
+}
+
+/// Substrings of tensors that stay at their source type: routing tables,
+/// positional tables and tensors too small to be worth quantizing
+const NEVER_QUANTIZED: &[&str] = &[
+    "_norm.weight",
+    "ffn_gate_inp.weight",
+    "altup",
+    "laurel",
+    "per_layer_model_proj",
+    "position_embd.weight",
+    "token_types.weight",
+    "ssm_conv1d",
+    "shortconv.conv.weight",
+    "time_mix_first.weight",
+    "time_mix_w0.weight",
+    "time_mix_w1.weight",
+    "time_mix_w2.weight",
+    "time_mix_v0.weight",
+    "time_mix_v1.weight",
+    "time_mix_v2.weight",
+    "time_mix_a0.weight",
+    "time_mix_a1.weight",
+    "time_mix_a2.weight",
+    "time_mix_g1.weight",
+    "time_mix_g2.weight",
+    "time_mix_decay_w1.weight",
+    "time_mix_decay_w2.weight",
+    "time_mix_lerp_fused.weight",
+    "attn_rel_b.weight",
+];
+
+/// Dimensions of `tensor` not counting trailing ones, as in `ggml_n_dims`
+pub fn n_dims(tensor: &TensorInfo) -> usize {
+    tensor.dims.iter().rposition(|&d| d > 1).map_or(1, |i| i + 1)
+}
+
+/// Whether llama.cpp would quantize this tensor at all: only matrices whose
+/// name ends in `weight`, excluding norms and the tensors above
+fn allows_quantization(tensor: &TensorInfo) -> bool {
+    n_dims(tensor) >= 2
+        && tensor.name.ends_with("weight")
+        && !NEVER_QUANTIZED.iter().any(|s| tensor.name.contains(s))
+}
+
+/// A planned output type and what chose it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Choice {
+    /// Not a tensor llama.cpp would quantize; copied unchanged, except that
+    /// 1-D tensors such as norms and biases are stored as F32
+    Unquantizable,
+    /// Picked by the preset's mix
+    Preset(GgmlType),
+    /// Set by the rule at this index
+    Rule(usize, GgmlType),
+}
+
+/// Picks the output type of each tensor for a preset. Like llama.cpp, the
+/// choice for some roles depends on how many tensors of that role came
+/// before, so tensors must be planned once each, in file order.
+pub struct TypePlanner {
+    preset: Preset,
+    pure: bool,
+    rules: Vec<Rule>,
+    has_imatrix: bool,
+    is_falcon: bool,
+    is_70b: bool,
+    n_gqa: u64,
+    n_expert: u64,
+    n_layer: usize,
+    has_tied_embeddings: bool,
+    n_attention_wv: usize,
+    i_attention_wv: usize,
+    i_ffn_down: usize,
+    i_ffn_gate: usize,
+    i_ffn_up: usize,
+}
+
+impl TypePlanner {
+    /// Reads the hyperparameters the mixes depend on from `{arch}.*`
+    /// metadata, counting `blk.N` layers when `block_count` is missing.
+    /// A `pure` planner gives every quantizable tensor the default type.
+    pub fn new(
+        preset: Preset,
+        pure: bool,
+        metadata: &BTreeMap<String, GGUFValue>,
+        tensors: &[TensorInfo],
+    ) -> Self {
+        let arch = match metadata.get("general.architecture") {
+            Some(GGUFValue::String(s)) => s.as_str(),
+            _ => "",
+        };
+        let hparam = |key: &str| metadata_u64(metadata, &format!("{arch}.{key}"));
+
+        let n_layer = hparam("block_count").map(|n| n as usize).unwrap_or_else(|| {
+            tensors
+                .iter()
+                .filter_map(|t| layer_index(&t.name))
+                .collect::<HashSet<_>>()
+                .len()
+        });
+        let n_head = hparam("attention.head_count").unwrap_or(0);
+        let n_head_kv = hparam("attention.head_count_kv").unwrap_or(n_head);
+        let n_gqa = n_head.checked_div(n_head_kv).unwrap_or(0);
+
+        TypePlanner {
+            preset,
+            pure,
+            rules: Vec::new(),
+            has_imatrix: false,
+            is_falcon: arch == "falcon",
+            // llama.cpp's LLM_TYPE_70B: 80 llama layers with grouped-query attention
+            is_70b: arch == "llama" && n_layer == 80 && n_gqa > 1,
+            n_gqa,
+            n_expert: hparam("expert_count").unwrap_or(0),
+            n_layer,
+            has_tied_embeddings: !tensors.iter().any(|t| Category::of(&t.name) == Category::Output),
+            n_attention_wv: tensors
+                .iter()
+                .filter(|t| Category::of(&t.name).is_attn_v())
+                .count(),
+            i_attention_wv: 0,
+            i_ffn_down: 0,
+            i_ffn_gate: 0,
+            i_ffn_up: 0,
+        }
+    }
+
+    /// Ordered `pattern → type` overrides. The first rule matching a
+    /// quantizable tensor replaces the mix's choice for it, and like
+    /// llama.cpp's manual overrides it does not advance the layer counters.
+    pub fn with_rules(mut self, rules: Vec<Rule>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Whether tensors will be quantized with an importance matrix, which
+    /// lets the Q4_0 and Q5_0 mixes move the first few `ffn_down` layers
+    /// to Q4_1 and Q5_1 as llama.cpp does. Without one, the IQ3_XXS and
+    /// IQ4 mixes keep more of `attn_v` and `ffn_down` at K-quant types.
+    pub fn with_imatrix(mut self, has_imatrix: bool) -> Self {
+        self.has_imatrix = has_imatrix;
+        self
+    }
+
+    /// Output type for the next tensor
+    pub fn tensor_type(&mut self, tensor: &TensorInfo) -> Choice {
+        if !allows_quantization(tensor) {
+            return Choice::Unquantizable;
+        }
+
+        if let Some(i) = self.rules.iter().position(|r| r.pattern.matches(&tensor.name)) {
+            return Choice::Rule(i, fallback(tensor, self.rules[i].ggml_type));
+        }
+
+        let default = self.preset.default_type();
+        if !default.is_quantized() {
+            return Choice::Preset(default);
+        }
+
+        let ty = if self.pure {
+            default
+        } else {
+            self.refine(default, tensor)
+        };
+        Choice::Preset(fallback(tensor, ty))
+    }
+
+    /// The role- and layer-based part of `llama_tensor_get_type`
+    fn refine(&mut self, default: GgmlType, tensor: &TensorInfo) -> GgmlType {
+        use GgmlType as T;
+
+        let preset = self.preset;
+        let category = Category::of(&tensor.name);
+        let ncols = tensor.dims.first().copied().unwrap_or(1);
+        let mut new_type = default;
+
+        if category == Category::Output
+            || (self.has_tied_embeddings && category == Category::TokenEmbd)
+        {
+            if self.is_falcon || !ncols.is_multiple_of(default.block_size()) {
+                new_type = T::Q8_0;
+            } else if matches!(
+                preset,
+                Preset::IQ2_XXS | Preset::IQ2_XS | Preset::IQ3_XXS | Preset::IQ2_S | Preset::IQ2_M
+            ) {
+                new_type = T::Q5_K;
+            } else if new_type != T::Q8_0 {
+                new_type = T::Q6_K;
+            }
+        } else if category == Category::TokenEmbd {
+            match preset {
+                Preset::IQ2_XXS | Preset::IQ2_XS => new_type = T::Q2_K,
+                Preset::IQ2_S | Preset::IQ2_M | Preset::IQ3_XXS => new_type = T::IQ3_S,
+                _ => {}
+            }
+        } else if is_iq2_mix(preset) {
+            // The 2-bit mixes only raise a few roles, and by less
+            let iq2_s = matches!(preset, Preset::IQ2_S | Preset::IQ2_M);
+            if category.is_attn_v() {
+                new_type = if self.n_gqa >= 4 || self.n_expert >= 4 {
+                    T::Q4_K
+                } else if iq2_s {
+                    T::IQ3_S
+                } else {
+                    T::Q2_K
+                };
+                self.i_attention_wv += 1;
+            } else if category == Category::AttentionK && self.n_expert == 8 {
+                new_type = T::Q4_K;
+            } else if category == Category::FfnDown {
+                if self.i_ffn_down < self.n_layer / 8 {
+                    new_type = if iq2_s { T::IQ3_S } else { T::Q2_K };
+                }
+                self.i_ffn_down += 1;
+            } else if category == Category::AttentionOutput {
+                if self.n_expert == 8 {
+                    new_type = T::Q5_K;
+                } else if iq2_s {
+                    new_type = T::IQ3_S;
+                }
+            }
+        } else if category.is_attn_v() {
+            let (i, n) = (self.i_attention_wv, self.n_attention_wv);
+            match preset {
+                Preset::Q2_K => new_type = if self.n_gqa >= 4 { T::Q4_K } else { T::Q3_K },
+                Preset::Q2_K_S if self.n_gqa >= 4 => new_type = T::Q4_K,
+                Preset::IQ3_XXS => {
+                    new_type = if self.n_gqa >= 4 {
+                        T::Q4_K
+                    } else if !self.has_imatrix {
+                        T::IQ3_S
+                    } else {
+                        T::IQ3_XXS
+                    }
+                }
+                Preset::IQ3_XS | Preset::IQ3_S if self.n_gqa >= 4 => new_type = T::Q4_K,
+                Preset::IQ3_M => new_type = T::Q4_K,
+                Preset::Q3_K_M => new_type = if i < 2 { T::Q5_K } else { T::Q4_K },
+                Preset::Q3_K_L => new_type = T::Q5_K,
+                Preset::IQ4_NL | Preset::IQ4_XS if self.n_gqa >= 4 => new_type = T::Q5_K,
+                Preset::Q4_K_M | Preset::Q5_K_M if use_more_bits(i, n) => new_type = T::Q6_K,
+                Preset::Q4_K_S if i < 4 => new_type = T::Q5_K,
+                _ => {}
+            }
+            // 70B models share each attn_v between 8 heads, so it is 8x
+            // smaller than attn_q and a few more bits are nearly free
+            if self.is_70b && matches!(new_type, T::Q3_K | T::Q4_K) {
+                new_type = T::Q5_K;
+            }
+            if self.n_expert == 8 {
+                new_type = T::Q8_0;
+            }
+            self.i_attention_wv += 1;
+        } else if category == Category::AttentionK {
+            if self.n_expert == 8 {
+                new_type = T::Q8_0;
+            } else if preset == Preset::IQ3_XS {
+                new_type = T::IQ3_XXS;
+            } else if preset == Preset::IQ3_XXS {
+                new_type = T::IQ2_S;
+            }
+        } else if category == Category::AttentionQ {
+            match preset {
+                Preset::IQ3_XS => new_type = T::IQ3_XXS,
+                Preset::IQ3_XXS => new_type = T::IQ2_S,
+                _ => {}
+            }
+        } else if category == Category::FfnDown {
+            let (i, n) = self.layer_info(self.i_ffn_down, &tensor.name);
+            match preset {
+                Preset::Q2_K => new_type = T::Q3_K,
+                Preset::Q2_K_S if i < n / 8 => new_type = T::Q4_K,
+                Preset::IQ3_XXS if !self.has_imatrix => {
+                    new_type = if i < n / 8 { T::Q4_K } else { T::Q3_K }
+                }
+                Preset::Q3_K_M => {
+                    new_type = if i < n / 16 {
+                        T::Q5_K
+                    } else if !self.is_falcon || use_more_bits(i, n) {
+                        T::Q4_K
+                    } else {
+                        T::Q3_K
+                    }
+                }
+                Preset::IQ3_M if i < n / 8 || (self.n_expert == 8 && use_more_bits(i, n)) => {
+                    new_type = T::Q4_K
+                }
+                Preset::Q3_K_L => new_type = if self.is_falcon { T::Q4_K } else { T::Q5_K },
+                Preset::Q4_K_M => {
+                    if self.is_falcon {
+                        new_type = if i < n / 16 {
+                            T::Q6_K
+                        } else if use_more_bits(i, n) {
+                            T::Q5_K
+                        } else {
+                            T::Q4_K
+                        };
+                    } else if use_more_bits(i, n) {
+                        new_type = T::Q6_K;
+                    }
+                }
+                Preset::IQ4_NL | Preset::IQ4_XS if i < n / 8 && !self.has_imatrix => new_type = T::Q5_K,
+                Preset::Q5_K_M if use_more_bits(i, n) => new_type = T::Q6_K,
+                Preset::Q4_K_S if !self.is_falcon && i < n / 8 => new_type = T::Q5_K,
+                // Guards against the first ffn_down layers going wrong even
+                // with an imatrix; without one the output stays as it was
+                Preset::Q4_0 if self.has_imatrix && i < n / 8 => new_type = T::Q4_1,
+                Preset::Q5_0 if self.has_imatrix && i < n / 8 => new_type = T::Q5_1,
+                _ => {}
+            }
+            self.i_ffn_down += 1;
+        } else if category == Category::AttentionOutput {
+            if self.is_falcon {
+                if preset == Preset::Q3_K_L {
+                    new_type = T::Q4_K;
+                }
+            } else if self.n_expert == 8 {
+                if matches!(
+                    preset,
+                    Preset::Q2_K
+                        | Preset::IQ3_XS
+                        | Preset::IQ3_XXS
+                        | Preset::Q3_K_S
+                        | Preset::Q3_K_M
+                        | Preset::IQ4_NL
+                        | Preset::Q4_K_S
+                        | Preset::Q4_K_M
+                        | Preset::IQ3_S
+                        | Preset::IQ3_M
+                        | Preset::IQ4_XS
+                ) {
+                    new_type = T::Q5_K;
+                }
+            } else {
+                match preset {
+                    Preset::Q2_K => new_type = T::Q3_K,
+                    Preset::IQ3_XXS => new_type = T::IQ3_S,
+                    Preset::Q3_K_M | Preset::IQ3_M => new_type = T::Q4_K,
+                    Preset::Q3_K_L => new_type = T::Q5_K,
+                    _ => {}
+                }
+            }
+        } else if matches!(category, Category::FfnGate | Category::FfnUp) {
+            let counter = if category == Category::FfnGate {
+                &mut self.i_ffn_gate
+            } else {
+                &mut self.i_ffn_up
+            };
+            let i_counter = *counter;
+            *counter += 1;
+            let (i, n) = self.layer_info(i_counter, &tensor.name);
+            if preset == Preset::IQ3_XS && i >= n / 8 && i < 7 * n / 8 {
+                new_type = T::IQ3_XXS;
+            }
+        }
+
+        new_type
+    }
+
+    /// Layer index and count for a per-layer tensor. Expert tensors are not
+    /// stored in layer order, so with experts the index comes from the name.
+    fn layer_info(&self, counter: usize, name: &str) -> (usize, usize) {
+        if self.n_expert > 1 {
+            if let Some(i) = layer_index(name) {
+                return (i, self.n_layer);
+            }
+        }
+        (counter, self.n_layer)
+    }
+}
+
+/// The 2-bit i-quant mixes, which replace the usual per-role adjustments
+fn is_iq2_mix(preset: Preset) -> bool {
+    matches!(preset, Preset::IQ2_XXS | Preset::IQ2_XS | Preset::IQ2_S | Preset::IQ2_M)
+}
+
+/// llama.cpp's choice of layers that get extra bits: the first and last
+/// eighth, plus every third layer in between
+fn use_more_bits(i_layer: usize, n_layers: usize) -> bool {
+    let (i, n) = (i_layer as i64, n_layers as i64);
+    i < n / 8 || i >= 7 * n / 8 || (i - n / 8) % 3 == 2
+}
+
+/// Demotes `ty` to a 32-value block type when the tensor's rows are not a
+/// whole number of its blocks, and to F16 when even that does not fit.
+///
+/// The table is llama.cpp's `tensor_type_fallback`. Q2_K and Q3_K go to
+/// Q4_0 as they do there now; older llama.cpp releases sent them to IQ4_NL,
+/// so files made by those differ from ours in these tensors.
+fn fallback(tensor: &TensorInfo, ty: GgmlType) -> GgmlType {
+    let ncols = tensor.dims.first().copied().unwrap_or(1);
+    if ncols.is_multiple_of(ty.block_size()) {
+        return ty;
+    }
+
+    let mut fallback = match ty {
+        GgmlType::Q2_K | GgmlType::Q3_K | GgmlType::TQ1_0 | GgmlType::TQ2_0 => GgmlType::Q4_0,
+        GgmlType::Q4_K => GgmlType::Q5_0,
+        GgmlType::Q5_K => GgmlType::Q5_1,
+        GgmlType::Q6_K => GgmlType::Q8_0,
+        GgmlType::IQ1_S
+        | GgmlType::IQ1_M
+        | GgmlType::IQ2_XXS
+        | GgmlType::IQ2_XS
+        | GgmlType::IQ2_S
+        | GgmlType::IQ3_XXS
+        | GgmlType::IQ3_S
+        | GgmlType::IQ4_XS => GgmlType::IQ4_NL,
+        other => other,
+    };
+    if !ncols.is_multiple_of(fallback.block_size()) {
+        fallback = GgmlType::F16;
+    }
+    eprintln!(
+        "⚠️  {}: {} columns are not a multiple of {} for {}, falling back to {}",
+        tensor.name,
+        ncols,
+        ty.block_size(),
+        ty,
+        fallback
+    );
+    fallback
+}
+
+/// `N` from a `blk.N.` tensor name
+fn layer_index(name: &str) -> Option<usize> {
+    name.strip_prefix("blk.")?.split('.').next()?.parse().ok()
+}
+
+/// Integer metadata value of any width
+fn metadata_u64(metadata: &BTreeMap<String, GGUFValue>, key: &str) -> Option<u64> {
+    match metadata.get(key)? {
+        GGUFValue::U8(v) => Some(*v as u64),
+        GGUFValue::U16(v) => Some(*v as u64),
+        GGUFValue::U32(v) => Some(*v as u64),
+        GGUFValue::U64(v) => Some(*v),
+        GGUFValue::I8(v) => u64::try_from(*v).ok(),
+        GGUFValue::I16(v) => u64::try_from(*v).ok(),
+        GGUFValue::I32(v) => u64::try_from(*v).ok(),
+        GGUFValue::I64(v) => u64::try_from(*v).ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,118 @@
+//! Per-tensor overrides: an ordered rules file of pattern → type mappings,
+//! and the `--keep` / `--exclude` pattern lists.
+//!
+//! A pattern is a glob over the whole tensor name (`*` matches any run of
+//! characters, `?` a single one) unless it is wrapped in slashes, in which
+//! case it is a regular expression searched for anywhere in the name:
+//!
+//! ```toml
+//! [[rule]]
+//! pattern = "*.attn_q.weight"
+//! type = "Q8_0"
+//!
+//! [[rule]]
+//! pattern = '/^blk\.[0-3]\.ffn_down/'
+//! type = "Q6_K"
+//! ```
+//!
+//! The JSON form is `{"rule": [{"pattern": ..., "type": ...}, ...]}`.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use gguf_core::types::GgmlType;
+use regex::Regex;
+use serde::Deserialize;
+
+/// A glob or `/regex/` matched against tensor names
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn parse(source: &str) -> Result<Pattern, String> {
+        let regex = match source
+            .strip_prefix('/')
+            .and_then(|s| s.strip_suffix('/'))
+        {
+            Some(re) => Regex::new(re),
+            None => Regex::new(&glob_to_regex(source)),
+        }
+        .map_err(|e| format!("invalid pattern '{source}': {e}"))?;
+
+        Ok(Pattern {
+            source: source.to_string(),
+            regex,
+        })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Anchored regex equivalent of a glob
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re.push('$');
+    re
+}
+
+/// One `pattern → type` mapping
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub pattern: Pattern,
+    pub ggml_type: GgmlType,
+}
+
+#[derive(Deserialize)]
+struct RulesFile {
+    #[serde(default, alias = "rules")]
+    rule: Vec<RawRule>,
+}
+
+#[derive(Deserialize)]
+struct RawRule {
+    pattern: String,
+    #[serde(rename = "type")]
+    ggml_type: String,
+}
+
+/// Loads rules in file order from a `.toml` or `.json` file
+pub fn load_rules(path: &Path) -> io::Result<Vec<Rule>> {
+    let text = fs::read_to_string(path)?;
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {e}", path.display()));
+
+    let file: RulesFile = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?,
+        Some("toml") => toml::from_str(&text).map_err(|e| invalid(e.to_string()))?,
+        _ => return Err(invalid("rules file must end in .toml or .json".to_string())),
+    };
+
+    file.rule
+        .into_iter()
+        .map(|raw| {
+            Ok(Rule {
+                pattern: Pattern::parse(&raw.pattern).map_err(&invalid)?,
+                ggml_type: raw.ggml_type.parse().map_err(&invalid)?,
+            })
+        })
+        .collect()
+}
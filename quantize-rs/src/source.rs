@@ -0,0 +1,147 @@
+//! Model inputs: a GGUF file, or a safetensors checkpoint (one file, a
+//! `model.safetensors.index.json` or a model directory) read directly, so a Hugging Face model
+//! can be quantized without first writing an F32 GGUF.
+//!
+//! Either way the tensors are described as a GGUF tensor directory, and each
+//! is widened or dequantized to f32 only while it is being quantized.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use gguf_core::decoder;
+use gguf_core::file::GgufFile;
+use gguf_core::types::{GGUFValue, GgmlType, TensorInfo};
+use gguf_writer::checkpoint::Checkpoint;
+use gguf_writer::hf_config_to_gguf::{build_default_metadata, rope_freqs, ROPE_FREQS};
+use gguf_writer::tensor_map::{TensorMap, Transform};
+
+pub struct Input {
+    pub metadata: BTreeMap<String, GGUFValue>,
+    pub tensors: Vec<TensorInfo>,
+    source: Source,
+}
+
+enum Source {
+    Gguf(GgufFile),
+    /// The checkpoint, and for each listed tensor its index within it and
+    /// the transform its architecture applies to its values, then the F32
+    /// bytes of tensors generated from the config, listed after those
+    Safetensors(Checkpoint, Vec<(usize, Option<Transform>)>, Vec<Vec<u8>>),
+}
+
+impl Input {
+    /// Opens a GGUF file, or a `.safetensors` / `.json` / directory
+    /// checkpoint with its metadata built from `config` and its tensors
+    /// renamed and transformed by `config` and `tensor_map` the way
+    /// gguf-writer does it
+    pub fn open(
+        path: &Path,
+        config: Option<&Path>,
+        tensor_map: Option<&Path>,
+        ignore_rope_scaling: bool,
+    ) -> io::Result<Input> {
+        let is_checkpoint = path.is_dir()
+            || path
+                .extension()
+                .is_some_and(|e| e == "safetensors" || e == "json");
+        if !is_checkpoint {
+            let file = GgufFile::open(path)?;
+            return Ok(Input {
+                metadata: file.metadata.clone(),
+                tensors: file.tensors.clone(),
+                source: Source::Gguf(file),
+            });
+        }
+
+        let checkpoint = Checkpoint::open(path)?;
+        let map = TensorMap::load(config, tensor_map)?;
+        let names = match &map {
+            Some(map) => map.gguf_names(checkpoint.tensors().iter().map(|t| t.name.as_str()))?,
+            None => checkpoint.tensors().iter().map(|t| Some(t.name.clone())).collect(),
+        };
+        let mut tensors = Vec::new();
+        let mut indices = Vec::new();
+        for (i, (t, name)) in checkpoint.tensors().iter().zip(names).enumerate() {
+            let Some(name) = name else {
+                continue;
+            };
+            let ty = t.ggml_type()?;
+            let transform = map.as_ref().and_then(|map| map.transform(&t.name));
+            tensors.push(TensorInfo {
+                name,
+                type_id: ty.id(),
+                dims: transform.map_or_else(|| t.dims(), |tr| tr.dims(&t.dims())),
+                offset: 0,
+                size: t.nbytes(),
+            });
+            indices.push((i, transform));
+        }
+
+        let metadata = build_default_metadata(config, false, "NA", ignore_rope_scaling)?;
+        let mut generated = Vec::new();
+        if let Some(factors) = config.map(rope_freqs).transpose()?.flatten() {
+            tensors.push(TensorInfo {
+                name: ROPE_FREQS.to_string(),
+                type_id: GgmlType::F32.id(),
+                dims: vec![factors.len() as u64],
+                offset: 0,
+                size: factors.len() as u64 * 4,
+            });
+            generated.push(factors.iter().flat_map(|v| v.to_le_bytes()).collect());
+        }
+
+        Ok(Input {
+            metadata,
+            tensors,
+            source: Source::Safetensors(checkpoint, indices, generated),
+        })
+    }
+
+    /// Bytes of the `i`th tensor, in its own type
+    pub fn tensor_data(&self, i: usize) -> io::Result<Cow<'_, [u8]>> {
+        let t = &self.tensors[i];
+        match &self.source {
+            Source::Gguf(file) => Ok(file.tensor_data(t)?),
+            Source::Safetensors(_, indices, generated) if i >= indices.len() => {
+                Ok(Cow::Borrowed(&generated[i - indices.len()]))
+            }
+            Source::Safetensors(checkpoint, indices, _) => {
+                let (index, transform) = indices[i];
+                let source = &checkpoint.tensors()[index];
+                let data = checkpoint.data(source);
+                match (transform, t.ggml_type()) {
+                    (Some(transform), Some(ty)) => {
+                        Ok(Cow::Owned(transform.apply_bytes(&t.name, ty, data, &source.dims())?))
+                    }
+                    _ => Ok(Cow::Borrowed(data)),
+                }
+            }
+        }
+    }
+
+    /// Values of the `i`th tensor as f32, widened from F16 or BF16 or
+    /// dequantized from any type the decoder supports. Transforms are
+    /// applied after widening, so they lose nothing to F16 or BF16 rounding.
+    pub fn tensor_f32(&self, i: usize) -> io::Result<Vec<f32>> {
+        let t = &self.tensors[i];
+        let Some(ty) = t.ggml_type() else {
+            return Err(invalid(format!("{} has unknown ggml type {}", t.name, t.type_id)));
+        };
+        if let Source::Safetensors(checkpoint, indices, _) = &self.source {
+            if let Some(&(index, Some(transform))) = indices.get(i) {
+                let source = &checkpoint.tensors()[index];
+                let values = decoder::dequantize(ty, checkpoint.data(source), &source.dims())
+                    .map_err(|e| invalid(format!("{}: {e}", t.name)))?;
+                return transform.apply(&t.name, &values, &source.dims());
+            }
+        }
+        let data = self.tensor_data(i)?;
+        decoder::dequantize(ty, &data, &t.dims).map_err(|e| invalid(format!("{}: {e}", t.name)))
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
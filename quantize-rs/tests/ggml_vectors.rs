@@ -0,0 +1,154 @@
+//! Runs quantize-rs over the llama.cpp reference input in
+//! `crates/gguf-core/tests/vectors` and checks every output tensor is
+//! byte-identical to the blocks ggml's reference quantizers produced.
+//! `<type>_imatrix.bin` is ggml's `quantize_<type>` of the same input with
+//! the per-column importance weights in `imatrix.f32`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use gguf_core::file::GgufFile;
+use gguf_core::types::GgmlType;
+use gguf_test_support::{write_f32_gguf, TempDir};
+
+const DIMS: [u64; 2] = [256, 2];
+
+fn vectors_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../crates/gguf-core/tests/vectors")
+}
+
+const NAME: &str = "blk.0.ffn_up.weight";
+
+/// The preset whose default type is `ty`; llama.cpp's IQ2_S preset is
+/// mostly IQ2_XS, and the IQ2_S type comes from IQ2_M
+fn preset_for(ty: GgmlType) -> &'static str {
+    match ty {
+        GgmlType::IQ2_S => "IQ2_M",
+        other => other.name(),
+    }
+}
+
+fn quantize(format: &str, input: &Path, output: &Path, extra: &[&std::ffi::OsStr]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_quantize-rs"))
+        .arg("-i")
+        .arg(input)
+        .arg("-o")
+        .arg(output)
+        .args(["-f", format])
+        .args(extra)
+        .status()
+        .unwrap();
+    assert!(status.success(), "quantize-rs -f {format} failed");
+}
+
+/// Writes the reference input as a one-tensor GGUF in a fresh directory
+fn setup(tag: &str) -> (TempDir, PathBuf) {
+    let dir = TempDir::new(tag);
+    let values = fs::read(vectors_dir().join("input.f32"))
+        .unwrap()
+        .chunks_exact(4)
+        .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+        .collect();
+    let input = dir.join("input.gguf");
+    write_f32_gguf(&input, &[(NAME, DIMS.to_vec(), values)]);
+    (dir, input)
+}
+
+fn assert_blocks(output: &Path, ty: GgmlType, vector: &str) {
+    let file = GgufFile::open(output).unwrap();
+    let tensor = file.tensor(NAME).unwrap();
+    assert_eq!(tensor.ggml_type(), Some(ty));
+
+    let expected = fs::read(vectors_dir().join(vector)).unwrap();
+    assert!(file.tensor_data(tensor).unwrap()[..] == expected[..], "{ty} blocks differ from {vector}");
+}
+
+#[test]
+fn output_matches_ggml_reference_blocks() {
+    let (dir, input) = setup("vectors");
+
+    for ty in [
+        GgmlType::Q4_0,
+        GgmlType::Q4_1,
+        GgmlType::Q5_0,
+        GgmlType::Q5_1,
+        GgmlType::Q8_0,
+        GgmlType::Q2_K,
+        GgmlType::Q3_K,
+        GgmlType::Q4_K,
+        GgmlType::Q5_K,
+        GgmlType::Q6_K,
+        GgmlType::IQ3_S,
+        GgmlType::IQ4_NL,
+        GgmlType::IQ4_XS,
+    ] {
+        let output = dir.join(format!("{ty}.gguf"));
+        quantize(preset_for(ty), &input, &output, &[]);
+        assert_blocks(&output, ty, &format!("{}.bin", ty.name().to_lowercase()));
+    }
+}
+
+#[test]
+fn imatrix_output_matches_ggml_weighted_blocks() {
+    let (dir, input) = setup("imatrix");
+
+    // Legacy imatrix.dat with the weights stored as sums over 4 calls
+    let weights = fs::read(vectors_dir().join("imatrix.f32")).unwrap();
+    let mut dat = Vec::new();
+    dat.extend_from_slice(&1i32.to_le_bytes());
+    dat.extend_from_slice(&(NAME.len() as i32).to_le_bytes());
+    dat.extend_from_slice(NAME.as_bytes());
+    dat.extend_from_slice(&4i32.to_le_bytes());
+    dat.extend_from_slice(&(weights.len() as i32 / 4).to_le_bytes());
+    for w in weights.chunks_exact(4) {
+        let w = f32::from_le_bytes([w[0], w[1], w[2], w[3]]);
+        dat.extend_from_slice(&(w * 4.0).to_le_bytes());
+    }
+    let imatrix = dir.join("imatrix.dat");
+    fs::write(&imatrix, dat).unwrap();
+
+    for ty in [
+        GgmlType::Q4_0,
+        GgmlType::Q4_1,
+        GgmlType::Q5_0,
+        GgmlType::Q5_1,
+        GgmlType::Q2_K,
+        GgmlType::Q3_K,
+        GgmlType::Q4_K,
+        GgmlType::Q5_K,
+        GgmlType::Q6_K,
+        GgmlType::IQ2_XXS,
+        GgmlType::IQ2_XS,
+        GgmlType::IQ2_S,
+        GgmlType::IQ3_XXS,
+        GgmlType::IQ3_S,
+        GgmlType::IQ4_NL,
+        GgmlType::IQ4_XS,
+    ] {
+        let output = dir.join(format!("{ty}.gguf"));
+        quantize(preset_for(ty), &input, &output, &["--imatrix".as_ref(), imatrix.as_os_str()]);
+        assert_blocks(&output, ty, &format!("{}_imatrix.bin", ty.name().to_lowercase()));
+    }
+}
+
+#[test]
+fn low_bit_iq_types_require_an_imatrix() {
+    let (dir, input) = setup("no-imatrix");
+
+    for format in ["IQ2_XXS", "IQ2_XS", "IQ2_M", "IQ3_XXS"] {
+        let run = |extra: &[&str]| {
+            Command::new(env!("CARGO_BIN_EXE_quantize-rs"))
+                .arg("-i")
+                .arg(&input)
+                .arg("-o")
+                .arg(dir.join(format!("{format}.gguf")))
+                .args(["-f", format])
+                .args(extra)
+                .status()
+                .unwrap()
+        };
+        assert!(!run(&[]).success(), "{format} quantized without an imatrix");
+        assert!(run(&["--dry-run"]).success(), "{format} --dry-run failed");
+    }
+}
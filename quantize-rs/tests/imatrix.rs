@@ -0,0 +1,43 @@
+//! Checks a GGUF imatrix whose sums and counts do not describe whole rows is
+//! refused with an error rather than a panic.
+
+use std::process::Command;
+
+use gguf_test_support::{write_f32_gguf, TempDir};
+
+#[test]
+fn malformed_gguf_imatrix_is_refused() {
+    let dir = TempDir::new("imatrix-rows");
+    let input = dir.join("input.gguf");
+    write_f32_gguf(&input, &[("blk.0.ffn_up.weight", vec![32, 4], vec![0.5; 128])]);
+
+    let sums = "blk.0.ffn_up.weight.in_sum2";
+    let counts = "blk.0.ffn_up.weight.counts";
+    for (case, tensors, message) in [
+        // Rows of no columns, with a count for the one expert
+        ("empty", vec![(sums, vec![0, 1], vec![]), (counts, vec![1, 1], vec![4.0])], "empty rows"),
+        // Two experts' sums, but three counts
+        (
+            "mismatched",
+            vec![(sums, vec![4, 2], vec![1.0; 8]), (counts, vec![1, 3], vec![4.0; 3])],
+            "do not match",
+        ),
+    ] {
+        let imatrix = dir.join(format!("{case}.imatrix.gguf"));
+        write_f32_gguf(&imatrix, &tensors);
+
+        let output = Command::new(env!("CARGO_BIN_EXE_quantize-rs"))
+            .arg("-i")
+            .arg(&input)
+            .arg("-o")
+            .arg(dir.join("output.gguf"))
+            .args(["-f", "Q4_0", "--imatrix"])
+            .arg(&imatrix)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{case}");
+        assert!(!stderr.contains("panicked"), "{case}: {stderr}");
+        assert!(stderr.contains(message), "{case}: {stderr}");
+    }
+}
@@ -0,0 +1,79 @@
+//! Checks requantizing a quantized GGUF: tensors already of the target type
+//! are copied, other quantized tensors need `--allow-requantize` and are
+//! quantized from their decoded values.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+use gguf_core::decoder;
+use gguf_core::file::GgufFile;
+use gguf_test_support::{write_f32_gguf, TempDir};
+
+const NAMES: &[&str] = &["blk.0.attn_q.weight", "blk.0.ffn_up.weight", "blk.0.attn_norm.weight"];
+
+fn quantize(input: &Path, output: &Path, format: &str, extra: &[&str]) -> ExitStatus {
+    Command::new(env!("CARGO_BIN_EXE_quantize-rs"))
+        .arg("-i")
+        .arg(input)
+        .arg("-o")
+        .arg(output)
+        .args(["-f", format, "--pure"])
+        .args(extra)
+        .status()
+        .unwrap()
+}
+
+#[test]
+fn requantize_copies_matching_types_and_decodes_the_rest() {
+    let dir = TempDir::new("requantize");
+
+    let tensors: Vec<_> = NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, &name)| {
+            let dims = if name.ends_with("norm.weight") { vec![256u64] } else { vec![256u64, 8] };
+            let n: u64 = dims.iter().product();
+            let values = (0..n).map(|j| (j as f32 * 0.05 + i as f32).cos()).collect();
+            (name, dims, values)
+        })
+        .collect();
+    let input = dir.join("input.gguf");
+    write_f32_gguf(&input, &tensors);
+
+    let q4 = dir.join("q4_k.gguf");
+    assert!(quantize(&input, &q4, "Q4_K_M", &[]).success());
+
+    // Same type again: every tensor is copied, so nothing changes
+    let again = dir.join("again.gguf");
+    assert!(quantize(&q4, &again, "Q4_K_M", &[]).success());
+    assert!(fs::read(&q4).unwrap() == fs::read(&again).unwrap());
+
+    // Another type needs the guard
+    let q8 = dir.join("q8_0.gguf");
+    assert!(!quantize(&q4, &q8, "Q8_0", &[]).success());
+    assert!(quantize(&q4, &q8, "Q8_0", &["--allow-requantize"]).success());
+
+    // ... and matches quantizing the decoded Q4_K values
+    let q4_file = GgufFile::open(&q4).unwrap();
+    let decoded: Vec<_> = q4_file
+        .tensors
+        .iter()
+        .map(|t| {
+            let values = decoder::dequantize(t.ggml_type().unwrap(), &q4_file.tensor_data(t).unwrap(), &t.dims).unwrap();
+            (t.name.as_str(), t.dims.clone(), values)
+        })
+        .collect();
+    let decoded_input = dir.join("decoded.gguf");
+    write_f32_gguf(&decoded_input, &decoded);
+    let reference = dir.join("reference.gguf");
+    assert!(quantize(&decoded_input, &reference, "Q8_0", &[]).success());
+
+    let output = GgufFile::open(&q8).unwrap();
+    let reference = GgufFile::open(&reference).unwrap();
+    for expected in &reference.tensors {
+        let t = output.tensor(&expected.name).unwrap();
+        assert_eq!(t.type_id, expected.type_id, "{}", t.name);
+        assert!(output.tensor_data(t).unwrap() == reference.tensor_data(expected).unwrap(), "{}", t.name);
+    }
+}
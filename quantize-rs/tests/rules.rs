@@ -0,0 +1,103 @@
+//! Checks that a rules file, `--keep` and `--exclude` override the preset
+//! for the tensors they match and leave the rest to it.
+
+use std::fs;
+use std::process::Command;
+
+use gguf_core::file::GgufFile;
+use gguf_core::types::GgmlType;
+use gguf_test_support::{write_f32_gguf, TempDir};
+
+const NAMES: &[&str] = &[
+    "token_embd.weight",
+    "blk.0.attn_q.weight",
+    "blk.0.attn_k.weight",
+    "blk.0.ffn_up.weight",
+    "blk.0.ffn_down.weight",
+    "blk.0.lora_merged.weight",
+];
+
+#[test]
+fn rules_keep_and_exclude_override_preset() {
+    let dir = TempDir::new("rules");
+
+    let values: Vec<f32> = (0..256 * 4).map(|i| (i as f32 * 0.37).sin()).collect();
+    let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let tensors: Vec<_> = NAMES.iter().map(|&name| (name, vec![256u64, 4], values.clone())).collect();
+    let input = dir.join("input.gguf");
+    write_f32_gguf(&input, &tensors);
+
+    let rules = dir.join("rules.toml");
+    fs::write(
+        &rules,
+        r#"
+[[rule]]
+pattern = "*.attn_q.weight"
+type = "Q8_0"
+
+[[rule]]
+pattern = "/^token_embd/"
+type = "F16"
+
+[[rule]]
+pattern = "*.attn_?.weight"
+type = "Q6_K"
+"#,
+    )
+    .unwrap();
+
+    let output = dir.join("output.gguf");
+    let status = Command::new(env!("CARGO_BIN_EXE_quantize-rs"))
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .args(["-f", "Q4_0", "--rules"])
+        .arg(&rules)
+        .args(["--keep", "*.ffn_down.weight", "--exclude", "/lora_merged/"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let file = GgufFile::open(&output).unwrap();
+    let ty = |name: &str| file.tensor(name).and_then(|t| t.ggml_type());
+    assert_eq!(ty("token_embd.weight"), Some(GgmlType::F16));
+    assert_eq!(ty("blk.0.attn_q.weight"), Some(GgmlType::Q8_0));
+    assert_eq!(ty("blk.0.attn_k.weight"), Some(GgmlType::Q6_K));
+    assert_eq!(ty("blk.0.ffn_up.weight"), Some(GgmlType::Q4_0));
+    assert_eq!(ty("blk.0.ffn_down.weight"), Some(GgmlType::F32));
+    assert_eq!(ty("blk.0.lora_merged.weight"), Some(GgmlType::F32));
+
+    // Kept and excluded tensors are copied as they are, not dropped
+    assert_eq!(file.tensors.len(), NAMES.len());
+    for name in ["blk.0.ffn_down.weight", "blk.0.lora_merged.weight"] {
+        let t = file.tensor(name).unwrap();
+        assert!(file.tensor_data(t).unwrap()[..] == data[..], "{name}");
+    }
+}
+
+#[test]
+fn rule_needing_an_imatrix_is_refused_before_writing() {
+    let dir = TempDir::new("rules-imatrix");
+
+    let input = dir.join("input.gguf");
+    let values = (0..256 * 4).map(|i| i as f32).collect();
+    write_f32_gguf(&input, &[("blk.0.ffn_up.weight", vec![256, 4], values)]);
+
+    // IQ2 types have no quantizer that works without importance weights
+    let rules = dir.join("rules.json");
+    fs::write(&rules, r#"{"rules": [{"pattern": "*.ffn_up.weight", "type": "IQ2_XS"}]}"#).unwrap();
+
+    let output = dir.join("output.gguf");
+    let status = Command::new(env!("CARGO_BIN_EXE_quantize-rs"))
+        .arg("-i")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .args(["-f", "Q4_0", "--rules"])
+        .arg(&rules)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    assert!(!output.exists());
+}
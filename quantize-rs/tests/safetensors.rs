@@ -0,0 +1,130 @@
+//! Checks that quantizing a safetensors checkpoint, whole or sharded, gives
+//! the same tensors as quantizing the equivalent F32 GGUF, including a BF16
+//! norm that has to come out as F32, and that llama q/k projections are
+//! RoPE-permuted as gguf-writer does.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use gguf_core::decoder;
+use gguf_core::file::GgufFile;
+use gguf_test_support::{encode, rounded, write_f32_gguf, write_safetensors, TempDir};
+
+/// Name, safetensors dtype and row-major shape
+const TENSORS: &[(&str, &str, &[u64])] = &[
+    ("blk.0.attn_q.weight", "BF16", &[64, 256]),
+    ("blk.0.ffn_up.weight", "F16", &[32, 512]),
+    ("blk.0.attn_norm.weight", "BF16", &[256]),
+];
+
+fn values(seed: usize, n: u64) -> Vec<f32> {
+    (0..n).map(|j| (j as f32 * 0.021 + seed as f32).sin() * 0.5).collect()
+}
+
+fn quantize(input: &Path, output: &Path) {
+    let status = Command::new(env!("CARGO_BIN_EXE_quantize-rs"))
+        .arg("-i")
+        .arg(input)
+        .arg("-o")
+        .arg(output)
+        .args(["-f", "Q4_K_M"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn safetensors_input_matches_f32_gguf_input() {
+    let dir = TempDir::new("safetensors");
+
+    let mut encoded = Vec::new();
+    let mut widened = Vec::new();
+    for (i, &(name, dtype, shape)) in TENSORS.iter().enumerate() {
+        let values = values(i, shape.iter().product());
+        encoded.push((name, dtype, shape, encode(dtype, &values)));
+        widened.push((name, shape.iter().rev().copied().collect(), rounded(dtype, &values)));
+    }
+
+    // The same tensors widened to F32, with GGUF's innermost-first dims
+    let gguf = dir.join("input.gguf");
+    write_f32_gguf(&gguf, &widened);
+
+    let single = dir.join("model.safetensors");
+    write_safetensors(&single, &encoded);
+
+    // Shards list their tensors out of order; the index decides which is where
+    write_safetensors(&dir.join("model-00001-of-00002.safetensors"), &[encoded[2].clone(), encoded[0].clone()]);
+    write_safetensors(&dir.join("model-00002-of-00002.safetensors"), &encoded[1..2]);
+    let index = dir.join("model.safetensors.index.json");
+    fs::write(
+        &index,
+        r#"{"metadata": {}, "weight_map": {
+            "blk.0.attn_q.weight": "model-00001-of-00002.safetensors",
+            "blk.0.ffn_up.weight": "model-00002-of-00002.safetensors",
+            "blk.0.attn_norm.weight": "model-00001-of-00002.safetensors"
+        }}"#,
+    )
+    .unwrap();
+
+    let reference = dir.join("reference.gguf");
+    quantize(&gguf, &reference);
+    let reference = GgufFile::open(&reference).unwrap();
+
+    for input in [&single, &index] {
+        let output = dir.join("output.gguf");
+        quantize(input, &output);
+        let output = GgufFile::open(&output).unwrap();
+
+        assert_eq!(output.tensors.len(), TENSORS.len());
+        for expected in &reference.tensors {
+            let t = output.tensor(&expected.name).unwrap();
+            assert_eq!(t.dims, expected.dims, "{}", t.name);
+            assert_eq!(t.type_id, expected.type_id, "{}", t.name);
+            assert!(
+                output.tensor_data(t).unwrap() == reference.tensor_data(expected).unwrap(),
+                "{} differs from the F32 GGUF result",
+                t.name
+            );
+        }
+    }
+}
+
+#[test]
+fn llama_q_projection_is_permuted() {
+    let dir = TempDir::new("permute");
+    let config = dir.join("config.json");
+    fs::write(&config, r#"{"architectures": ["LlamaForCausalLM"], "num_attention_heads": 2}"#).unwrap();
+
+    // Row r holds 32 r + c, exact in F16
+    let q: Vec<f32> = (0..8 * 32).map(|i| i as f32).collect();
+    let bytes = encode("BF16", &q);
+    let input = dir.join("model.safetensors");
+    write_safetensors(&input, &[("model.layers.0.self_attn.q_proj.weight", "BF16", &[8, 32], bytes)]);
+    let permuted: Vec<f32> = [0, 2, 1, 3, 4, 6, 5, 7]
+        .iter()
+        .flat_map(|&r| q[r * 32..(r + 1) * 32].to_vec())
+        .collect();
+
+    // Quantized to F16, and copied as BF16 by --keep
+    for extra in [&[][..], &["--keep", "*"][..]] {
+        let output = dir.join("output.gguf");
+        let status = Command::new(env!("CARGO_BIN_EXE_quantize-rs"))
+            .arg("-i")
+            .arg(&input)
+            .arg("--config")
+            .arg(&config)
+            .arg("-o")
+            .arg(&output)
+            .args(["-f", "F16"])
+            .args(extra)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let file = GgufFile::open(&output).unwrap();
+        let t = file.tensor("blk.0.attn_q.weight").unwrap();
+        let values = decoder::dequantize(t.ggml_type().unwrap(), &file.tensor_data(t).unwrap(), &t.dims).unwrap();
+        assert_eq!(values, permuted, "{extra:?}");
+    }
+}
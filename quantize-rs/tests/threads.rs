@@ -0,0 +1,55 @@
+//! Checks that quantizing with one thread and with several gives the same
+//! file, both for tensors split by rows and for batches of small tensors.
+
+use std::fs;
+use std::process::Command;
+
+use gguf_test_support::{write_f32_gguf, TempDir};
+
+#[test]
+fn output_is_identical_for_any_thread_count() {
+    let dir = TempDir::new("threads");
+
+    // Large enough that each tensor is split into several runs of rows
+    let tensors: Vec<(String, Vec<u64>)> = (0..4)
+        .flat_map(|i| {
+            [
+                (format!("blk.{i}.attn_q.weight"), vec![512u64, 384]),
+                (format!("blk.{i}.ffn_down.weight"), vec![768u64, 256]),
+                (format!("blk.{i}.attn_norm.weight"), vec![512u64]),
+            ]
+        })
+        .collect();
+    let values = |seed: usize, n: u64| -> Vec<f32> {
+        (0..n)
+            .map(|j| (j as f32 * 0.013 + seed as f32).sin() * (1.0 + (j % 7) as f32))
+            .collect()
+    };
+    let tensors: Vec<_> = tensors
+        .iter()
+        .enumerate()
+        .map(|(i, (name, dims))| (name.as_str(), dims.clone(), values(i, dims.iter().product())))
+        .collect();
+    let input = dir.join("input.gguf");
+    write_f32_gguf(&input, &tensors);
+
+    for format in ["Q4_0", "Q4_K_M"] {
+        let outputs: Vec<Vec<u8>> = ["1", "4"]
+            .iter()
+            .map(|threads| {
+                let output = dir.join(format!("{format}-{threads}.gguf"));
+                let status = Command::new(env!("CARGO_BIN_EXE_quantize-rs"))
+                    .arg("-i")
+                    .arg(&input)
+                    .arg("-o")
+                    .arg(&output)
+                    .args(["-f", format, "--threads", threads])
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+                fs::read(&output).unwrap()
+            })
+            .collect();
+        assert!(outputs[0] == outputs[1], "{format} output differs between thread counts");
+    }
+}